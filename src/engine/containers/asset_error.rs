@@ -0,0 +1,40 @@
+use std::fmt;
+use std::io;
+
+// Why an asset couldn't be loaded. `path` is the file, or the id of an embedded image.
+#[derive(Debug)]
+pub enum AssetError {
+    Io { path: String, error: io::Error },
+    Image { path: String, error: image::ImageError },
+    Obj { path: String, error: tobj::LoadError },
+    Gltf { path: String, message: String },
+    // The faces of a cube map must all have the size of the first one
+    CubeFaceSize { path: String, expected: (u32, u32), found: (u32, u32) },
+}
+
+impl AssetError {
+    pub fn path(&self) -> &str {
+        match self {
+            AssetError::Io { path, .. } => path,
+            AssetError::Image { path, .. } => path,
+            AssetError::Obj { path, .. } => path,
+            AssetError::Gltf { path, .. } => path,
+            AssetError::CubeFaceSize { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetError::Io { path, error } => write!(f, "{}: IO error: {}", path, error),
+            AssetError::Image { path, error } => write!(f, "{}: invalid image: {}", path, error),
+            AssetError::Obj { path, error } => write!(f, "{}: invalid OBJ file: {}", path, error),
+            AssetError::Gltf { path, message } => write!(f, "{}: invalid glTF file: {}", path, message),
+            AssetError::CubeFaceSize { path, expected, found } =>
+                write!(f, "{}: cube map face is {}x{}, expected {}x{}", path, found.0, found.1, expected.0, expected.1),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
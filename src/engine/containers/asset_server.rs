@@ -0,0 +1,409 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock, Weak};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use image::DynamicImage;
+use specs::rayon::{ThreadPool, ThreadPoolBuilder};
+use crate::ecs::components::Mesh;
+use crate::gl_wrapper::TextureFormat;
+use crate::gl_wrapper::texture_2d::Texture2D;
+use super::{compute_tangents, AssetError, GltfFile, ModelLoader, ModelNode, TextureCache, CONTAINER};
+use super::gltf::compute_normals;
+
+const DECODING_THREADS: usize = 2;
+// Uploads are spread over several frames to avoid hitches
+const MAX_UPLOADS_PER_FRAME: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+enum Slot<T> {
+    Loading,
+    Loaded(Arc<T>),
+    Failed,
+}
+
+struct AssetSlot<T> {
+    id: String,
+    slot: RwLock<Slot<T>>,
+}
+
+// Asset that may still be loading, cheap to clone. Handles of the same file share the
+// asset, which is dropped with the last handle.
+pub struct Handle<T> {
+    inner: Arc<AssetSlot<T>>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Handle<T> {
+    fn new(id: &str) -> Self {
+        Handle { inner: Arc::new(AssetSlot { id: id.to_string(), slot: RwLock::new(Slot::Loading) }) }
+    }
+
+    // For the assets loaded synchronously
+    pub fn loaded(id: &str, asset: Arc<T>) -> Self {
+        Handle { inner: Arc::new(AssetSlot { id: id.to_string(), slot: RwLock::new(Slot::Loaded(asset)) }) }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    pub fn load_state(&self) -> LoadState {
+        match *self.inner.slot.read().unwrap() {
+            Slot::Loading => LoadState::Loading,
+            Slot::Loaded(..) => LoadState::Loaded,
+            Slot::Failed => LoadState::Failed,
+        }
+    }
+
+    // None until the asset is loaded
+    pub fn get(&self) -> Option<Arc<T>> {
+        match &*self.inner.slot.read().unwrap() {
+            Slot::Loaded(asset) => Some(asset.clone()),
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, asset: Option<Arc<T>>) {
+        *self.inner.slot.write().unwrap() = asset.map_or(Slot::Failed, Slot::Loaded);
+    }
+}
+
+impl Handle<Texture2D> {
+    // The fallback texture is bound until the texture is loaded, and when it failed
+    pub fn current_texture(&self) -> Arc<Texture2D> {
+        self.get().unwrap_or_else(|| CONTAINER.get_local::<TextureCache>().fallback_texture())
+    }
+}
+
+pub(crate) struct MeshData {
+    pub positions: Vec<f32>,
+    pub texcoords: Vec<f32>,
+    pub normals: Vec<f32>,
+    // Generated when the mesh is uploaded if missing
+    pub tangents: Option<Vec<f32>>,
+    pub indices: Vec<u32>,
+}
+
+pub(crate) struct ObjObject {
+    pub name: String,
+    pub mesh: MeshData,
+    pub material: Option<usize>,
+}
+
+// The objects of an OBJ file and the materials of its MTL files
+pub(crate) struct ObjData {
+    pub objects: Vec<ObjObject>,
+    pub materials: Vec<tobj::Material>,
+}
+
+enum ModelData {
+    Obj(ObjData),
+    Gltf(GltfFile),
+}
+
+// Results of the decoding threads, waiting to be uploaded
+enum Decoded {
+    Texture(Handle<Texture2D>, Result<(TextureFormat, DynamicImage), AssetError>),
+    // Meshes of the models built by `update`, uploaded on the next frames
+    Mesh(Handle<Mesh>, MeshData),
+    Model(Handle<ModelNode>, Result<ModelData, AssetError>),
+}
+
+// The missing normals and texture coordinates are generated, doesn't need the GL context
+pub(crate) fn decode_obj(filename: &str) -> Result<ObjData, AssetError> {
+    let (models, materials) = tobj::load_obj(Path::new(filename))
+        .map_err(|error| AssetError::Obj { path: filename.to_string(), error })?;
+
+    let objects = models.into_iter()
+        .map(|model| {
+            let mesh = model.mesh;
+            let vertex_count = mesh.positions.len() / 3;
+
+            let normals = if mesh.normals.len() == mesh.positions.len() {
+                mesh.normals
+            } else {
+                compute_normals(&mesh.positions, &mesh.indices)
+            };
+            let texcoords = if mesh.texcoords.len() / 2 == vertex_count {
+                mesh.texcoords
+            } else {
+                vec![0.0; 2 * vertex_count]
+            };
+            let tangents = compute_tangents(&mesh.positions, &normals, &texcoords, &mesh.indices);
+
+            ObjObject {
+                name: model.name,
+                mesh: MeshData { positions: mesh.positions, texcoords, normals, tangents: Some(tangents), indices: mesh.indices },
+                material: mesh.material_id,
+            }
+        })
+        .collect();
+    Ok(ObjData { objects, materials })
+}
+
+// Same formats as ModelLoader::load_model
+fn decode_model(filename: &str) -> Result<ModelData, AssetError> {
+    let extension = Path::new(filename).extension().and_then(|extension| extension.to_str());
+    match extension {
+        Some("gltf") | Some("glb") => GltfFile::open(Path::new(filename)).map(ModelData::Gltf),
+        _ => decode_obj(filename).map(ModelData::Obj),
+    }
+}
+
+// Loads the files on a thread pool and returns their handles right away. The GL objects
+// are created on the main thread by `update`, see AssetUploadSystem.
+pub struct AssetServer {
+    pool: ThreadPool,
+    sender: Sender<Decoded>,
+    receiver: Receiver<Decoded>,
+    textures: RefCell<HashMap<String, Weak<AssetSlot<Texture2D>>>>,
+    meshes: RefCell<HashMap<String, Weak<AssetSlot<Mesh>>>>,
+    models: RefCell<HashMap<String, Weak<AssetSlot<ModelNode>>>>,
+}
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        AssetServer {
+            pool: ThreadPoolBuilder::new()
+                .num_threads(DECODING_THREADS)
+                .thread_name(|i| format!("asset-decoder-{}", i))
+                .build()
+                .unwrap(),
+            sender,
+            receiver,
+            textures: RefCell::new(HashMap::new()),
+            meshes: RefCell::new(HashMap::new()),
+            models: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl AssetServer {
+    // Shares the textures already in the TextureCache
+    pub fn load_texture(&self, filename: &str) -> Handle<Texture2D> {
+        let path = filename.to_string();
+        self.texture(filename, move || image::open(&path))
+    }
+
+    // For the images embedded in model files, `id` must be unique to the image
+    pub fn load_texture_from_memory(&self, id: &str, bytes: Vec<u8>) -> Handle<Texture2D> {
+        self.texture(id, move || image::load_from_memory(&bytes))
+    }
+
+    fn texture<F>(&self, id: &str, decode: F) -> Handle<Texture2D>
+        where F: FnOnce() -> image::ImageResult<DynamicImage> + Send + 'static
+    {
+        if let Some(handle) = self.textures.borrow().get(id).and_then(Weak::upgrade) {
+            return Handle { inner: handle };
+        }
+
+        let handle = match CONTAINER.get_local::<TextureCache>().get_loaded_texture(id) {
+            Some(texture) => Handle::loaded(id, texture),
+            None => {
+                let handle = Handle::new(id);
+                let (sender, job_handle, path) = (self.sender.clone(), handle.clone(), id.to_string());
+                self.pool.spawn(move || {
+                    let image = decode()
+                        .map(TextureCache::prepare_image)
+                        .map_err(|error| AssetError::Image { path, error });
+                    // The server is gone when the receiver is
+                    let _ = sender.send(Decoded::Texture(job_handle, image));
+                });
+                handle
+            }
+        };
+        self.textures.borrow_mut().insert(id.to_string(), Arc::downgrade(&handle.inner));
+        handle
+    }
+
+    // OBJ, glTF or GLB file. The handle is loaded once the hierarchy is built, the meshes
+    // and the textures of the nodes are loaded over the next frames.
+    pub fn load_model(&self, filename: &str) -> Handle<ModelNode> {
+        if let Some(handle) = self.models.borrow().get(filename).and_then(Weak::upgrade) {
+            return Handle { inner: handle };
+        }
+
+        let handle = Handle::new(filename);
+        let (sender, job_handle, path) = (self.sender.clone(), handle.clone(), filename.to_string());
+        self.pool.spawn(move || {
+            let _ = sender.send(Decoded::Model(job_handle, decode_model(&path)));
+        });
+        self.models.borrow_mut().insert(filename.to_string(), Arc::downgrade(&handle.inner));
+        handle
+    }
+
+    // Mesh of a model file, shared with the TextureCache. Uploaded by `update` when deferred,
+    // right away otherwise.
+    pub(crate) fn mesh(&self, id: &str, data: MeshData, deferred: bool) -> Handle<Mesh> {
+        if let Some(handle) = self.meshes.borrow().get(id).and_then(Weak::upgrade) {
+            return Handle { inner: handle };
+        }
+
+        let texture_cache = CONTAINER.get_local::<TextureCache>();
+        let handle = match texture_cache.get_mesh(id) {
+            Some(mesh) => Handle::loaded(id, mesh),
+            None if deferred => {
+                let handle = Handle::new(id);
+                let _ = self.sender.send(Decoded::Mesh(handle.clone(), data));
+                handle
+            }
+            None => {
+                let mesh = Arc::new(Self::upload_mesh(data));
+                texture_cache.insert_mesh(id.to_string(), &mesh);
+                Handle::loaded(id, mesh)
+            }
+        };
+        self.meshes.borrow_mut().insert(id.to_string(), Arc::downgrade(&handle.inner));
+        handle
+    }
+
+    fn upload_mesh(data: MeshData) -> Mesh {
+        ModelLoader::build_mesh(data.positions, data.texcoords, data.normals, data.tangents, data.indices)
+    }
+
+    fn build_model(&self, id: &str, data: ModelData) -> Result<ModelNode, AssetError> {
+        match data {
+            ModelData::Obj(data) => Ok(ModelLoader::build_obj(id, data, self, true)),
+            // The skins and animations are only read by ModelLoader::load_gltf
+            ModelData::Gltf(file) => ModelLoader::build_gltf(id, &file, self, true)
+                .map(|model| model.root)
+                .map_err(|message| AssetError::Gltf { path: id.to_string(), message }),
+        }
+    }
+
+    // Uploads the decoded assets, must be called on the GL thread
+    pub fn update(&self) {
+        // Received first, the meshes of the models built below wait for the next frames
+        let decoded: Vec<Decoded> = self.receiver.try_iter().take(MAX_UPLOADS_PER_FRAME).collect();
+        for decoded in decoded {
+            match decoded {
+                Decoded::Texture(handle, Ok((format, image))) => {
+                    let texture = Arc::new(TextureCache::upload_image(format, &image));
+                    CONTAINER.get_local::<TextureCache>().insert_texture(handle.id(), &texture);
+                    handle.resolve(Some(texture));
+                }
+                Decoded::Mesh(handle, data) => {
+                    let mesh = Arc::new(Self::upload_mesh(data));
+                    CONTAINER.get_local::<TextureCache>().insert_mesh(handle.id().to_string(), &mesh);
+                    handle.resolve(Some(mesh));
+                }
+                Decoded::Model(handle, Ok(data)) => {
+                    match self.build_model(handle.id(), data) {
+                        Ok(model) => handle.resolve(Some(Arc::new(model))),
+                        Err(err) => {
+                            error!("{}", err);
+                            handle.resolve(None);
+                        }
+                    }
+                }
+                Decoded::Texture(handle, Err(err)) => {
+                    error!("{}", err);
+                    handle.resolve(None);
+                }
+                Decoded::Model(handle, Err(err)) => {
+                    error!("{}", err);
+                    handle.resolve(None);
+                }
+            }
+        }
+
+        // Forgets the assets whose handles were all dropped
+        self.textures.borrow_mut().retain(|_, slot| slot.strong_count() > 0);
+        self.meshes.borrow_mut().retain(|_, slot| slot.strong_count() > 0);
+        self.models.borrow_mut().retain(|_, slot| slot.strong_count() > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    // Calls `update` until the handle isn't loading anymore
+    fn wait_for<T>(server: &AssetServer, handle: &Handle<T>) {
+        for _ in 0..200 {
+            server.update();
+            if handle.load_state() != LoadState::Loading {
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn dropped_handles_are_forgotten() {
+        let server = AssetServer::default();
+        let kept = server.load_model("models/missing_kept.obj");
+        drop(server.load_model("models/missing_dropped.obj"));
+
+        // The decoding jobs hold their handle until the result is received
+        wait_for(&server, &kept);
+        for _ in 0..200 {
+            server.update();
+            if server.models.borrow().len() == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(kept.load_state(), LoadState::Failed);
+        assert!(kept.get().is_none());
+        assert_eq!(server.models.borrow().keys().collect::<Vec<_>>(), vec!["models/missing_kept.obj"]);
+
+        // Still shared while alive
+        assert!(Arc::ptr_eq(&server.load_model("models/missing_kept.obj").inner, &kept.inner));
+    }
+
+    #[test]
+    fn decodes_the_objects_of_an_obj_file() {
+        let data = decode_obj("models/cube/Cube.obj").unwrap();
+        assert_eq!(data.objects.len(), 1);
+        assert_eq!(data.materials.len(), 1);
+        assert_eq!(data.materials[0].diffuse_texture, "Cube_diffuse.jpg");
+
+        let object = &data.objects[0];
+        assert_eq!(object.material, Some(0));
+        let vertex_count = object.mesh.positions.len() / 3;
+        assert!(vertex_count > 0);
+        assert_eq!(object.mesh.normals.len(), 3 * vertex_count);
+        assert_eq!(object.mesh.texcoords.len(), 2 * vertex_count);
+        assert_eq!(object.mesh.tangents.as_ref().map(Vec::len), Some(4 * vertex_count));
+    }
+
+    #[test]
+    fn models_are_loaded_before_their_meshes_and_textures() {
+        CONTAINER.set_local(TextureCache::default);
+        let server = AssetServer::default();
+        let handle = server.load_model("models/cube/Cube.obj");
+        assert!(Arc::ptr_eq(&server.load_model("models/cube/Cube.obj").inner, &handle.inner));
+
+        // Building the hierarchy doesn't need GL, unlike the uploads left to the next updates
+        wait_for(&server, &handle);
+        assert_eq!(handle.load_state(), LoadState::Loaded);
+
+        let model = handle.get().unwrap();
+        assert_eq!(model.name, "Cube");
+        assert!(model.mesh_renderer.is_none());
+        assert_eq!(model.children.len(), 1);
+
+        let mesh = &model.children[0].mesh_renderer.as_ref().unwrap().mesh;
+        assert_eq!(mesh.id(), "models/cube/Cube.obj#0");
+        assert_eq!(mesh.load_state(), LoadState::Loading);
+        assert!(server.meshes.borrow().contains_key("models/cube/Cube.obj#0"));
+        // The textures of the material go through the server too
+        assert!(server.textures.borrow().contains_key("models/cube/Cube_diffuse.jpg"));
+    }
+}
@@ -0,0 +1,42 @@
+use image::{DynamicImage, ImageBuffer, Rgba};
+
+const CHECKERBOARD_SIZE: u32 = 64;
+const CHECKERBOARD_CELL: u32 = 8;
+
+// Magenta and black, hard to miss in a scene
+pub(crate) fn checkerboard_image() -> DynamicImage {
+    DynamicImage::ImageRgba8(ImageBuffer::from_fn(CHECKERBOARD_SIZE, CHECKERBOARD_SIZE, |x, y| {
+        if (x / CHECKERBOARD_CELL + y / CHECKERBOARD_CELL) % 2 == 0 {
+            Rgba([255, 0, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    }))
+}
+
+// Unit cube centered on the origin with one quad per face, the faces are given by their
+// normal and the directions of the texture's u and v axes
+pub(crate) fn cube_vertices() -> (Vec<f32>, Vec<f32>, Vec<f32>, Vec<u32>) {
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+
+    let (mut positions, mut texcoords, mut normals, mut indices) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (normal, u, v) in faces.iter() {
+        let first = (positions.len() / 3) as u32;
+        for &(a, b) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            for axis in 0..3 {
+                positions.push(0.5 * (normal[axis] + a * u[axis] + b * v[axis]));
+            }
+            texcoords.extend_from_slice(&[(a + 1.0) / 2.0, (b + 1.0) / 2.0]);
+            normals.extend_from_slice(normal);
+        }
+        indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+    (positions, texcoords, normals, indices)
+}
@@ -9,9 +9,11 @@ use crate::ecs::components::*;
 use crate::shaders::diffuse::{DiffuseData, PixelData};
 use tobj;
 use crate::utils::ToVec3;
+use super::{compute_tangents, decode_obj, ModelNode, AssetError, AssetServer, Handle, ObjData, ObjObject};
+use super::fallback::{checkerboard_image, cube_vertices};
 use std::sync::{Arc, Weak};
 use crate::gl_wrapper::{BufferUpdateFrequency, TextureFormat};
-use image::GenericImageView;
+use image::{GenericImageView, DynamicImage, ImageResult};
 use std::borrow::BorrowMut;
 
 pub static CONTAINER: state::Container = state::Container::new();
@@ -19,50 +21,104 @@ pub static CONTAINER: state::Container = state::Container::new();
 #[derive(Default)]
 pub struct TextureCache {
     textures: RefCell<HashMap<String, Weak<Texture2D>>>,
-    meshes: RefCell<HashMap<String, Weak<Mesh>>>
+    meshes: RefCell<HashMap<String, Weak<Mesh>>>,
+    // Created on first use, kept for the whole session
+    fallback_texture: RefCell<Option<Arc<Texture2D>>>,
+    fallback_mesh: RefCell<Option<Arc<Mesh>>>,
 }
 
 impl TextureCache {
+    // The fallback texture is returned when the image can't be loaded
     pub fn get_texture(&self, id: &str) -> Arc<Texture2D> {
-        let update_tex = || {
-            let mut t = Texture2D::new();
-            let img = image::open(id);
-            let img = match img {
-                Ok(img) => img.flipv(),
-                Err(err) => panic!("Filename: {}, error: {}", id, err.to_string())
-            };
+        self.load_texture(id).unwrap_or_else(|err| {
+            error!("{}, using the fallback texture", err);
+            self.fallback_texture()
+        })
+    }
 
-            let format = match img.color() {
-                image::RGB(8) => TextureFormat::RGB,
-                image::RGBA(8) => TextureFormat::RGBA,
-                _ => panic!("Texture format not supported")
-            };
+    // For the images embedded in model files, `id` must be unique to the image
+    pub fn get_texture_from_memory(&self, id: &str, bytes: &[u8]) -> Arc<Texture2D> {
+        self.load_texture_from_memory(id, bytes).unwrap_or_else(|err| {
+            error!("{}, using the fallback texture", err);
+            self.fallback_texture()
+        })
+    }
 
-            t.allocate(format, img.width(), img.height(), 8);
-            t.update(0, 0, &img);
+    pub fn load_texture(&self, id: &str) -> Result<Arc<Texture2D>, AssetError> {
+        self.cached_texture(id, || image::open(id))
+    }
 
-            let t = Arc::new(t);
-            self.textures.borrow_mut().insert(id.into(), Arc::downgrade(&t));
-            t
-        };
+    pub fn load_texture_from_memory(&self, id: &str, bytes: &[u8]) -> Result<Arc<Texture2D>, AssetError> {
+        self.cached_texture(id, || image::load_from_memory(bytes))
+    }
 
-        let texture = self.textures.borrow().get(id).cloned();
+    // Failures aren't cached, the file is read again on the next call
+    fn cached_texture<F>(&self, id: &str, load: F) -> Result<Arc<Texture2D>, AssetError>
+        where F: FnOnce() -> ImageResult<DynamicImage>
+    {
+        let texture = self.textures.borrow().get(id).and_then(|tex| tex.upgrade());
         match texture {
-            Some(tex) => {
-                let tex = tex.upgrade();
-                match tex {
-                    Some(tex) => tex,
-                    None => {
-                        update_tex()
-                    }
-                }
-            },
+            Some(tex) => Ok(tex),
             None => {
-                update_tex()
+                let img = load().map_err(|error| AssetError::Image { path: id.to_string(), error })?;
+                let t = Arc::new(Self::upload(img));
+                self.textures.borrow_mut().insert(id.into(), Arc::downgrade(&t));
+                Ok(t)
             }
         }
     }
 
+    // Magenta checkerboard standing in for the textures that couldn't be loaded
+    pub fn fallback_texture(&self) -> Arc<Texture2D> {
+        self.fallback_texture.borrow_mut()
+            .get_or_insert_with(|| Arc::new(Self::upload(checkerboard_image())))
+            .clone()
+    }
+
+    // Unit cube standing in for the models that couldn't be loaded
+    pub fn fallback_mesh(&self) -> Arc<Mesh> {
+        self.fallback_mesh.borrow_mut()
+            .get_or_insert_with(|| {
+                let (positions, texcoords, normals, indices) = cube_vertices();
+                Arc::new(ModelLoader::build_mesh(positions, texcoords, normals, None, indices))
+            })
+            .clone()
+    }
+
+    fn upload(img: DynamicImage) -> Texture2D {
+        let (format, img) = Self::prepare_image(img);
+        Self::upload_image(format, &img)
+    }
+
+    // Flipped and converted to a supported format, doesn't need the GL context.
+    // Grayscale, BGR and palette images are expanded.
+    pub(crate) fn prepare_image(img: DynamicImage) -> (TextureFormat, DynamicImage) {
+        let img = img.flipv();
+        match img.color() {
+            image::RGB(8) => (TextureFormat::RGB, img),
+            image::RGBA(8) => (TextureFormat::RGBA, img),
+            _ => (TextureFormat::RGBA, DynamicImage::ImageRgba8(img.to_rgba())),
+        }
+    }
+
+    pub(crate) fn upload_image(format: TextureFormat, img: &DynamicImage) -> Texture2D {
+        let mut t = Texture2D::new();
+
+        // Down to 1x1 at most
+        let max_levels = 32 - img.width().max(img.height()).leading_zeros();
+        t.allocate(format, img.width(), img.height(), max_levels.min(8));
+        t.update(0, 0, img);
+        t
+    }
+
+    pub fn get_loaded_texture(&self, id: &str) -> Option<Arc<Texture2D>> {
+        self.textures.borrow().get(id).and_then(|tex| tex.upgrade())
+    }
+
+    pub fn insert_texture(&self, id: &str, texture: &Arc<Texture2D>) {
+        self.textures.borrow_mut().insert(id.into(), Arc::downgrade(texture));
+    }
+
     pub fn get_mesh(&self, id: &str) -> Option<Arc<Mesh>> {
         self.meshes.borrow().get(id).and_then(|m| m.upgrade())
     }
@@ -76,12 +132,18 @@ impl TextureCache {
 pub struct ModelLoader;
 
 impl ModelLoader {
-    fn load_mesh(model: &tobj::Model) -> Mesh {
-        // TODO Maybe there aren't any normals/texture coords
+    // Uploads the vertex data, the tangents are generated when missing
+    pub(crate) fn build_mesh(positions: Vec<f32>,
+                             texcoords: Vec<f32>,
+                             normals: Vec<f32>,
+                             tangents: Option<Vec<f32>>,
+                             indices: Vec<u32>
+    ) -> Mesh {
+        let tangents = tangents.unwrap_or_else(|| compute_tangents(&positions, &normals, &texcoords, &indices));
 
         let ebo = {
             let mut ebo = EBO::new();
-            ebo.with(&model.mesh.indices, BufferUpdateFrequency::Never);
+            ebo.with(&indices, BufferUpdateFrequency::Never);
             ebo
         };
 
@@ -92,7 +154,7 @@ impl ModelLoader {
                     let mut vbo = VBO::new(vec![
                         VertexAttribute { index: 0, components: 3 }
                     ]);
-                    vbo.with(&model.mesh.positions, BufferUpdateFrequency::Never);
+                    vbo.with(&positions, BufferUpdateFrequency::Never);
                     vbo
                 },
                 // texcoords
@@ -100,7 +162,7 @@ impl ModelLoader {
                     let mut vbo = VBO::new(vec![
                         VertexAttribute { index: 1, components: 2 }
                     ]);
-                    vbo.with(&model.mesh.texcoords, BufferUpdateFrequency::Never);
+                    vbo.with(&texcoords, BufferUpdateFrequency::Never);
                     vbo
                 },
                 // normals
@@ -108,7 +170,15 @@ impl ModelLoader {
                     let mut vbo = VBO::new(vec![
                         VertexAttribute { index: 2, components: 3 }
                     ]);
-                    vbo.with(&model.mesh.normals, BufferUpdateFrequency::Never);
+                    vbo.with(&normals, BufferUpdateFrequency::Never);
+                    vbo
+                },
+                // tangents, the handedness of the bitangent in w
+                {
+                    let mut vbo = VBO::new(vec![
+                        VertexAttribute { index: 3, components: 4 }
+                    ]);
+                    vbo.with(&tangents, BufferUpdateFrequency::Never);
                     vbo
                 }
             ],
@@ -117,17 +187,16 @@ impl ModelLoader {
 
         Mesh {
             vao,
-            positions: model.mesh.positions.clone(),
-            indices: model.mesh.indices.clone(),
-            normals: model.mesh.normals.clone(),
-            texcoords: model.mesh.texcoords.clone()
+            positions,
+            indices,
+            normals,
+            texcoords,
+            tangents
         }
     }
 
-    fn load_material(obj_path: &Path, model: &tobj::Model, materials: &[tobj::Material]) -> Material {
-        let _shader_data = DiffuseData::default();
-
-        let material = model.mesh.material_id.map(|id| &materials[id]);
+    // The textures are loaded by the server
+    fn load_material(obj_path: &Path, object_name: &str, material: Option<&tobj::Material>, server: &AssetServer) -> Material {
         let shader_data = if let Some(material) = material {
             trace!("Loading material {}", material.name);
             debug!("material.Ka = {:?}", material.ambient);
@@ -140,8 +209,15 @@ impl ModelLoader {
             debug!("material.map_Ns = {}", material.normal_texture);
             debug!("material.map_d = {}", material.dissolve_texture);
 
-            let texture_cache = CONTAINER.get_local::<TextureCache>();
-            let files_path = dbg!(obj_path.parent().unwrap());
+            let files_path = obj_path.parent().unwrap_or_else(|| Path::new(""));
+            // Relative to the OBJ file, the paths that aren't valid UTF-8 can't be texture ids
+            let texture = |file: &str| match files_path.join(file).to_str() {
+                Some(path) => server.load_texture(path),
+                None => {
+                    error!("{}: texture path isn't valid UTF-8, using the fallback texture", files_path.join(file).display());
+                    Handle::loaded(file, CONTAINER.get_local::<TextureCache>().fallback_texture())
+                }
+            };
 
             // Load diffuse
             let diffuse = if material.diffuse_texture.is_empty() {
@@ -149,8 +225,7 @@ impl ModelLoader {
                 PixelData::Color(material.diffuse.to_vec3())
             } else {
                 // TODO load textures and material
-                let diffuse_path = files_path.join(&material.diffuse_texture);
-                PixelData::Texture(texture_cache.get_texture(diffuse_path.to_str().unwrap()))
+                PixelData::Texture(texture(&material.diffuse_texture))
             };
 
             // Load specular
@@ -158,8 +233,7 @@ impl ModelLoader {
                 warn!("No specular texture");
                 PixelData::Color(material.specular.to_vec3())
             } else {
-                let specular_path = files_path.join(&material.specular_texture);
-                PixelData::Texture(texture_cache.get_texture(specular_path.to_str().unwrap()))
+                PixelData::Texture(texture(&material.specular_texture))
             };
 
             // Load normal
@@ -167,8 +241,7 @@ impl ModelLoader {
                 warn!("No normal texture");
                 None
             } else {
-                let normal_path = files_path.join(&material.normal_texture);
-                Some(texture_cache.get_texture(normal_path.to_str().unwrap()))
+                Some(texture(&material.normal_texture))
             };
 
             let shininess = material.shininess;
@@ -180,7 +253,7 @@ impl ModelLoader {
                 shininess
             }
         } else {
-            warn!("Model {} doesn't have a material", model.name);
+            warn!("Model {} doesn't have a material", object_name);
             warn!("Loading default material");
             DiffuseData::default()
         };
@@ -190,20 +263,83 @@ impl ModelLoader {
         }
     }
 
-    pub fn load(&self, filename: &str) -> MeshRenderer {
+    // A textured cube is shown in place of the models that couldn't be loaded
+    pub fn load(&self, filename: &str) -> ModelNode {
+        self.load_model(filename).unwrap_or_else(|err| {
+            error!("{}, using the fallback model", err);
+            Self::fallback_model(filename)
+        })
+    }
+
+    // OBJ, glTF or GLB file, depending on the extension. Missing textures are replaced
+    // by the fallback texture.
+    pub fn load_model(&self, filename: &str) -> Result<ModelNode, AssetError> {
+        let extension = Path::new(filename).extension().and_then(|extension| extension.to_str());
+        match extension {
+            Some("gltf") | Some("glb") => self.load_gltf(filename).map(|model| model.root),
+            _ => self.load_obj(filename),
+        }
+    }
+
+    fn fallback_model(filename: &str) -> ModelNode {
+        let texture_cache = CONTAINER.get_local::<TextureCache>();
+        let shader_data = DiffuseData {
+            diffuse: PixelData::Texture(Handle::loaded(filename, texture_cache.fallback_texture())),
+            ..DiffuseData::default()
+        };
+
+        ModelNode {
+            name: Path::new(filename).file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            transform: Transform::default(),
+            mesh_renderer: Some(MeshRenderer {
+                mesh: Handle::loaded(filename, texture_cache.fallback_mesh()),
+                material: Arc::new(Material { shader_data: Box::new(shader_data) }),
+            }),
+            children: Vec::new(),
+        }
+    }
+
+    // One child node per object of the file, each with its own material. The meshes are
+    // cached, loading the same file again shares their buffers.
+    pub fn load_obj(&self, filename: &str) -> Result<ModelNode, AssetError> {
+        let data = decode_obj(filename)?;
+        Ok(Self::build_obj(filename, data, CONTAINER.get_local::<AssetServer>(), false))
+    }
+
+    // The mesh uploads are left to the server when deferred
+    pub(crate) fn build_obj(filename: &str, data: ObjData, server: &AssetServer, deferred: bool) -> ModelNode {
         let obj_path = Path::new(filename);
-        let obj = tobj::load_obj(&obj_path);
-        assert!(obj.is_ok());
+        let ObjData { objects, materials } = data;
+
+        // Shared by the objects using the same material
+        let mut loaded_materials: HashMap<Option<usize>, Arc<Material>> = HashMap::new();
 
-        let (models, materials) = obj.unwrap();
-        let first_model = models.first().unwrap();
+        let children = objects.into_iter().enumerate()
+            .map(|(i, ObjObject { name, mesh, material })| {
+                // The object names aren't unique
+                let mesh = server.mesh(&format!("{}#{}", filename, i), mesh, deferred);
 
-        let mesh = Self::load_mesh(&first_model);
-        let material = Self::load_material(&obj_path, &first_model, &materials);
+                let material = loaded_materials.entry(material)
+                    .or_insert_with(|| {
+                        let material = material.and_then(|id| materials.get(id));
+                        Arc::new(Self::load_material(obj_path, &name, material, server))
+                    })
+                    .clone();
 
-        MeshRenderer {
-            mesh: Arc::new(mesh),
-            material: Arc::new(material)
+                ModelNode {
+                    name,
+                    transform: Transform::default(),
+                    mesh_renderer: Some(MeshRenderer { mesh, material }),
+                    children: Vec::new(),
+                }
+            })
+            .collect();
+
+        ModelNode {
+            name: obj_path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            transform: Transform::default(),
+            mesh_renderer: None,
+            children,
         }
     }
-}
\ No newline at end of file
+}
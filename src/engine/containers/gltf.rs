@@ -0,0 +1,750 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use nalgebra::{Matrix3, Quaternion, Rotation3, UnitQuaternion};
+use nalgebra_glm::{Mat4, Vec3, vec3};
+use crate::ecs::components::*;
+use crate::gl_wrapper::texture_2d::Texture2D;
+use crate::shaders::pbr::PbrData;
+use super::{AssetError, AssetServer, Handle, JsonValue, MeshData, ModelLoader, ModelNode, CONTAINER};
+
+// Little endian "glTF", "JSON" and "BIN\0"
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+const MODE_TRIANGLES: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AnimationPath {
+    Translation,
+    Rotation,
+    Scale,
+    Weights,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    CubicSpline,
+}
+
+// Keyframes of one property of a node. The values are flattened, 4 floats per key for
+// the rotations, 3 for the translations and scales, and 3 times more with cubic splines
+// for the tangents.
+#[derive(Debug, Clone)]
+pub struct AnimationChannel {
+    pub node: usize,
+    pub path: AnimationPath,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GltfAnimation {
+    pub name: String,
+    pub channels: Vec<AnimationChannel>,
+}
+
+// The joints are node indices, see GltfModel::node_paths
+#[derive(Debug, Clone)]
+pub struct GltfSkin {
+    pub name: String,
+    pub joints: Vec<usize>,
+    pub skeleton: Option<usize>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+pub struct GltfModel {
+    pub root: ModelNode,
+    // Indices in the `children` of every node from the root to each node of the file,
+    // None for the nodes outside of the scene
+    pub node_paths: Vec<Option<Vec<usize>>>,
+    // Not applied yet, kept for the skinning and the animation systems
+    pub skins: Vec<GltfSkin>,
+    pub animations: Vec<GltfAnimation>,
+}
+
+// Vertex data of a primitive, ready to upload
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrimitiveData {
+    pub positions: Vec<f32>,
+    pub normals: Vec<f32>,
+    // With the origin at the bottom left, like the OBJ files
+    pub texcoords: Vec<f32>,
+    pub tangents: Option<Vec<f32>>,
+    pub indices: Vec<u32>,
+    pub material: Option<usize>,
+}
+
+fn base64_decode(data: &str) -> Result<Vec<u8>, String> {
+    fn sextet(byte: u8) -> Option<u32> {
+        match byte {
+            b'A'..=b'Z' => Some((byte - b'A') as u32),
+            b'a'..=b'z' => Some((byte - b'a') as u32 + 26),
+            b'0'..=b'9' => Some((byte - b'0') as u32 + 52),
+            b'+' | b'-' => Some(62),
+            b'/' | b'_' => Some(63),
+            _ => None,
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for byte in data.bytes().filter(|byte| !byte.is_ascii_whitespace() && *byte != b'=') {
+        bits = (bits << 6) | sextet(byte).ok_or_else(|| format!("Invalid base64 character '{}'", byte as char))?;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes.get(offset..offset + 4)
+        .map(|b| u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24)
+        .ok_or_else(|| "Unexpected end of the GLB file".to_string())
+}
+
+// JSON chunk and optional binary chunk
+fn parse_glb(bytes: &[u8]) -> Result<(String, Option<Vec<u8>>), String> {
+    if read_u32(bytes, 0)? != GLB_MAGIC {
+        return Err("Not a GLB file".to_string());
+    }
+    if read_u32(bytes, 4)? != 2 {
+        return Err("Only glTF 2.0 is supported".to_string());
+    }
+    let length = (read_u32(bytes, 8)? as usize).min(bytes.len());
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = read_u32(bytes, offset)? as usize;
+        let chunk_type = read_u32(bytes, offset + 4)?;
+        let chunk = bytes.get(offset + 8..offset + 8 + chunk_length)
+            .ok_or_else(|| "GLB chunk out of bounds".to_string())?;
+        match chunk_type {
+            GLB_JSON_CHUNK => json = Some(String::from_utf8(chunk.to_vec()).map_err(|err| err.to_string())?),
+            GLB_BIN_CHUNK if bin.is_none() => bin = Some(chunk.to_vec()),
+            _ => {}
+        }
+        // Chunks are 4 bytes aligned
+        offset += 8 + ((chunk_length + 3) & !3);
+    }
+
+    json.map(|json| (json, bin)).ok_or_else(|| "GLB file without JSON chunk".to_string())
+}
+
+fn component_size(component_type: usize) -> Result<usize, String> {
+    match component_type {
+        5120 | 5121 => Ok(1),
+        5122 | 5123 => Ok(2),
+        5125 | 5126 => Ok(4),
+        _ => Err(format!("Unknown component type {}", component_type)),
+    }
+}
+
+fn component_count(accessor_type: &str) -> Result<usize, String> {
+    match accessor_type {
+        "SCALAR" => Ok(1),
+        "VEC2" => Ok(2),
+        "VEC3" => Ok(3),
+        "VEC4" | "MAT2" => Ok(4),
+        "MAT3" => Ok(9),
+        "MAT4" => Ok(16),
+        _ => Err(format!("Unknown accessor type {}", accessor_type)),
+    }
+}
+
+fn read_component(bytes: &[u8], component_type: usize, normalized: bool) -> f32 {
+    match component_type {
+        5120 => {
+            let value = bytes[0] as i8 as f32;
+            if normalized { (value / 127.0).max(-1.0) } else { value }
+        }
+        5121 => {
+            let value = f32::from(bytes[0]);
+            if normalized { value / 255.0 } else { value }
+        }
+        5122 => {
+            let value = i16::from_le_bytes([bytes[0], bytes[1]]) as f32;
+            if normalized { (value / 32767.0).max(-1.0) } else { value }
+        }
+        5123 => {
+            let value = f32::from(u16::from_le_bytes([bytes[0], bytes[1]]));
+            if normalized { value / 65535.0 } else { value }
+        }
+        5125 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+        _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    }
+}
+
+// Smooth normals from the triangles, weighted by their area
+pub(crate) fn compute_normals(positions: &[f32], indices: &[u32]) -> Vec<f32> {
+    let mut normals = vec![Vec3::zeros(); positions.len() / 3];
+    let position = |i: u32| vec3(positions[3 * i as usize], positions[3 * i as usize + 1], positions[3 * i as usize + 2]);
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (position(triangle[0]), position(triangle[1]), position(triangle[2]));
+        let normal = (b - a).cross(&(c - a));
+        for &i in triangle {
+            normals[i as usize] += normal;
+        }
+    }
+    normals.iter()
+        .flat_map(|normal| {
+            let normal = if normal.norm() > std::f32::EPSILON { normal.normalize() } else { vec3(0.0, 0.0, 1.0) };
+            vec![normal.x, normal.y, normal.z]
+        })
+        .collect()
+}
+
+fn node_transform(node: &JsonValue) -> Transform {
+    if let Some(matrix) = node.get("matrix").as_f32_vec().filter(|matrix| matrix.len() == 16) {
+        let matrix = Mat4::from_column_slice(&matrix);
+        let columns = [matrix.column(0).xyz(), matrix.column(1).xyz(), matrix.column(2).xyz()];
+        let scale = vec3(columns[0].norm(), columns[1].norm(), columns[2].norm());
+        // A zero scale leaves no direction, the axis is kept instead of dividing by zero
+        let axis = |i: usize| if scale[i] > std::f32::EPSILON { columns[i] / scale[i] } else { Matrix3::identity().column(i).into_owned() };
+        let rotation = Matrix3::from_columns(&[axis(0), axis(1), axis(2)]);
+        return Transform {
+            position: matrix.column(3).xyz(),
+            rotation: UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation)),
+            scale,
+            ..Transform::default()
+        };
+    }
+
+    let mut transform = Transform::default();
+    if let Some(t) = node.get("translation").as_f32_vec().filter(|t| t.len() == 3) {
+        transform.position = vec3(t[0], t[1], t[2]);
+    }
+    // x, y, z, w
+    if let Some(r) = node.get("rotation").as_f32_vec().filter(|r| r.len() == 4) {
+        transform.rotation = UnitQuaternion::from_quaternion(Quaternion::new(r[3], r[0], r[1], r[2]));
+    }
+    if let Some(s) = node.get("scale").as_f32_vec().filter(|s| s.len() == 3) {
+        transform.scale = vec3(s[0], s[1], s[2]);
+    }
+    transform
+}
+
+// A parsed .gltf or .glb file with its buffers, independent from GL
+pub struct GltfFile {
+    pub document: JsonValue,
+    buffers: Vec<Vec<u8>>,
+    directory: PathBuf,
+}
+
+impl GltfFile {
+    pub fn open(path: &Path) -> Result<Self, AssetError> {
+        let bytes = std::fs::read(path)
+            .map_err(|error| AssetError::Io { path: path.display().to_string(), error })?;
+        Self::parse(path, bytes)
+            .map_err(|message| AssetError::Gltf { path: path.display().to_string(), message })
+    }
+
+    fn parse(path: &Path, bytes: Vec<u8>) -> Result<Self, String> {
+        let (json, bin) = if bytes.starts_with(b"glTF") {
+            parse_glb(&bytes)?
+        } else {
+            (String::from_utf8(bytes).map_err(|err| err.to_string())?, None)
+        };
+
+        let document = JsonValue::parse(&json)?;
+        let directory = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+
+        let mut bin = bin;
+        let buffers = document.get("buffers").members().iter()
+            .map(|buffer| match buffer.get("uri").as_str() {
+                Some(uri) => Self::read_uri(&directory, uri),
+                // The GLB binary chunk
+                None => bin.take().ok_or_else(|| "Buffer without data".to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(GltfFile { document, buffers, directory })
+    }
+
+    fn read_uri(directory: &Path, uri: &str) -> Result<Vec<u8>, String> {
+        if uri.starts_with("data:") {
+            let data = uri.splitn(2, ";base64,").nth(1)
+                .ok_or_else(|| "Only base64 data URIs are supported".to_string())?;
+            base64_decode(data)
+        } else {
+            let path = directory.join(uri);
+            std::fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))
+        }
+    }
+
+    fn element<'a>(&'a self, collection: &str, index: usize) -> Result<&'a JsonValue, String> {
+        self.document.get(collection).members().get(index)
+            .ok_or_else(|| format!("Missing {} {}", collection, index))
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), String> {
+        let view = self.element("bufferViews", index)?;
+        let buffer = view.get("buffer").as_usize()
+            .and_then(|buffer| self.buffers.get(buffer))
+            .ok_or_else(|| format!("Buffer view {} without buffer", index))?;
+        let offset = view.get("byteOffset").as_usize().unwrap_or(0);
+        let length = view.get("byteLength").as_usize().unwrap_or(0);
+        let bytes = buffer.get(offset..offset + length)
+            .ok_or_else(|| format!("Buffer view {} out of bounds", index))?;
+        Ok((bytes, view.get("byteStride").as_usize()))
+    }
+
+    // Flattened components, converted to floats. Sparse accessors aren't supported.
+    pub fn read_accessor(&self, index: usize) -> Result<Vec<f32>, String> {
+        let accessor = self.element("accessors", index)?;
+        let component_type = accessor.get("componentType").as_usize().unwrap_or(0);
+        let size = component_size(component_type)?;
+        let components = component_count(accessor.get("type").as_str().unwrap_or(""))?;
+        let count = accessor.get("count").as_usize().unwrap_or(0);
+        let normalized = accessor.get("normalized").as_bool().unwrap_or(false);
+
+        let view = match accessor.get("bufferView").as_usize() {
+            Some(view) => view,
+            // Initialized with zeros
+            None => return Ok(vec![0.0; count * components]),
+        };
+        let (bytes, stride) = self.buffer_view(view)?;
+        let offset = accessor.get("byteOffset").as_usize().unwrap_or(0);
+        let stride = stride.unwrap_or(size * components);
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let start = offset + element * stride + component * size;
+                let bytes = bytes.get(start..start + size)
+                    .ok_or_else(|| format!("Accessor {} out of bounds", index))?;
+                values.push(read_component(bytes, component_type, normalized));
+            }
+        }
+        Ok(values)
+    }
+
+    fn read_attribute(&self, attributes: &JsonValue, name: &str) -> Result<Option<Vec<f32>>, String> {
+        attributes.get(name).as_usize().map(|accessor| self.read_accessor(accessor)).transpose()
+    }
+
+    pub fn primitive(&self, mesh: usize, primitive: usize) -> Result<PrimitiveData, String> {
+        let primitive = self.element("meshes", mesh)?.get("primitives").members().get(primitive)
+            .ok_or_else(|| format!("Missing primitive {} of mesh {}", primitive, mesh))?;
+        if primitive.get("mode").as_usize().unwrap_or(MODE_TRIANGLES) != MODE_TRIANGLES {
+            return Err(format!("Mesh {} isn't made of triangles", mesh));
+        }
+
+        let attributes = primitive.get("attributes");
+        let positions = self.read_attribute(attributes, "POSITION")?
+            .ok_or_else(|| format!("Mesh {} without positions", mesh))?;
+        let vertex_count = positions.len() / 3;
+
+        let indices: Vec<u32> = match primitive.get("indices").as_usize() {
+            Some(accessor) => self.read_accessor(accessor)?.iter().map(|&index| index as u32).collect(),
+            None => (0..vertex_count as u32).collect(),
+        };
+        if indices.iter().any(|&index| index as usize >= vertex_count) {
+            return Err(format!("Mesh {} has indices out of bounds", mesh));
+        }
+
+        let normals = match self.read_attribute(attributes, "NORMAL")? {
+            Some(normals) => normals,
+            None => compute_normals(&positions, &indices),
+        };
+
+        // The textures are flipped when uploaded
+        let texcoords = match self.read_attribute(attributes, "TEXCOORD_0")? {
+            Some(mut texcoords) => {
+                for v in texcoords.iter_mut().skip(1).step_by(2) {
+                    *v = 1.0 - *v;
+                }
+                texcoords
+            }
+            None => vec![0.0; 2 * vertex_count],
+        };
+
+        Ok(PrimitiveData {
+            positions,
+            normals,
+            texcoords,
+            tangents: self.read_attribute(attributes, "TANGENT")?,
+            indices,
+            material: primitive.get("material").as_usize(),
+        })
+    }
+
+    // Embedded images are read from their buffer view or data URI, the textures are loaded by the server
+    fn texture(&self, texture_info: &JsonValue, id: &str, server: &AssetServer) -> Result<Option<Handle<Texture2D>>, String> {
+        let texture = match texture_info.get("index").as_usize() {
+            Some(texture) => self.element("textures", texture)?,
+            None => return Ok(None),
+        };
+        let image_index = texture.get("source").as_usize()
+            .ok_or_else(|| "Texture without source".to_string())?;
+        let image = self.element("images", image_index)?;
+        let image_id = format!("{}#image{}", id, image_index);
+
+        match (image.get("uri").as_str(), image.get("bufferView").as_usize()) {
+            (Some(uri), _) if !uri.starts_with("data:") => {
+                let path = self.directory.join(uri).to_string_lossy().into_owned();
+                Ok(Some(server.load_texture(&path)))
+            }
+            (Some(uri), _) => {
+                let bytes = Self::read_uri(&self.directory, uri)?;
+                Ok(Some(server.load_texture_from_memory(&image_id, bytes)))
+            }
+            (None, Some(view)) => {
+                let (bytes, _) = self.buffer_view(view)?;
+                Ok(Some(server.load_texture_from_memory(&image_id, bytes.to_vec())))
+            }
+            (None, None) => Err(format!("Image {} without data", image_index)),
+        }
+    }
+
+    fn material(&self, index: usize, id: &str, server: &AssetServer) -> Result<PbrData, String> {
+        let material = self.element("materials", index)?;
+        let pbr = material.get("pbrMetallicRoughness");
+        let mut data = PbrData::default();
+
+        // The alpha is ignored
+        if let Some(color) = pbr.get("baseColorFactor").as_f32_vec().filter(|color| color.len() == 4) {
+            data.albedo = vec3(color[0], color[1], color[2]);
+        }
+        data.metallic = pbr.get("metallicFactor").as_f32().unwrap_or(1.0);
+        data.roughness = pbr.get("roughnessFactor").as_f32().unwrap_or(1.0);
+        if let Some(emissive) = material.get("emissiveFactor").as_f32_vec().filter(|emissive| emissive.len() == 3) {
+            data.emissive = vec3(emissive[0], emissive[1], emissive[2]);
+        }
+
+        data.albedo_map = self.texture(pbr.get("baseColorTexture"), id, server)?;
+        data.metallic_roughness_map = self.texture(pbr.get("metallicRoughnessTexture"), id, server)?;
+        data.normal_map = self.texture(material.get("normalTexture"), id, server)?;
+        data.ao_map = self.texture(material.get("occlusionTexture"), id, server)?;
+        data.emissive_map = self.texture(material.get("emissiveTexture"), id, server)?;
+        Ok(data)
+    }
+
+    // Nodes of the default scene, or every node without a parent when there are no scenes
+    pub fn scene_roots(&self) -> Vec<usize> {
+        let scenes = self.document.get("scenes").members();
+        if !scenes.is_empty() {
+            let scene = self.document.get("scene").as_usize().unwrap_or(0);
+            return scenes.get(scene)
+                .map(|scene| scene.get("nodes").members().iter().filter_map(JsonValue::as_usize).collect())
+                .unwrap_or_default();
+        }
+
+        let nodes = self.document.get("nodes").members();
+        let children: HashSet<usize> = nodes.iter()
+            .flat_map(|node| node.get("children").members().iter().filter_map(JsonValue::as_usize))
+            .collect();
+        (0..nodes.len()).filter(|node| !children.contains(node)).collect()
+    }
+
+    pub fn skins(&self) -> Result<Vec<GltfSkin>, String> {
+        self.document.get("skins").members().iter()
+            .map(|skin| {
+                let inverse_bind_matrices = match skin.get("inverseBindMatrices").as_usize() {
+                    Some(accessor) => self.read_accessor(accessor)?.chunks_exact(16).map(Mat4::from_column_slice).collect(),
+                    None => Vec::new(),
+                };
+                Ok(GltfSkin {
+                    name: skin.get("name").as_str().unwrap_or("").to_string(),
+                    joints: skin.get("joints").members().iter().filter_map(JsonValue::as_usize).collect(),
+                    skeleton: skin.get("skeleton").as_usize(),
+                    inverse_bind_matrices,
+                })
+            })
+            .collect()
+    }
+
+    pub fn animations(&self) -> Result<Vec<GltfAnimation>, String> {
+        self.document.get("animations").members().iter()
+            .map(|animation| {
+                let samplers = animation.get("samplers").members();
+                let channels = animation.get("channels").members().iter()
+                    // Channels without a node target extensions
+                    .filter(|channel| !channel.get("target").get("node").is_null())
+                    .map(|channel| {
+                        let target = channel.get("target");
+                        let path = match target.get("path").as_str() {
+                            Some("translation") => AnimationPath::Translation,
+                            Some("rotation") => AnimationPath::Rotation,
+                            Some("scale") => AnimationPath::Scale,
+                            Some("weights") => AnimationPath::Weights,
+                            path => return Err(format!("Unknown animation path {:?}", path)),
+                        };
+                        let sampler = channel.get("sampler").as_usize()
+                            .and_then(|sampler| samplers.get(sampler))
+                            .ok_or_else(|| "Animation channel without sampler".to_string())?;
+                        let interpolation = match sampler.get("interpolation").as_str() {
+                            Some("STEP") => Interpolation::Step,
+                            Some("CUBICSPLINE") => Interpolation::CubicSpline,
+                            _ => Interpolation::Linear,
+                        };
+                        let input = sampler.get("input").as_usize()
+                            .ok_or_else(|| "Animation sampler without input".to_string())?;
+                        let output = sampler.get("output").as_usize()
+                            .ok_or_else(|| "Animation sampler without output".to_string())?;
+
+                        Ok(AnimationChannel {
+                            node: target.get("node").as_usize().unwrap_or(0),
+                            path,
+                            interpolation,
+                            times: self.read_accessor(input)?,
+                            values: self.read_accessor(output)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+
+                Ok(GltfAnimation {
+                    name: animation.get("name").as_str().unwrap_or("").to_string(),
+                    channels,
+                })
+            })
+            .collect()
+    }
+}
+
+// Builds the ModelNodes, loading the meshes and the materials once
+struct NodeBuilder<'a> {
+    file: &'a GltfFile,
+    id: &'a str,
+    server: &'a AssetServer,
+    // The mesh uploads are left to the server
+    deferred: bool,
+    materials: HashMap<Option<usize>, Arc<Material>>,
+    node_paths: Vec<Option<Vec<usize>>>,
+    visited: HashSet<usize>,
+}
+
+impl<'a> NodeBuilder<'a> {
+    fn mesh_renderer(&mut self, mesh: usize, primitive: usize) -> Result<MeshRenderer, String> {
+        let mesh_id = format!("{}#mesh{}/{}", self.id, mesh, primitive);
+        let data = self.file.primitive(mesh, primitive)?;
+        let material_index = data.material;
+
+        let mesh = self.server.mesh(&mesh_id, MeshData {
+            positions: data.positions,
+            texcoords: data.texcoords,
+            normals: data.normals,
+            tangents: data.tangents,
+            indices: data.indices,
+        }, self.deferred);
+
+        let material = match self.materials.get(&material_index) {
+            Some(material) => material.clone(),
+            None => {
+                let shader_data = match material_index {
+                    Some(material) => self.file.material(material, self.id, self.server)?,
+                    None => PbrData::default(),
+                };
+                let material = Arc::new(Material { shader_data: Box::new(shader_data) });
+                self.materials.insert(material_index, material.clone());
+                material
+            }
+        };
+
+        Ok(MeshRenderer { mesh, material })
+    }
+
+    fn node(&mut self, index: usize, path: Vec<usize>) -> Result<ModelNode, String> {
+        if !self.visited.insert(index) {
+            return Err(format!("Node {} appears twice in the hierarchy", index));
+        }
+        let node = self.file.element("nodes", index)?;
+        if let Some(node_path) = self.node_paths.get_mut(index) {
+            *node_path = Some(path.clone());
+        }
+
+        let mut model_node = ModelNode {
+            name: node.get("name").as_str().map_or_else(|| format!("node{}", index), str::to_string),
+            transform: node_transform(node),
+            mesh_renderer: None,
+            children: Vec::new(),
+        };
+
+        // Extra primitives become children of the node
+        if let Some(mesh) = node.get("mesh").as_usize() {
+            let primitive_count = self.file.element("meshes", mesh)?.get("primitives").members().len();
+            for primitive in 0..primitive_count {
+                let mesh_renderer = self.mesh_renderer(mesh, primitive)?;
+                if primitive == 0 {
+                    model_node.mesh_renderer = Some(mesh_renderer);
+                } else {
+                    model_node.children.push(ModelNode {
+                        name: format!("{}.{}", model_node.name, primitive),
+                        transform: Transform::default(),
+                        mesh_renderer: Some(mesh_renderer),
+                        children: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        for child in node.get("children").members().iter().filter_map(JsonValue::as_usize) {
+            let mut child_path = path.clone();
+            child_path.push(model_node.children.len());
+            let child = self.node(child, child_path)?;
+            model_node.children.push(child);
+        }
+        Ok(model_node)
+    }
+}
+
+impl ModelLoader {
+    // .gltf with external or embedded buffers, or .glb. The materials are PbrData.
+    // Missing textures are replaced by the fallback texture
+    pub fn load_gltf(&self, filename: &str) -> Result<GltfModel, AssetError> {
+        let file = GltfFile::open(Path::new(filename))?;
+        Self::build_gltf(filename, &file, CONTAINER.get_local::<AssetServer>(), false)
+            .map_err(|message| AssetError::Gltf { path: filename.to_string(), message })
+    }
+
+    // The mesh uploads are left to the server when deferred
+    pub(crate) fn build_gltf(filename: &str, file: &GltfFile, server: &AssetServer, deferred: bool) -> Result<GltfModel, String> {
+        let path = Path::new(filename);
+
+        let mut builder = NodeBuilder {
+            file,
+            id: filename,
+            server,
+            deferred,
+            materials: HashMap::new(),
+            node_paths: vec![None; file.document.get("nodes").members().len()],
+            visited: HashSet::new(),
+        };
+
+        let mut children = Vec::new();
+        for node in file.scene_roots() {
+            let child = builder.node(node, vec![children.len()])?;
+            children.push(child);
+        }
+
+        Ok(GltfModel {
+            root: ModelNode {
+                name: path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+                transform: Transform::default(),
+                mesh_renderer: None,
+                children,
+            },
+            node_paths: builder.node_paths,
+            skins: file.skins()?,
+            animations: file.animations()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert_eq!(base64_decode("Zg==").unwrap(), b"f");
+        assert_eq!(base64_decode("Zm8=").unwrap(), b"fo");
+        assert_eq!(base64_decode("Zm9v").unwrap(), b"foo");
+        assert_eq!(base64_decode("Zm9v\nYmFy").unwrap(), b"foobar");
+        // Standard and URL safe alphabets
+        assert_eq!(base64_decode("+/8=").unwrap(), vec![0xFB, 0xFF]);
+        assert_eq!(base64_decode("-_8=").unwrap(), vec![0xFB, 0xFF]);
+        assert!(base64_decode("Zm9v!").is_err());
+    }
+
+    #[test]
+    fn zero_scale_matrices_dont_give_nan() {
+        let node = JsonValue::parse(r#"{ "matrix": [0, 0, 0, 0,  0, 2, 0, 0,  0, 0, 0, 0,  1, 2, 3, 1] }"#).unwrap();
+        let transform = node_transform(&node);
+        assert_eq!(transform.position, vec3(1.0, 2.0, 3.0));
+        assert_eq!(transform.scale, vec3(0.0, 2.0, 0.0));
+        assert!(transform.rotation.coords.iter().all(|value| value.is_finite()));
+        assert!(transform.rotation.angle().abs() < 1e-5);
+    }
+
+    #[test]
+    fn reads_an_embedded_buffer() {
+        let file = GltfFile::open(Path::new("models/gltf/triangle.gltf")).unwrap();
+        assert_eq!(file.scene_roots(), vec![0]);
+
+        let primitive = file.primitive(0, 0).unwrap();
+        assert_eq!(primitive.positions, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        assert_eq!(primitive.indices, vec![0, 1, 2]);
+        // Computed, the file has no normals
+        assert_eq!(primitive.normals.chunks(3).collect::<Vec<_>>(), vec![&[0.0, 0.0, 1.0]; 3]);
+        assert_eq!(primitive.texcoords, vec![0.0; 6]);
+        assert_eq!(primitive.tangents, None);
+        assert_eq!(primitive.material, None);
+
+        assert!(file.primitive(0, 1).is_err());
+        assert!(file.primitive(1, 0).is_err());
+        assert!(file.skins().unwrap().is_empty());
+        assert!(file.animations().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_a_glb_file() {
+        let file = GltfFile::open(Path::new("models/gltf/textured_cube.glb")).unwrap();
+        assert_eq!(file.scene_roots(), vec![0]);
+
+        let primitive = file.primitive(0, 0).unwrap();
+        assert_eq!(primitive.positions.len(), 24 * 3);
+        assert_eq!(primitive.normals.len(), 24 * 3);
+        assert_eq!(primitive.texcoords.len(), 24 * 2);
+        assert!(primitive.texcoords.iter().all(|&t| t >= 0.0 && t <= 1.0));
+        assert_eq!(primitive.indices.len(), 36);
+        assert_eq!(primitive.material, Some(0));
+    }
+
+    #[test]
+    fn reads_skins_and_animations() {
+        let file = GltfFile::open(Path::new("models/gltf/simple_skin.gltf")).unwrap();
+        assert_eq!(file.scene_roots(), vec![0, 1]);
+        assert_eq!(file.primitive(0, 0).unwrap().indices, vec![0, 1, 3, 0, 3, 2, 2, 3, 5, 2, 5, 4]);
+
+        let skins = file.skins().unwrap();
+        assert_eq!(skins.len(), 1);
+        assert_eq!(skins[0].name, "arm");
+        assert_eq!(skins[0].joints, vec![1, 2]);
+        assert_eq!(skins[0].skeleton, Some(1));
+        assert_eq!(skins[0].inverse_bind_matrices.len(), 2);
+        assert_eq!(skins[0].inverse_bind_matrices[0], Mat4::identity());
+        assert_eq!(skins[0].inverse_bind_matrices[1], nalgebra_glm::translation(&vec3(0.0, -0.5, 0.0)));
+
+        let animations = file.animations().unwrap();
+        assert_eq!(animations.len(), 1);
+        assert_eq!(animations[0].name, "bend");
+        let channel = &animations[0].channels[0];
+        assert_eq!(channel.node, 2);
+        assert_eq!(channel.path, AnimationPath::Rotation);
+        assert_eq!(channel.interpolation, Interpolation::Linear);
+        assert_eq!(channel.times, vec![0.0, 0.5, 1.0]);
+        assert_eq!(channel.values.len(), 3 * 4);
+        assert_eq!(channel.values[4..8], [0.0, 0.0, 0.382_683_43, 0.923_879_5]);
+    }
+
+    #[test]
+    fn roots_without_scenes_are_the_nodes_without_parent() {
+        let json = r#"{ "nodes": [{ "children": [2] }, {}, { "children": [3] }, {}] }"#;
+        let file = GltfFile::parse(Path::new("test.gltf"), json.as_bytes().to_vec()).unwrap();
+        assert_eq!(file.scene_roots(), vec![0, 1]);
+    }
+
+    #[test]
+    fn missing_files_and_buffers_are_errors() {
+        match GltfFile::open(Path::new("models/gltf/missing.gltf")) {
+            Err(AssetError::Io { path, .. }) => assert_eq!(path, "models/gltf/missing.gltf"),
+            _ => panic!("Expected an IO error"),
+        }
+        match GltfFile::parse(Path::new("test.gltf"), b"{ \"buffers\": [{}] }".to_vec()) {
+            Err(message) => assert_eq!(message, "Buffer without data"),
+            Ok(_) => panic!("Expected an error"),
+        }
+    }
+}
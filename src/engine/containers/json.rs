@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+
+// Deepest nesting of arrays and objects, deeper documents are rejected instead of overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+// Minimal JSON document, enough for the glTF files
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(HashMap<String, JsonValue>),
+}
+
+impl JsonValue {
+    pub fn parse(source: &str) -> Result<JsonValue, String> {
+        let mut parser = Parser { bytes: source.as_bytes(), position: 0, depth: 0 };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(parser.error("Trailing characters"));
+        }
+        Ok(value)
+    }
+
+    // Null when the key is missing or this isn't an object
+    pub fn get(&self, key: &str) -> &JsonValue {
+        match self {
+            JsonValue::Object(members) => members.get(key).unwrap_or(&JsonValue::Null),
+            _ => &JsonValue::Null,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == JsonValue::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        self.as_f64().map(|value| value as f32)
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64()
+            .filter(|value| *value >= 0.0 && value.fract() == 0.0)
+            .map(|value| value as usize)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    // Empty when this isn't an array
+    pub fn members(&self) -> &[JsonValue] {
+        match self {
+            JsonValue::Array(values) => values,
+            _ => &[],
+        }
+    }
+
+    // Array of numbers, None if any element isn't a number
+    pub fn as_f32_vec(&self) -> Option<Vec<f32>> {
+        match self {
+            JsonValue::Array(values) => values.iter().map(JsonValue::as_f32).collect(),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    // Arrays and objects being parsed
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.position)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", byte as char)))
+        }
+    }
+
+    fn expect_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, String> {
+        if self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("Invalid literal"))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') | Some(b'[') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error("Nested too deeply"));
+                }
+                self.depth += 1;
+                let value = if self.peek() == Some(b'{') { self.parse_object() } else { self.parse_array() };
+                self.depth -= 1;
+                value
+            }
+            Some(b'"') => self.parse_string().map(JsonValue::String),
+            Some(b't') => self.expect_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.expect_literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.expect_literal("null", JsonValue::Null),
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end")),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, String> {
+        self.expect(b'{')?;
+        let mut members = HashMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            let value = self.parse_value()?;
+            members.insert(key, value);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, String> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(values));
+        }
+
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Array(values));
+                }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, String> {
+        let start = self.position;
+        while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e') | Some(b'E') | Some(b'0'..=b'9') = self.peek() {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|number| number.parse::<f64>().ok())
+            .map(JsonValue::Number)
+            .ok_or_else(|| self.error("Invalid number"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let digits = self.bytes.get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("Invalid unicode escape"))?;
+        self.position += 4;
+        Ok(digits)
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self.peek().ok_or_else(|| self.error("Unterminated string"))?;
+            self.position += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.peek().ok_or_else(|| self.error("Unterminated string"))?;
+                    self.position += 1;
+                    let unescaped = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            // Surrogate pair
+                            if (0xD800..0xDC00).contains(&code) && self.bytes[self.position..].starts_with(b"\\u") {
+                                self.position += 2;
+                                let low = self.parse_hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            std::char::from_u32(code).unwrap_or('\u{FFFD}')
+                        }
+                        _ => return Err(self.error("Invalid escape")),
+                    };
+                    let mut buffer = [0u8; 4];
+                    bytes.extend_from_slice(unescaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("Invalid UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(JsonValue::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(JsonValue::parse(&nested(MAX_DEPTH + 1)).unwrap_err().starts_with("Nested too deeply"));
+        // Would overflow the stack without the limit
+        assert!(JsonValue::parse(&"{\"a\":".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn parses_nested_values() {
+        let value = JsonValue::parse(r#" { "a": [1, -2.5e1, true, null], "b": { "c": "d" }, "e": [] } "#).unwrap();
+        assert_eq!(value.get("a").as_f32_vec(), None);
+        assert_eq!(value.get("a").members()[0].as_usize(), Some(1));
+        assert_eq!(value.get("a").members()[1].as_f64(), Some(-25.0));
+        assert_eq!(value.get("a").members()[2].as_bool(), Some(true));
+        assert!(value.get("a").members()[3].is_null());
+        assert_eq!(value.get("b").get("c").as_str(), Some("d"));
+        assert!(value.get("e").members().is_empty());
+        assert!(value.get("missing").get("c").is_null());
+    }
+
+    #[test]
+    fn unescapes_strings() {
+        let value = JsonValue::parse(r#""\"\\\/\b\f\n\r\té""#).unwrap();
+        assert_eq!(value.as_str(), Some("\"\\/\u{8}\u{c}\n\r\t\u{e9}"));
+        assert!(JsonValue::parse(r#""\x""#).is_err());
+        assert!(JsonValue::parse(r#""\u12""#).is_err());
+        assert!(JsonValue::parse(r#""unterminated"#).is_err());
+    }
+
+    #[test]
+    fn joins_surrogate_pairs() {
+        assert_eq!(JsonValue::parse(r#""\ud83d\ude00""#).unwrap().as_str(), Some("\u{1F600}"));
+        // A lone surrogate isn't a char
+        assert_eq!(JsonValue::parse(r#""\ud83d""#).unwrap().as_str(), Some("\u{FFFD}"));
+    }
+
+    #[test]
+    fn rejects_trailing_characters() {
+        assert!(JsonValue::parse("{} {}").is_err());
+        assert!(JsonValue::parse("[1, 2] x").is_err());
+        assert!(JsonValue::parse("1 ").is_ok());
+        assert!(JsonValue::parse("[1, 2,]").is_err());
+        assert!(JsonValue::parse(r#"{"a" 1}"#).is_err());
+        assert!(JsonValue::parse("").is_err());
+    }
+}
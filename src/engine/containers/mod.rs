@@ -1,2 +1,15 @@
 mod global_instances;
-pub use global_instances::*;
\ No newline at end of file
+mod tangents;
+mod model;
+mod json;
+mod gltf;
+mod asset_error;
+mod fallback;
+mod asset_server;
+pub use global_instances::*;
+pub use tangents::*;
+pub use model::*;
+pub use json::*;
+pub use gltf::*;
+pub use asset_error::*;
+pub use asset_server::*;
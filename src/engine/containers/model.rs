@@ -0,0 +1,30 @@
+use crate::ecs::components::*;
+use specs::prelude::*;
+
+// Hierarchy of a loaded model file. A node without a mesh only groups its children.
+pub struct ModelNode {
+    pub name: String,
+    // Relative to the parent node
+    pub transform: Transform,
+    pub mesh_renderer: Option<MeshRenderer>,
+    pub children: Vec<ModelNode>,
+}
+
+impl ModelNode {
+    // Creates an entity for every node, parented like the nodes. Returns the root's entity.
+    pub fn spawn(&self, world: &mut World, parent: Option<Entity>) -> Entity {
+        let mut builder = world.create_entity().with(self.transform.clone());
+        if let Some(mesh_renderer) = &self.mesh_renderer {
+            builder = builder.with(mesh_renderer.clone());
+        }
+        if let Some(parent) = parent {
+            builder = builder.with(Parent { entity: parent });
+        }
+        let entity = builder.build();
+
+        for child in &self.children {
+            child.spawn(world, Some(entity));
+        }
+        entity
+    }
+}
@@ -0,0 +1,80 @@
+use nalgebra_glm::{Vec2, Vec3, vec2, vec3};
+
+fn vec3_at(data: &[f32], index: usize) -> Vec3 {
+    vec3(data[3 * index], data[3 * index + 1], data[3 * index + 2])
+}
+
+fn vec2_at(data: &[f32], index: usize) -> Vec2 {
+    vec2(data[2 * index], data[2 * index + 1])
+}
+
+// Angle between the two edges leaving a corner of a triangle
+fn corner_angle(corner: &Vec3, a: &Vec3, b: &Vec3) -> f32 {
+    let (ea, eb) = (a - corner, b - corner);
+    let lengths = ea.norm() * eb.norm();
+    if lengths <= std::f32::EPSILON {
+        return 0.0;
+    }
+    (ea.dot(&eb) / lengths).max(-1.0).min(1.0).acos()
+}
+
+// Any unit vector perpendicular to the normal
+fn perpendicular(normal: &Vec3) -> Vec3 {
+    let axis = if normal.x.abs() < 0.9 { vec3(1.0, 0.0, 0.0) } else { vec3(0.0, 1.0, 0.0) };
+    (axis - normal * normal.dot(&axis)).normalize()
+}
+
+// Per vertex tangents following the MikkTSpace conventions: the triangles' tangents are
+// weighted by the angle of each corner, orthogonalized against the normal, and the
+// handedness of the bitangent is stored in w. The shaders compute the bitangent as
+// cross(normal, tangent.xyz) * tangent.w.
+// Returns 4 floats per vertex, an arbitrary frame when there are no texture coordinates.
+pub fn compute_tangents(positions: &[f32], normals: &[f32], texcoords: &[f32], indices: &[u32]) -> Vec<f32> {
+    let vertex_count = positions.len() / 3;
+    let has_texcoords = texcoords.len() / 2 == vertex_count;
+    let has_normals = normals.len() / 3 == vertex_count;
+
+    let mut tangents = vec![Vec3::zeros(); vertex_count];
+    let mut bitangents = vec![Vec3::zeros(); vertex_count];
+
+    if has_texcoords {
+        for triangle in indices.chunks_exact(3) {
+            let corners = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+            let p = [vec3_at(positions, corners[0]), vec3_at(positions, corners[1]), vec3_at(positions, corners[2])];
+            let uv = [vec2_at(texcoords, corners[0]), vec2_at(texcoords, corners[1]), vec2_at(texcoords, corners[2])];
+
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (duv1, duv2) = (uv[1] - uv[0], uv[2] - uv[0]);
+            let det = duv1.x * duv2.y - duv2.x * duv1.y;
+            if det.abs() <= std::f32::EPSILON {
+                continue;
+            }
+
+            let tangent = (e1 * duv2.y - e2 * duv1.y) / det;
+            let bitangent = (e2 * duv1.x - e1 * duv2.x) / det;
+
+            for i in 0..3 {
+                let weight = corner_angle(&p[i], &p[(i + 1) % 3], &p[(i + 2) % 3]);
+                tangents[corners[i]] += tangent * weight;
+                bitangents[corners[i]] += bitangent * weight;
+            }
+        }
+    }
+
+    let mut result = Vec::with_capacity(4 * vertex_count);
+    for vertex in 0..vertex_count {
+        let normal = if has_normals { vec3_at(normals, vertex).normalize() } else { vec3(0.0, 0.0, 1.0) };
+
+        // Gram-Schmidt
+        let tangent = tangents[vertex] - normal * normal.dot(&tangents[vertex]);
+        let tangent = if tangent.norm() > std::f32::EPSILON {
+            tangent.normalize()
+        } else {
+            perpendicular(&normal)
+        };
+
+        let handedness = if normal.cross(&tangent).dot(&bitangents[vertex]) < 0.0 { -1.0 } else { 1.0 };
+        result.extend_from_slice(&[tangent.x, tangent.y, tangent.z, handedness]);
+    }
+    result
+}
@@ -1,7 +1,7 @@
 use specs::prelude::*;
-use nalgebra_glm::{Vec3, vec3, Mat4, Mat3};
+use nalgebra_glm::{Vec2, Vec3, vec3, vec4, Mat4, Mat3};
 use crate::shaders::*;
-use nalgebra::{Matrix4, Point3, Point, Vector3};
+use nalgebra::{Matrix4, Point3, Point, Vector3, UnitQuaternion, Unit};
 use crate::gl_wrapper::vao::VAO;
 use std::sync::Arc;
 use nphysics3d::material::BasicMaterial;
@@ -14,15 +14,19 @@ use crate::gl_wrapper::texture_2d::Texture2D;
 use crate::gl_wrapper::rbo::RBO;
 use crate::gl_wrapper::texture_cube_map::TextureCubeMap;
 use crate::gl_wrapper::TextureFormat;
+use crate::containers::{Handle, TextureCache, CONTAINER};
 
 // TODO implement Default trait to all the components
 
-#[derive(Debug)]
+// Position, rotation and scale are relative to the Parent if there is one.
+// With the identity rotation, the entity looks towards -z with y up, like the GL camera.
+#[derive(Debug, Clone)]
 pub struct Transform {
     pub position: Vec3,
-    pub rotation: Vec3,
+    pub rotation: UnitQuaternion<f32>,
     pub scale: Vec3,
 
+    // World matrix, computed by the TransformSystem
     pub model_matrix: Mat4
 }
 
@@ -34,7 +38,7 @@ impl Default for Transform {
     fn default() -> Self {
         Transform {
             position: vec3(0.0, 0.0, 0.0),
-            rotation: vec3(0.0, 0.0, 0.0),
+            rotation: UnitQuaternion::identity(),
             scale: vec3(1.0, 1.0, 1.0),
             model_matrix: Matrix4::identity()
         }
@@ -42,21 +46,107 @@ impl Default for Transform {
 }
 
 impl Transform {
+    pub fn from_position(position: Vec3) -> Self {
+        Transform {
+            position,
+            ..Transform::default()
+        }
+    }
+
+    // Angles in radians around x, y and z, applied in this order
+    pub fn from_euler_angles(roll: f32, pitch: f32, yaw: f32) -> Self {
+        Transform {
+            rotation: UnitQuaternion::from_euler_angles(roll, pitch, yaw),
+            ..Transform::default()
+        }
+    }
+
+    pub fn from_position_euler_angles(position: Vec3, roll: f32, pitch: f32, yaw: f32) -> Self {
+        Transform {
+            position,
+            ..Transform::from_euler_angles(roll, pitch, yaw)
+        }
+    }
+
+    // Roll, pitch, yaw
+    pub fn euler_angles(&self) -> (f32, f32, f32) {
+        self.rotation.euler_angles()
+    }
+
+    pub fn set_euler_angles(&mut self, roll: f32, pitch: f32, yaw: f32) {
+        self.rotation = UnitQuaternion::from_euler_angles(roll, pitch, yaw);
+    }
+
+    pub fn local_matrix(&self) -> Mat4 {
+        let translate_matrix = Matrix4::new_translation(&self.position);
+        let rotate_matrix = self.rotation.to_homogeneous();
+        let scale_matrix: Mat4 = Matrix4::new_nonuniform_scaling(&self.scale);
+        translate_matrix * rotate_matrix * scale_matrix
+    }
+
     pub fn forward(&self) -> Vec3 {
-        vec3(
-            self.rotation.x.cos() * self.rotation.y.cos(),
-            self.rotation.x.sin(),
-            self.rotation.x.cos() * self.rotation.y.sin(),
-        )
+        self.rotation * -Vector3::z()
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * Vector3::x()
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.rotation * Vector3::y()
+    }
+
+    // Turns the entity towards the target, keeping its up vector as close as possible to `up`
+    pub fn look_at(&mut self, target: &Vec3, up: &Vec3) {
+        let direction = target - self.position;
+        if direction.norm_squared() > 0.0 {
+            self.rotation = UnitQuaternion::face_towards(&-direction, up);
+        }
+    }
+
+    // Rotates the position and the orientation around the axis going through the point
+    pub fn rotate_around(&mut self, point: &Vec3, axis: &Unit<Vector3<f32>>, angle: f32) {
+        let rotation = UnitQuaternion::from_axis_angle(axis, angle);
+        self.position = point + rotation * (self.position - point);
+        self.rotation = rotation * self.rotation;
+    }
+
+    // Rotates the orientation around an axis of the parent's space
+    pub fn rotate(&mut self, axis: &Unit<Vector3<f32>>, angle: f32) {
+        self.rotation = UnitQuaternion::from_axis_angle(axis, angle) * self.rotation;
     }
 }
 
+// Makes the Transform of the entity relative to the one of another entity.
+// Cycles are refused, and the children of a deleted entity become roots.
+#[derive(Copy, Clone, Debug)]
+pub struct Parent {
+    pub entity: Entity
+}
+
+impl Component for Parent {
+    type Storage = FlaggedStorage<Self>;
+}
+
+// Direct children of an entity, maintained by the TransformSystem from the Parent components
+#[derive(Component, Debug, Default)]
+pub struct Children {
+    pub entities: Vec<Entity>
+}
+
 #[derive(Component, Clone)]
 pub struct MeshRenderer {
-    pub mesh: Arc<Mesh>,
+    pub mesh: Handle<Mesh>,
     pub material: Arc<Material>
 }
 
+impl MeshRenderer {
+    // The fallback cube is drawn with the material until the mesh is loaded, and when it failed
+    pub fn current_mesh(&self) -> Arc<Mesh> {
+        self.mesh.get().unwrap_or_else(|| CONTAINER.get_local::<TextureCache>().fallback_mesh())
+    }
+}
+
 pub struct Material {
     pub shader_data: Box<dyn ShaderData>,
 }
@@ -67,7 +157,8 @@ pub struct Mesh {
     pub(crate) positions: Vec<f32>,
     pub(crate) indices: Vec<u32>,
     pub(crate) normals: Vec<f32>,
-    pub(crate) texcoords: Vec<f32>
+    pub(crate) texcoords: Vec<f32>,
+    pub(crate) tangents: Vec<f32>
 }
 
 #[derive(Debug)]
@@ -171,28 +262,85 @@ impl Camera {
             fb: FBO::new(color_texture, DepthStencilTarget::RBO(depth_stencil_rb))
         }
     }
+
+    pub fn view_matrix(transform: &Transform) -> Mat4 {
+        nalgebra_glm::look_at(&transform.position, &(transform.position + transform.forward()), &transform.up())
+    }
+
+    pub fn projection_matrix(&self) -> Mat4 {
+        self.projection_matrix_range(self.near_plane, self.far_plane)
+    }
+
+    // Same projection, between other planes. Used to split the view in shadow cascades.
+    pub fn projection_matrix_range(&self, near_plane: f32, far_plane: f32) -> Mat4 {
+        match self.projection {
+            Projection::Orthographic(size) => {
+                nalgebra_glm::ortho(-self.aspect_ratio * size, self.aspect_ratio * size, -size, size, near_plane, far_plane)
+            }
+            Projection::Perspective(fov) => {
+                nalgebra_glm::perspective(self.aspect_ratio, fov, near_plane, far_plane)
+            }
+        }
+    }
+
+    // Ray going through the cursor, from the near plane to the far plane.
+    // The cursor position is in pixels from the top left corner of the screen.
+    // Returns the origin and the normalized direction of the ray.
+    pub fn screen_to_world_ray(&self, transform: &Transform, cursor: &Vec2, screen_size: &Vec2) -> (Vec3, Vec3) {
+        let ndc_x = 2.0 * cursor.x / screen_size.x - 1.0;
+        let ndc_y = 1.0 - 2.0 * cursor.y / screen_size.y;
+
+        let inverse = (self.projection_matrix() * Self::view_matrix(transform))
+            .try_inverse()
+            .unwrap_or_else(Mat4::identity);
+        let near = inverse * vec4(ndc_x, ndc_y, -1.0, 1.0);
+        let far = inverse * vec4(ndc_x, ndc_y, 1.0, 1.0);
+        let near = near.xyz() / near.w;
+        let far = far.xyz() / far.w;
+
+        (near, (far - near).normalize())
+    }
 }
 
+// Lights are gathered every frame by the MeshRendererSystem, see shaders::lights
+
 #[derive(Component, Debug)]
 pub struct DirLight {
     pub color: Vec3,
+    // Distance from the camera covered by the shadow cascades
     pub range: f32,
     pub intensity: f32,
+    // In world space, from the light towards the scene
     pub direction: Vec3,
+    // Only the first shadow casting DirLight gets the cascades
+    pub casts_shadows: bool,
+    // Depth offset against shadow acne, scaled up on surfaces facing away from the light
+    pub shadow_bias: f32,
 }
 
+// Lights up to `range` units around the position of its Transform
 #[derive(Component, Debug)]
 pub struct PointLight {
     pub color: Vec3,
     pub range: f32,
-    pub intensity: f32
+    pub intensity: f32,
+    // Up to shaders::shadows::MAX_POINT_SHADOWS point lights cast shadows
+    pub casts_shadows: bool,
+    pub shadow_bias: f32,
 }
 
+// Points towards the forward direction of its Transform
 #[derive(Component, Debug)]
 pub struct Spotlight {
     pub color: Vec3,
     pub range: f32,
-    pub intensity: f32
+    pub intensity: f32,
+    // Half angles of the cone in radians, the light fades out between the two
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    // Up to shaders::shadows::MAX_SPOT_SHADOWS spotlights cast shadows
+    pub casts_shadows: bool,
+    pub shadow_bias: f32,
 }
 
 #[derive(Component, Default)]
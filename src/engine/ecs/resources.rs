@@ -1,7 +1,7 @@
 use specs::Entity;
-use std::collections::{VecDeque, HashMap};
-use nalgebra_glm::{Vec2, vec2};
-use glfw::{Key, Action};
+use std::collections::{VecDeque, HashMap, HashSet};
+use nalgebra_glm::{Vec2, Vec3, vec2, vec3};
+use glfw::{Key, Action, MouseButton};
 use nphysics3d::object::{BodyHandle, ColliderHandle};
 use glfw::ffi::glfwGetTime;
 
@@ -25,6 +25,13 @@ pub struct InputCache {
     pub cursor_rel_pos: Vec2,
 
     pub key_states: HashMap<Key, Action>,
+    pub mouse_button_states: HashMap<MouseButton, Action>,
+    // Buttons pressed since the last run of the InputSystem
+    pub mouse_button_presses: HashSet<MouseButton>,
+    // In pixels
+    pub window_size: Vec2,
+    // Tab switches between the visible cursor, to pick the blocks, and the captured one
+    pub cursor_visible: bool,
 }
 
 impl Default for InputCache {
@@ -33,6 +40,10 @@ impl Default for InputCache {
             last_cursor_pos: vec2(0.0, 0.0),
             cursor_rel_pos: vec2(0.0, 0.0),
             key_states: HashMap::default(),
+            mouse_button_states: HashMap::default(),
+            mouse_button_presses: HashSet::default(),
+            window_size: vec2(1920.0, 1080.0),
+            cursor_visible: false,
         }
     }
 }
@@ -44,13 +55,38 @@ impl InputCache {
             Some(action) => *action == Action::Press || *action == Action::Repeat
         }
     }
+
+    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        match self.mouse_button_states.get(&button) {
+            None => false,
+            Some(action) => *action == Action::Press || *action == Action::Repeat
+        }
+    }
+
+    // Pressed this frame, unlike is_mouse_button_pressed which is true while the button is held
+    pub fn was_mouse_button_clicked(&self, button: MouseButton) -> bool {
+        self.mouse_button_presses.contains(&button)
+    }
+}
+
+// Added to every lit fragment, scaled by its diffuse color
+pub struct AmbientLight {
+    pub color: Vec3
+}
+
+impl Default for AmbientLight {
+    fn default() -> Self {
+        AmbientLight { color: vec3(0.1, 0.1, 0.1) }
+    }
 }
 
 #[derive(Default)]
 pub struct PhysicsWorld {
     pub world: nphysics3d::world::World<f32>,
     pub body_handles: HashMap<u32, BodyHandle>,
-    pub collider_handles: HashMap<u32, ColliderHandle>
+    pub collider_handles: HashMap<u32, ColliderHandle>,
+    // Static colliders of the voxel chunks, see VoxelWorld::sync_colliders
+    pub chunk_collider_handles: HashMap<(i32, i32), ColliderHandle>,
 }
 
 #[derive(Default)]
@@ -68,4 +104,9 @@ impl Time {
         self.dt = now - self.prev;
         self.prev = now;
     }
+
+    // Seconds since the start, at the last tick
+    pub fn elapsed(&self) -> f64 {
+        self.prev
+    }
 }
\ No newline at end of file
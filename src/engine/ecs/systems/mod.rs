@@ -1,14 +1,18 @@
 mod physics;
+mod voxel;
 
 pub use physics::*;
+pub use voxel::*;
 
 use specs::prelude::*;
 use specs::{System, WriteStorage, ReadStorage};
+use specs::world::Index;
+use std::collections::HashMap;
 use crate::ecs::components::*;
 use crate::ecs::resources::*;
-use nalgebra_glm::{vec2, Mat4, vec3};
-use nalgebra::{Vector3, Matrix4};
-use glfw::{Key, WindowEvent};
+use nalgebra_glm::{vec2, Mat4, Vec3};
+use nalgebra::Matrix4;
+use glfw::{Key, WindowEvent, Action};
 use ncollide3d::shape::{ShapeHandle, Cuboid};
 use nphysics3d::object::ColliderDesc;
 use nphysics3d::material::MaterialHandle;
@@ -16,9 +20,13 @@ use glfw::ffi::glfwGetTime;
 use crate::shaders::outline::OutlineData;
 use crate::shaders::ShaderData;
 use crate::gl_wrapper::fbo::FBO;
-use crate::containers::CONTAINER;
+use crate::containers::{AssetServer, CONTAINER};
 use crate::shapes::PredefinedShapes;
 use crate::shaders::cube_map::CubeMapShader;
+use crate::shaders::diffuse::DiffuseShader;
+use crate::shaders::lights::LightBuffers;
+use crate::shaders::shadows::ShadowMaps;
+use crate::shaders::pbr::{PbrShader, EnvironmentLighting};
 use crate::gl_wrapper::texture_cube_map::TextureCubeMap;
 use crate::gl_wrapper::ubo::{Std140, GlslTypes, UBO, ComputeStd140LayoutSize};
 use std::os::raw::c_void;
@@ -27,13 +35,16 @@ use crate::gl_wrapper::BufferUpdateFrequency;
 struct CameraUBO<'a> {
     pub view: &'a Mat4,
     pub projection: &'a Mat4,
+    // World space
+    pub position: &'a Vec3,
 }
 
 impl<'a> Std140 for CameraUBO<'a> {
     fn get_std140_layout(&self) -> &'static [GlslTypes] {
         &[
             GlslTypes::Mat4,
-            GlslTypes::Mat4
+            GlslTypes::Mat4,
+            GlslTypes::Vec4
         ]
     }
 
@@ -45,22 +56,67 @@ impl<'a> Std140 for CameraUBO<'a> {
             let buf = buf.cast::<f32>();
             self.view.as_ptr().copy_to_nonoverlapping(buf, 16);
             self.projection.as_ptr().copy_to_nonoverlapping(buf.offset(16), 16);
+            self.position.as_ptr().copy_to_nonoverlapping(buf.offset(32), 3);
             gl_call!(gl::UnmapNamedBuffer(ubo.id));
         }
     }
 }
 
+// Computes the world matrices, a parent before its children. Only the subtrees of the
+// transforms modified and of the entities reparented since the last run are updated.
 pub struct TransformSystem {
-    pub reader_id: ReaderId<ComponentEvent>,
+    pub transforms_reader_id: ReaderId<ComponentEvent>,
+    pub parents_reader_id: ReaderId<ComponentEvent>,
+    // Last known parent of each child, by entity id. The Parent component is gone
+    // by the time its removal is read.
+    pub parents: HashMap<Index, Entity>,
     pub dirty: BitSet,
 }
 
+impl TransformSystem {
+    pub fn new(world: &mut World) -> Self {
+        TransformSystem {
+            transforms_reader_id: world.write_storage::<Transform>().register_reader(),
+            parents_reader_id: world.write_storage::<Parent>().register_reader(),
+            parents: HashMap::new(),
+            dirty: BitSet::new(),
+        }
+    }
+}
+
+// Whether the entity is one of the ancestors of `parent` (or `parent` itself)
+fn creates_cycle(entity: Entity, parent: Entity, parents: &WriteStorage<Parent>) -> bool {
+    let mut current = parent;
+    loop {
+        if current == entity {
+            return true;
+        }
+        match parents.get(current) {
+            Some(next) => current = next.entity,
+            None => return false
+        }
+    }
+}
+
+fn depth(entity: Entity, parents: &WriteStorage<Parent>) -> usize {
+    let mut depth = 0;
+    let mut current = entity;
+    while let Some(parent) = parents.get(current) {
+        depth += 1;
+        current = parent.entity;
+    }
+    depth
+}
+
 impl<'a> System<'a> for TransformSystem {
-    type SystemData = (Entities<'a>, WriteStorage<'a, Transform>);
+    type SystemData = (Entities<'a>,
+                       WriteStorage<'a, Transform>,
+                       WriteStorage<'a, Parent>,
+                       WriteStorage<'a, Children>);
 
-    fn run(&mut self, (_entities, mut transforms): Self::SystemData) {
+    fn run(&mut self, (entities, mut transforms, mut parents, mut children): Self::SystemData) {
         self.dirty.clear();
-        let events = transforms.channel().read(&mut self.reader_id);
+        let events = transforms.channel().read(&mut self.transforms_reader_id);
 
         for event in events {
             match event {
@@ -71,25 +127,101 @@ impl<'a> System<'a> for TransformSystem {
             }
         }
 
-        for (mut transforms, _entity) in (&mut transforms.restrict_mut(), &self.dirty).join() {
-            let mut transform = transforms.get_mut_unchecked();
+        let mut reparented = BitSet::new();
+        for event in parents.channel().read(&mut self.parents_reader_id) {
+            match event {
+                ComponentEvent::Inserted(id)
+                | ComponentEvent::Modified(id)
+                | ComponentEvent::Removed(id) => reparented.add(*id),
+            };
+        }
 
-            transform.model_matrix = {
-                let translate_matrix = Matrix4::new_translation(&transform.position);
+        // The children of deleted entities become roots
+        let orphans: Vec<Entity> = (&entities, &parents).join()
+            .filter(|(_, parent)| !entities.is_alive(parent.entity))
+            .map(|(entity, _)| entity)
+            .collect();
+        for entity in orphans {
+            parents.remove(entity);
+            reparented.add(entity.id());
+        }
 
-                let rotate_matrix = Matrix4::from_euler_angles(
-                    transform.rotation.x,
-                    transform.rotation.y,
-                    transform.rotation.z,
-                );
+        for id in (&reparented).join() {
+            let entity = entities.entity(id);
+            let parent = match parents.get(entity) {
+                Some(parent) if entities.is_alive(entity) => parent.entity,
+                _ => continue
+            };
+            if creates_cycle(entity, parent, &parents) {
+                error!("Entity {:?} can't be a child of {:?}, it is one of its ancestors", entity, parent);
+                parents.remove(entity);
+            }
+        }
 
-                let scale_matrix: Mat4 = Matrix4::new_nonuniform_scaling(&transform.scale);
-                translate_matrix * rotate_matrix * scale_matrix
+        // Update the children lists
+        for id in (&reparented).join() {
+            let entity = entities.entity(id);
+            let new_parent = if entities.is_alive(entity) {
+                parents.get(entity).map(|parent| parent.entity)
+            } else {
+                None
             };
+
+            if let Some(old_parent) = self.parents.remove(&id) {
+                if let Some(old_children) = children.get_mut(old_parent) {
+                    old_children.entities.retain(|child| child.id() != id);
+                }
+            }
+
+            if let Some(new_parent) = new_parent {
+                if children.get(new_parent).is_none() {
+                    children.insert(new_parent, Children::default()).unwrap();
+                }
+                children.get_mut(new_parent).unwrap().entities.push(entity);
+                self.parents.insert(id, new_parent);
+            }
+
+            self.dirty.add(id);
+        }
+
+        // Shallowest first, so that the world matrix of a parent is up to date
+        // before its children are updated
+        let mut roots: Vec<(usize, Entity)> = (&entities, &self.dirty).join()
+            .map(|(entity, _)| (depth(entity, &parents), entity))
+            .collect();
+        roots.sort_by_key(|(depth, _)| *depth);
+
+        let mut updated = BitSet::new();
+        let mut stack = Vec::new();
+        for (_, root) in roots {
+            if updated.contains(root.id()) {
+                continue;
+            }
+
+            stack.push(root);
+            while let Some(entity) = stack.pop() {
+                updated.add(entity.id());
+
+                let parent_matrix = parents.get(entity)
+                    .and_then(|parent| transforms.get(parent.entity))
+                    .map(|parent_transform| parent_transform.model_matrix);
+
+                if let Some(transform) = transforms.get_mut(entity) {
+                    let local_matrix = transform.local_matrix();
+                    transform.model_matrix = match parent_matrix {
+                        Some(parent_matrix) => parent_matrix * local_matrix,
+                        None => local_matrix
+                    };
+                }
+
+                if let Some(entity_children) = children.get(entity) {
+                    stack.extend(entity_children.entities.iter().cloned());
+                }
+            }
         }
 
         // Workaround for unflagging the components
-        transforms.channel().read(&mut self.reader_id);
+        transforms.channel().read(&mut self.transforms_reader_id);
     }
 }
 
@@ -107,9 +239,8 @@ impl<'a> System<'a> for InputSystem {
         let active_camera = active_camera.entity.unwrap();
         let _camera = cameras.get(active_camera).unwrap();
 
+        input_cache.mouse_button_presses.clear();
         while let Some(ref event) = input_event_queue.queue.pop_front() {
-            let transform = transforms.get_mut(active_camera).unwrap();
-
             match event {
                 WindowEvent::CursorPos(x, y) => {
                     let x = *x as f32;
@@ -117,17 +248,23 @@ impl<'a> System<'a> for InputSystem {
                     let current_pos = vec2(x, y);
                     input_cache.cursor_rel_pos = current_pos - input_cache.last_cursor_pos;
                     input_cache.last_cursor_pos = vec2(x, y);
-                    let (x, y) = (input_cache.cursor_rel_pos.x, input_cache.cursor_rel_pos.y);
-//                    println!("x: {} y: {}", x, y);
-
-//                    transform.rotation.y += x * 0.001;
-//                    transform.rotation.x -= y * 0.001;
                 }
 
                 WindowEvent::Key(key, _, action, _) => {
                     input_cache.key_states.insert(*key, *action);
                 }
 
+                WindowEvent::MouseButton(button, action, _) => {
+                    input_cache.mouse_button_states.insert(*button, *action);
+                    if *action == Action::Press {
+                        input_cache.mouse_button_presses.insert(*button);
+                    }
+                }
+
+                WindowEvent::Size(width, height) => {
+                    input_cache.window_size = vec2(*width as f32, *height as f32);
+                }
+
                 _ => {}
             }
         }
@@ -144,12 +281,12 @@ impl<'a> System<'a> for InputSystem {
 
         if input_cache.is_key_pressed(Key::A) {
             let transform = transforms.get_mut(active_camera).unwrap();
-            transform.position -= transform.forward().cross(&Vector3::y()).scale(0.03f32);
+            transform.position -= transform.right().scale(0.03f32);
         }
 
         if input_cache.is_key_pressed(Key::D) {
             let transform = transforms.get_mut(active_camera).unwrap();
-            transform.position += transform.forward().cross(&Vector3::y()).scale(0.03f32);
+            transform.position += transform.right().scale(0.03f32);
         }
 
         if input_cache.is_key_pressed(Key::Q) {
@@ -165,7 +302,10 @@ impl<'a> System<'a> for InputSystem {
 }
 
 pub struct MeshRendererSystem {
-    camera_matrices_ubo: UBO
+    camera_matrices_ubo: UBO,
+    light_buffers: LightBuffers,
+    shadow_maps: ShadowMaps,
+    environment: EnvironmentLighting,
 }
 
 impl Default for MeshRendererSystem {
@@ -173,10 +313,16 @@ impl Default for MeshRendererSystem {
         let camera_matrices_ubo = UBO::new(&[
             GlslTypes::Mat4,
             GlslTypes::Mat4,
+            GlslTypes::Vec4,
         ], BufferUpdateFrequency::Often);
 
         camera_matrices_ubo.bind(0);
-        MeshRendererSystem { camera_matrices_ubo }
+        MeshRendererSystem {
+            camera_matrices_ubo,
+            light_buffers: LightBuffers::default(),
+            shadow_maps: ShadowMaps::default(),
+            environment: EnvironmentLighting::default(),
+        }
     }
 }
 
@@ -188,10 +334,14 @@ impl<'a> System<'a> for MeshRendererSystem {
                        ReadStorage<'a, MeshRenderer>,
                        ReadStorage<'a, Camera>,
                        Read<'a, ActiveCamera>,
+                       ReadStorage<'a, DirLight>,
                        ReadStorage<'a, PointLight>,
-                       ReadStorage<'a, Outliner>);
+                       ReadStorage<'a, Spotlight>,
+                       Read<'a, AmbientLight>,
+                       ReadStorage<'a, Outliner>,
+                       Read<'a, InputCache>);
 
-    fn run(&mut self, (entities, transforms, mesh_renderer, camera, active_camera, point_lights, outliners): Self::SystemData) {
+    fn run(&mut self, (entities, transforms, mesh_renderer, camera, active_camera, dir_lights, point_lights, spotlights, ambient_light, outliners, input_cache): Self::SystemData) {
         let (camera, cam_tr) = match active_camera.entity {
             Some(e) => (
                 camera.get(e).expect("Active camera must have a Camera component"),
@@ -200,37 +350,42 @@ impl<'a> System<'a> for MeshRendererSystem {
             None => return
         };
 
-        let direction = vec3(
-            cam_tr.rotation.x.cos() * cam_tr.rotation.y.cos(),
-            cam_tr.rotation.x.sin(),
-            cam_tr.rotation.x.cos() * cam_tr.rotation.y.sin(),
-        );
-
-        let view_matrix = nalgebra_glm::look_at(&cam_tr.position, &(cam_tr.position + direction), &Vector3::y());
-
-        let projection_matrix = match camera.projection {
-            Projection::Orthographic(size) => {
-                nalgebra_glm::ortho(-camera.aspect_ratio * size, camera.aspect_ratio * size, -size, size, camera.near_plane, camera.far_plane)
-            }
-            Projection::Perspective(fov) => {
-                nalgebra_glm::perspective(camera.aspect_ratio, fov, camera.near_plane, camera.far_plane)
-            }
-        };
+        let view_matrix = Camera::view_matrix(cam_tr);
+        let projection_matrix = camera.projection_matrix();
 
         {
-            let camera_ubo_struct = CameraUBO { view: &view_matrix, projection: &projection_matrix };
+            let camera_ubo_struct = CameraUBO {
+                view: &view_matrix,
+                projection: &projection_matrix,
+                position: &cam_tr.position
+            };
             self.camera_matrices_ubo.update(&camera_ubo_struct);
         }
 
+        // Changes the framebuffer and the viewport, they are reset below
+        self.shadow_maps.render(&entities, &transforms, &mesh_renderer, camera, cam_tr, &dir_lights, &spotlights, &point_lights);
+        self.shadow_maps.bind();
+
+        self.light_buffers.update(&entities, &self.shadow_maps, &transforms, &dir_lights, &point_lights, &spotlights);
+        self.light_buffers.bind();
+        CONTAINER.get_local::<DiffuseShader>().set_ambient_light(&ambient_light.color);
+
+        self.environment.update(&camera.background);
+        self.environment.bind();
+        CONTAINER.get_local::<PbrShader>().set_environment(&self.environment, &ambient_light.color);
+
 
         // Post processing
-        if camera.post_processing_effects.is_empty() {
+        let (width, height) = if camera.post_processing_effects.is_empty() {
             FBO::bind_default();
+            (input_cache.window_size.x as i32, input_cache.window_size.y as i32)
         } else {
             camera.fb.bind();
-        }
+            // The size the camera's framebuffer is allocated with
+            (1920, 1080)
+        };
 
-        gl_call!(gl::Viewport(0, 0, 1920, 1080));
+        gl_call!(gl::Viewport(0, 0, width, height));
         gl_call!(gl::Enable(gl::DEPTH_TEST));
         gl_call!(gl::DepthFunc(gl::LESS));
         gl_call!(gl::Enable(gl::STENCIL_TEST));
@@ -247,11 +402,11 @@ impl<'a> System<'a> for MeshRendererSystem {
             let model_matrix = transform.model_matrix;
 
             let mesh_renderer = mesh_renderer as &MeshRenderer;
-            mesh_renderer.mesh.vao.bind();
+            let mesh = mesh_renderer.current_mesh();
+            mesh.vao.bind();
 
             let shader_data = &mesh_renderer.material.shader_data;
             shader_data.bind_model(&model_matrix);
-            shader_data.bind_lights(&transforms, &point_lights);
 
             // Outline stencil test
             if outliners.get(entity).is_some() {
@@ -264,7 +419,7 @@ impl<'a> System<'a> for MeshRendererSystem {
             }
 
             gl_call!(gl::DrawElements(gl::TRIANGLES,
-                                  mesh_renderer.mesh.indices.len() as i32,
+                                  mesh.indices.len() as i32,
                                   gl::UNSIGNED_INT, std::ptr::null()));
         }
 
@@ -294,22 +449,11 @@ impl<'a> System<'a> for MeshRendererSystem {
 
         for (transform, mesh_renderer, outliner) in (&transforms, &mesh_renderer, &outliners).join() {
             // Calculate scaled model matrix
-            let scaled_model_matrix = {
-                let translate_matrix = Matrix4::new_translation(&transform.position);
-
-                let rotate_matrix = Matrix4::from_euler_angles(
-                    transform.rotation.x,
-                    transform.rotation.y,
-                    transform.rotation.z,
-                );
-
-                let scale_vec = transform.scale * outliner.scale;
-                let scale_matrix: Mat4 = Matrix4::new_nonuniform_scaling(&scale_vec);
-                translate_matrix * rotate_matrix * scale_matrix
-            };
+            let scaled_model_matrix = transform.model_matrix * Matrix4::new_scaling(outliner.scale);
 
             let mesh_renderer = mesh_renderer as &MeshRenderer;
-            mesh_renderer.mesh.vao.bind();
+            let mesh = mesh_renderer.current_mesh();
+            mesh.vao.bind();
 
             let shader_data = OutlineData {
                 color: outliner.color
@@ -317,9 +461,35 @@ impl<'a> System<'a> for MeshRendererSystem {
 
             shader_data.bind_model(&scaled_model_matrix);
             gl_call!(gl::DrawElements(gl::TRIANGLES,
-                                  mesh_renderer.mesh.indices.len() as i32,
+                                  mesh.indices.len() as i32,
                                   gl::UNSIGNED_INT, std::ptr::null()));
         }
+    }
+}
+
+// Uploads the assets decoded by the AssetServer's threads, must run before the rendering
+pub struct AssetUploadSystem;
+
+impl<'a> System<'a> for AssetUploadSystem {
+    type SystemData = ();
+
+    fn run(&mut self, _: Self::SystemData) {
+        CONTAINER.get_local::<AssetServer>().update();
+    }
+}
+
+// Applies the active camera's effects once everything has been drawn into its framebuffer
+pub struct PostProcessingSystem;
+
+impl<'a> System<'a> for PostProcessingSystem {
+    type SystemData = (ReadStorage<'a, Camera>,
+                       Read<'a, ActiveCamera>);
+
+    fn run(&mut self, (cameras, active_camera): Self::SystemData) {
+        let camera = match active_camera.entity.and_then(|entity| cameras.get(entity)) {
+            Some(camera) => camera,
+            None => return
+        };
 
         if !camera.post_processing_effects.is_empty() {
             let mut last_fb = &camera.fb;
@@ -369,7 +539,7 @@ impl<'a> System<'a> for BoxColliderSystem {
             let shape = ShapeHandle::<f32>::new(Cuboid::new(half_size));
             let _collider = ColliderDesc::new(shape)
                 .translation(transform.position)
-                .rotation(transform.rotation)
+                .rotation(transform.rotation.scaled_axis())
                 .material(MaterialHandle::new(box_collider.material))
                 .build(&mut physics_world.world);
         }
@@ -395,4 +565,138 @@ impl<'a> System<'a> for PrintFramerate {
             self.frames = 0;
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::{vec3, vec4};
+
+    fn setup() -> (World, TransformSystem) {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<Parent>();
+        world.register::<Children>();
+        let system = TransformSystem::new(&mut world);
+        (world, system)
+    }
+
+    fn run(world: &mut World, system: &mut TransformSystem) {
+        system.run_now(world);
+        world.maintain();
+    }
+
+    fn spawn(world: &mut World, transform: Transform, parent: Option<Entity>) -> Entity {
+        let builder = world.create_entity().with(transform);
+        match parent {
+            Some(parent) => builder.with(Parent { entity: parent }).build(),
+            None => builder.build(),
+        }
+    }
+
+    fn children_of(world: &World, entity: Entity) -> Vec<Entity> {
+        world.read_storage::<Children>().get(entity)
+            .map(|children| children.entities.clone())
+            .unwrap_or_default()
+    }
+
+    fn world_position(world: &World, entity: Entity) -> Vec3 {
+        let position = world.read_storage::<Transform>().get(entity).unwrap().model_matrix * vec4(0.0, 0.0, 0.0, 1.0);
+        vec3(position.x, position.y, position.z)
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).norm() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn reparenting_moves_the_child() {
+        let (mut world, mut system) = setup();
+        let a = spawn(&mut world, Transform::from_position(vec3(1.0, 0.0, 0.0)), None);
+        let b = spawn(&mut world, Transform::from_position(vec3(0.0, 5.0, 0.0)), None);
+        let child = spawn(&mut world, Transform::from_position(vec3(0.0, 0.0, 1.0)), Some(a));
+        run(&mut world, &mut system);
+        assert_eq!(children_of(&world, a), vec![child]);
+        assert_close(world_position(&world, child), vec3(1.0, 0.0, 1.0));
+
+        world.write_storage::<Parent>().insert(child, Parent { entity: b }).unwrap();
+        run(&mut world, &mut system);
+        assert!(children_of(&world, a).is_empty());
+        assert_eq!(children_of(&world, b), vec![child]);
+        assert_close(world_position(&world, child), vec3(0.0, 5.0, 1.0));
+    }
+
+    #[test]
+    fn children_of_deleted_entities_become_roots() {
+        let (mut world, mut system) = setup();
+        let parent = spawn(&mut world, Transform::from_position(vec3(3.0, 0.0, 0.0)), None);
+        let child = spawn(&mut world, Transform::from_position(vec3(0.0, 1.0, 0.0)), Some(parent));
+        let grandchild = spawn(&mut world, Transform::from_position(vec3(0.0, 0.0, 1.0)), Some(child));
+        run(&mut world, &mut system);
+        assert_close(world_position(&world, grandchild), vec3(3.0, 1.0, 1.0));
+
+        world.delete_entity(parent).unwrap();
+        world.maintain();
+        run(&mut world, &mut system);
+
+        assert!(world.read_storage::<Parent>().get(child).is_none());
+        assert_close(world_position(&world, child), vec3(0.0, 1.0, 0.0));
+        assert_eq!(children_of(&world, child), vec![grandchild]);
+        assert_close(world_position(&world, grandchild), vec3(0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let (mut world, mut system) = setup();
+        let a = spawn(&mut world, Transform::from_position(vec3(1.0, 0.0, 0.0)), None);
+        let b = spawn(&mut world, Transform::from_position(vec3(0.0, 1.0, 0.0)), Some(a));
+        let c = spawn(&mut world, Transform::from_position(vec3(0.0, 0.0, 1.0)), Some(b));
+        run(&mut world, &mut system);
+
+        world.write_storage::<Parent>().insert(a, Parent { entity: c }).unwrap();
+        run(&mut world, &mut system);
+        run(&mut world, &mut system);
+
+        let parents = world.read_storage::<Parent>();
+        assert!(parents.get(a).is_none());
+        assert_eq!(parents.get(b).unwrap().entity, a);
+        assert_eq!(parents.get(c).unwrap().entity, b);
+        drop(parents);
+        assert_eq!(children_of(&world, a), vec![b]);
+        assert_eq!(children_of(&world, b), vec![c]);
+        assert!(children_of(&world, c).is_empty());
+        assert_close(world_position(&world, a), vec3(1.0, 0.0, 0.0));
+        assert_close(world_position(&world, c), vec3(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn world_matrices_compose_from_the_root() {
+        let (mut world, mut system) = setup();
+        // Created deepest first, so that the ids don't give the update order
+        let leaf = spawn(&mut world, Transform::from_position(vec3(0.0, 0.0, 1.0)), None);
+        let middle = spawn(&mut world, Transform::from_position(vec3(0.0, 1.0, 0.0)), None);
+        let root = spawn(&mut world, Transform {
+            scale: vec3(2.0, 2.0, 2.0),
+            ..Transform::from_position(vec3(1.0, 0.0, 0.0))
+        }, None);
+        {
+            let mut parents = world.write_storage::<Parent>();
+            parents.insert(leaf, Parent { entity: middle }).unwrap();
+            parents.insert(middle, Parent { entity: root }).unwrap();
+        }
+        run(&mut world, &mut system);
+
+        let transforms = world.read_storage::<Transform>();
+        let expected = transforms.get(root).unwrap().local_matrix()
+            * transforms.get(middle).unwrap().local_matrix()
+            * transforms.get(leaf).unwrap().local_matrix();
+        assert!((transforms.get(leaf).unwrap().model_matrix - expected).norm() < 1e-5);
+        drop(transforms);
+        assert_close(world_position(&world, leaf), vec3(1.0, 2.0, 2.0));
+
+        // Only the root is modified, its descendants follow
+        world.write_storage::<Transform>().get_mut(root).unwrap().position = vec3(-1.0, 0.0, 0.0);
+        run(&mut world, &mut system);
+        assert_close(world_position(&world, middle), vec3(-1.0, 2.0, 0.0));
+        assert_close(world_position(&world, leaf), vec3(-1.0, 2.0, 2.0));
+    }
+}
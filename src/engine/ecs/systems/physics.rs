@@ -5,8 +5,7 @@ use crate::ecs::resources::*;
 use crate::ecs::components::*;
 use specs::prelude::ComponentEvent;
 use nphysics3d::object::{RigidBodyDesc, ColliderDesc, BodyPartHandle};
-use nalgebra::{Isometry, UnitQuaternion};
-use crate::utils::ToVec3;
+use nalgebra::Isometry;
 use nphysics3d::material::MaterialHandle;
 use glfw::ffi::glfwGetTime;
 
@@ -87,8 +86,7 @@ impl<'a> System<'a> for SyncBodiesToPhysicsSystem {
             physics.body_handles.remove(&id);
 
             let body = RigidBodyDesc::new()
-                .translation(transform.position)
-                .rotation(transform.rotation)
+                .position(Isometry::from_parts(transform.position.into(), transform.rotation))
                 .name(rigid_body.name.clone())
                 .gravity_enabled(rigid_body.gravity_enabled)
                 .status(rigid_body.status)
@@ -123,14 +121,7 @@ impl<'a> System<'a> for SyncBodiesToPhysicsSystem {
 
             if let Some(handle) = physics.body_handles.get(&id).cloned() {
                 if let Some(body) = physics.world.rigid_body_mut(handle) {
-                    body.set_position(Isometry::from_parts(
-                        transform.position.into(),
-                        UnitQuaternion::from_euler_angles(
-                            transform.rotation.x,
-                            transform.rotation.y,
-                            transform.rotation.z,
-                        ),
-                    ));
+                    body.set_position(Isometry::from_parts(transform.position.into(), transform.rotation));
                 }
             }
         }
@@ -161,8 +152,7 @@ impl<'a> System<'a> for SyncBodiesFromPhysicsSystem {
                 if let Some(rigid_body) = physics.world.rigid_body(handle) {
                     let iso = rigid_body.position();
                     transform.position = iso.translation.vector;
-                    let a = iso.rotation as UnitQuaternion<f32>;
-                    transform.rotation = a.euler_angles().to_vec3();
+                    transform.rotation = iso.rotation;
                 }
             }
         }
@@ -0,0 +1,76 @@
+use specs::prelude::*;
+use crate::ecs::components::*;
+use crate::ecs::resources::*;
+use crate::gl_wrapper::fbo::FBO;
+use crate::voxel_2d::{VoxelWorld, VoxelEditEvents};
+
+// The voxel systems touch GL objects and the main thread's container,
+// they must all be added with `with_thread_local`
+
+// Applies the edits queued by the other systems
+pub struct VoxelEditSystem;
+
+impl<'a> System<'a> for VoxelEditSystem {
+    type SystemData = (WriteExpect<'a, VoxelWorld>,
+                       Write<'a, VoxelEditEvents>);
+
+    fn run(&mut self, (mut voxel_world, mut edits): Self::SystemData) {
+        while let Some(edit) = edits.queue.pop_front() {
+            voxel_world.apply_edit(edit);
+        }
+    }
+}
+
+// Streams the chunks around the active camera and keeps their colliders in sync
+pub struct VoxelStreamingSystem;
+
+impl<'a> System<'a> for VoxelStreamingSystem {
+    type SystemData = (ReadStorage<'a, Transform>,
+                       Read<'a, ActiveCamera>,
+                       WriteExpect<'a, VoxelWorld>,
+                       Write<'a, PhysicsWorld>);
+
+    fn run(&mut self, (transforms, active_camera, mut voxel_world, mut physics): Self::SystemData) {
+        let camera_position = active_camera.entity
+            .and_then(|entity| transforms.get(entity))
+            .map(|transform| transform.position);
+
+        if let Some(position) = camera_position {
+            voxel_world.stream_around(&position);
+        }
+        voxel_world.sync_colliders(&mut physics);
+    }
+}
+
+// Draws the voxel world into the active camera's render target. Must run after the
+// MeshRendererSystem, which clears the target and updates the camera matrices, and
+// before the PostProcessingSystem.
+pub struct VoxelRenderSystem;
+
+impl<'a> System<'a> for VoxelRenderSystem {
+    type SystemData = (ReadStorage<'a, Camera>,
+                       Read<'a, ActiveCamera>,
+                       Read<'a, Time>,
+                       WriteExpect<'a, VoxelWorld>);
+
+    fn run(&mut self, (cameras, active_camera, time, mut voxel_world): Self::SystemData) {
+        let camera = match active_camera.entity.and_then(|entity| cameras.get(entity)) {
+            Some(camera) => camera,
+            None => return
+        };
+
+        if camera.post_processing_effects.is_empty() {
+            FBO::bind_default();
+        } else {
+            camera.fb.bind();
+        }
+
+        // The outlines leave the depth test disabled
+        gl_call!(gl::Enable(gl::DEPTH_TEST));
+        gl_call!(gl::DepthFunc(gl::LESS));
+        gl_call!(gl::StencilFunc(gl::ALWAYS, 1, 0xFF));
+        gl_call!(gl::StencilMask(0x00));
+
+        voxel_world.render(&time);
+    }
+}
@@ -4,13 +4,17 @@ use crate::gl_wrapper::rbo::RBO;
 #[derive(Debug)]
 pub enum DepthStencilTarget {
     Texture2D(Texture2D),
-    RBO(RBO)
+    RBO(RBO),
+    // Depth only, for the shadow passes. A layer of a texture owned by the caller
+    // is attached before each pass, see FBO::attach_depth_layer.
+    Layered,
 }
 
 #[derive(Debug)]
 pub struct FBO {
     id: u32,
-    pub(crate) color_texture: Texture2D,
+    // None for the layered depth framebuffers
+    pub(crate) color_texture: Option<Texture2D>,
     depth_stencil_target: DepthStencilTarget,
 }
 
@@ -28,11 +32,42 @@ impl FBO {
             DepthStencilTarget::RBO(rbo) => {
                 gl_call!(gl::NamedFramebufferRenderbuffer(id, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, rbo.id));
             }
+            DepthStencilTarget::Layered => panic!("Layered depth framebuffers are created by FBO::layered_depth"),
         }
         if gl_call!(gl::CheckNamedFramebufferStatus(id, gl::FRAMEBUFFER)) != gl::FRAMEBUFFER_COMPLETE {
             panic!("Framebuffer {} is not complete", id);
         }
-        FBO { id, color_texture, depth_stencil_target }
+        FBO { id, color_texture: Some(color_texture), depth_stencil_target }
+    }
+
+    // Without color attachment, incomplete until a depth layer is attached
+    pub fn layered_depth() -> Self {
+        let mut id = 0u32;
+        gl_call!(gl::CreateFramebuffers(1, &mut id));
+        gl_call!(gl::NamedFramebufferDrawBuffer(id, gl::NONE));
+        gl_call!(gl::NamedFramebufferReadBuffer(id, gl::NONE));
+        FBO { id, color_texture: None, depth_stencil_target: DepthStencilTarget::Layered }
+    }
+
+    // Layer of an array texture, or face of a cube map array. Only for the layered depth framebuffers.
+    pub fn attach_depth_layer(&self, texture_id: u32, layer: u32) -> Result<(), String> {
+        match self.depth_stencil_target {
+            DepthStencilTarget::Layered => (),
+            _ => return Err(format!("Framebuffer {} has no layered depth attachment", self.id)),
+        }
+
+        gl_call!(gl::NamedFramebufferTextureLayer(self.id, gl::DEPTH_ATTACHMENT, texture_id, 0, layer as i32));
+        let status = gl_call!(gl::CheckNamedFramebufferStatus(self.id, gl::FRAMEBUFFER));
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("Framebuffer {} is not complete with layer {} of texture {} (status {:#x})",
+                               self.id, layer, texture_id, status));
+        }
+        Ok(())
+    }
+
+    // The color attachment, panics for the layered depth framebuffers
+    pub fn color_texture(&self) -> &Texture2D {
+        self.color_texture.as_ref().expect("Framebuffer has no color attachment")
     }
 
     pub fn bind(&self) {
@@ -48,4 +83,4 @@ impl Drop for FBO {
     fn drop(&mut self) {
         gl_call!(gl::DeleteFramebuffers(1, &self.id));
     }
-}
\ No newline at end of file
+}
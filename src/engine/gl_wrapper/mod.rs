@@ -3,6 +3,7 @@ pub mod vao;
 pub mod vbo;
 pub mod ebo;
 pub mod texture_2d;
+pub mod texture_2d_array;
 pub mod texture_cube_map;
 pub mod fbo;
 pub mod rbo;
@@ -13,6 +14,7 @@ pub use vao::*;
 pub use vbo::*;
 pub use ebo::*;
 pub use texture_2d::*;
+pub use texture_2d_array::*;
 pub use texture_cube_map::*;
 pub use fbo::*;
 pub use rbo::*;
@@ -22,6 +22,10 @@ impl ShaderPart {
     pub fn from_frag_source(source: &CStr) -> Result<ShaderPart, String> {
         ShaderPart::from_source(source, gl::FRAGMENT_SHADER)
     }
+
+    pub fn from_compute_source(source: &CStr) -> Result<ShaderPart, String> {
+        ShaderPart::from_source(source, gl::COMPUTE_SHADER)
+    }
 }
 
 impl Drop for ShaderPart {
@@ -139,10 +143,25 @@ impl ShaderProgram {
     }
 
     pub fn from_shaders(vertex: ShaderPart, fragment: ShaderPart) -> Result<ShaderProgram, String> {
+        Self::link(&[&vertex, &fragment])
+    }
+
+    pub fn from_compute(compute: ShaderPart) -> Result<ShaderProgram, String> {
+        Self::link(&[&compute])
+    }
+
+    // Local size must be declared in the shader
+    pub fn dispatch_compute(&self, groups_x: u32, groups_y: u32, groups_z: u32) {
+        self.use_program();
+        gl_call!(gl::DispatchCompute(groups_x, groups_y, groups_z));
+    }
+
+    fn link(parts: &[&ShaderPart]) -> Result<ShaderProgram, String> {
         let program_id = gl_call!(gl::CreateProgram());
 
-        gl_call!(gl::AttachShader(program_id, vertex.id));
-        gl_call!(gl::AttachShader(program_id, fragment.id));
+        for part in parts {
+            gl_call!(gl::AttachShader(program_id, part.id));
+        }
         gl_call!(gl::LinkProgram(program_id));
 
         // Error checking
@@ -165,8 +184,9 @@ impl ShaderProgram {
             return Err(error.to_string_lossy().into_owned());
         }
 
-        gl_call!(gl::DetachShader(program_id, vertex.id));
-        gl_call!(gl::DetachShader(program_id, fragment.id));
+        for part in parts {
+            gl_call!(gl::DetachShader(program_id, part.id));
+        }
         Ok(ShaderProgram { id: program_id, uniform_cache: RefCell::new(HashMap::new()) })
     }
 }
@@ -7,6 +7,8 @@ pub enum TextureFormat {
     Unknown,
     RGB,
     RGBA,
+    // Half float, for the precomputed lookup tables
+    RG16F,
     DepthStencil
 }
 
@@ -15,6 +17,7 @@ impl TextureFormat {
         match self {
             TextureFormat::RGB => gl::RGB,
             TextureFormat::RGBA => gl::RGBA,
+            TextureFormat::RG16F => gl::RG,
             TextureFormat::DepthStencil => panic!("Use the to_gl_enum_sized() function instead"),
             _ => panic!("Uninitialized texture"),
         }
@@ -24,6 +27,7 @@ impl TextureFormat {
         match self {
             TextureFormat::RGB => gl::RGB8,
             TextureFormat::RGBA => gl::RGBA8,
+            TextureFormat::RG16F => gl::RG16F,
             TextureFormat::DepthStencil => gl::DEPTH24_STENCIL8,
             _ => panic!("Uninitialized texture"),
         }
@@ -0,0 +1,91 @@
+use image::RgbaImage;
+use std::os::raw::c_void;
+
+#[derive(Debug)]
+pub struct Texture2DArray {
+    pub(crate) id: u32,
+    width: u32,
+    height: u32,
+    layers: u32,
+    mipmap_levels: u32,
+}
+
+impl Texture2DArray {
+    pub fn new() -> Self {
+        let mut id: u32 = 0;
+        gl_call!(gl::CreateTextures(gl::TEXTURE_2D_ARRAY, 1, &mut id));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, gl::NEAREST_MIPMAP_NEAREST as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32));
+        Texture2DArray { id, width: 0, height: 0, layers: 0, mipmap_levels: 0 }
+    }
+
+    // RGBA
+    pub fn allocate(&mut self, width: u32, height: u32, layers: u32, mipmap_levels: u32) {
+        gl_call!(gl::TextureStorage3D(
+            self.id, mipmap_levels as i32,
+            gl::RGBA8,
+            width as i32, height as i32, layers as i32));
+        gl_call!(gl::TextureParameteri(self.id, gl::TEXTURE_MAX_LEVEL, mipmap_levels as i32 - 1));
+        self.width = width;
+        self.height = height;
+        self.layers = layers;
+        self.mipmap_levels = mipmap_levels;
+    }
+
+    // Depth only, sampled with a sampler2DArrayShadow: the lookups compare the depth
+    // and get filtered linearly. Outside of the texture, everything is lit.
+    pub fn allocate_depth(&mut self, width: u32, height: u32, layers: u32) {
+        gl_call!(gl::TextureStorage3D(
+            self.id, 1,
+            gl::DEPTH_COMPONENT32F,
+            width as i32, height as i32, layers as i32));
+        gl_call!(gl::TextureParameteri(self.id, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32));
+        gl_call!(gl::TextureParameteri(self.id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32));
+        gl_call!(gl::TextureParameteri(self.id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32));
+        gl_call!(gl::TextureParameteri(self.id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32));
+        gl_call!(gl::TextureParameterfv(self.id, gl::TEXTURE_BORDER_COLOR, [1.0f32, 1.0, 1.0, 1.0].as_ptr()));
+        gl_call!(gl::TextureParameteri(self.id, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32));
+        gl_call!(gl::TextureParameteri(self.id, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32));
+        self.width = width;
+        self.height = height;
+        self.layers = layers;
+        self.mipmap_levels = 1;
+    }
+
+    pub fn update_layer(&mut self, layer: u32, xoffset: u32, yoffset: u32, img: &RgbaImage) {
+        gl_call!(gl::TextureSubImage3D(
+            self.id, 0,
+            xoffset as i32, yoffset as i32, layer as i32,
+            img.width() as i32, img.height() as i32, 1,
+            gl::RGBA, gl::UNSIGNED_BYTE,
+            img.as_ptr() as *const c_void));
+    }
+
+    pub fn generate_mipmaps(&self) {
+        gl_call!(gl::GenerateTextureMipmap(self.id));
+    }
+
+    pub fn activate(&self, unit: u32) -> &Self {
+        gl_call!(gl::ActiveTexture(gl::TEXTURE0 + unit));
+        gl_call!(gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.id));
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+}
+
+impl Drop for Texture2DArray {
+    fn drop(&mut self) {
+        gl_call!(gl::DeleteTextures(1, &self.id))
+    }
+}
@@ -1,42 +1,46 @@
-use image::GenericImageView;
 use std::os::raw::c_void;
+use super::FBO;
+use crate::containers::AssetError;
 
 #[derive(Debug)]
 pub struct TextureCubeMap {
-    id: u32,
+    pub(crate) id: u32,
 }
 
 impl TextureCubeMap {
-    pub fn new(filenames: &[&str; 6]) -> Self {
+    // Faces are in the GL order: +x, -x, +y, -y, +z, -z. Every face is read before the
+    // texture is created, nothing is allocated when one of them can't be loaded.
+    pub fn new(filenames: &[&str; 6]) -> Result<Self, AssetError> {
+        let mut faces = Vec::with_capacity(6);
+        for filename in filenames.iter() {
+            let img = image::open(filename)
+                .map_err(|error| AssetError::Image { path: filename.to_string(), error })?;
+            faces.push(img.to_rgb());
+        }
+
+        let (width, height) = faces[0].dimensions();
+        if let Some((i, face)) = faces.iter().enumerate().find(|(_, face)| face.dimensions() != (width, height)) {
+            return Err(AssetError::CubeFaceSize {
+                path: filenames[i].to_string(),
+                expected: (width, height),
+                found: face.dimensions(),
+            });
+        }
+
         let mut id: u32 = 0;
         gl_call!(gl::CreateTextures(gl::TEXTURE_CUBE_MAP_ARRAY, 1, &mut id));
 
-        let (width, height) = {
-            let dims = match image::open(&filenames[0]) {
-                Ok(img) => img.dimensions(),
-                Err(err) => panic!("Filename: {}, error: {}", filenames[0], err.to_string())
-            };
-            (dims.0 as i32, dims.1 as i32)
-        };
-
         gl_call!(gl::TextureStorage3D(
             id, 1,
-            gl::RGB8, width, height, 6));
-
-        for i in 0..6 {
-            let filename = filenames[i];
-
-            let pixels = match image::open(&filename) {
-                Ok(img) => img.raw_pixels(),
-                Err(err) => panic!("Filename: {}, error: {}", filename, err.to_string())
-            };
+            gl::RGB8, width as i32, height as i32, 6));
 
+        for (i, face) in faces.iter().enumerate() {
             gl_call!(gl::TextureSubImage3D(
                 id, 0,
                 0, 0, i as i32,
-                width, height, 1,
-                gl::RGB, gl::UNSIGNED_BYTE, pixels.as_ptr() as *const c_void));
-        };
+                width as i32, height as i32, 1,
+                gl::RGB, gl::UNSIGNED_BYTE, face.as_ptr() as *const c_void));
+        }
 
         gl_call!(gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32));
         gl_call!(gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32));
@@ -44,9 +48,55 @@ impl TextureCubeMap {
         gl_call!(gl::TextureParameteri(id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32));
         gl_call!(gl::TextureParameteri(id, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32));
 
+        Ok(TextureCubeMap { id })
+    }
+
+    // Single half float cube map, written by compute shaders through imageCubeArray.
+    // Used for the precomputed environment lighting.
+    pub fn new_hdr(resolution: u32, mipmap_levels: u32) -> Self {
+        let mut id: u32 = 0;
+        gl_call!(gl::CreateTextures(gl::TEXTURE_CUBE_MAP_ARRAY, 1, &mut id));
+
+        gl_call!(gl::TextureStorage3D(
+            id, mipmap_levels as i32,
+            gl::RGBA16F, resolution as i32, resolution as i32, 6));
+
+        let min_filter = if mipmap_levels > 1 { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR };
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, min_filter as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32));
+
         TextureCubeMap { id }
     }
 
+    // Array of `cubes` depth cube maps, sampled with a samplerCubeArrayShadow.
+    // Used as render targets for the point lights' shadows, see attach_face.
+    pub fn new_depth(resolution: u32, cubes: u32) -> Self {
+        let mut id: u32 = 0;
+        gl_call!(gl::CreateTextures(gl::TEXTURE_CUBE_MAP_ARRAY, 1, &mut id));
+
+        gl_call!(gl::TextureStorage3D(
+            id, 1,
+            gl::DEPTH_COMPONENT32F, resolution as i32, resolution as i32, 6 * cubes as i32));
+
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32));
+        gl_call!(gl::TextureParameteri(id, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32));
+
+        TextureCubeMap { id }
+    }
+
+    // Faces are in the GL order: +x, -x, +y, -y, +z, -z
+    pub fn attach_face(&self, fbo: &FBO, cube: u32, face: u32) -> Result<(), String> {
+        fbo.attach_depth_layer(self.id, 6 * cube + face)
+    }
+
     pub fn activate(unit: u32) {
         gl_call!(gl::ActiveTexture(gl::TEXTURE0 + unit));
     }
@@ -73,3 +73,41 @@ impl Drop for UBO {
         gl_call!(gl::DeleteBuffers(1, &self.id))
     }
 }
+
+// Shader storage buffer for data whose size isn't known in advance, like arrays of lights.
+// The caller lays the data out with std430, the buffer grows when it doesn't fit anymore.
+pub struct SSBO {
+    pub(crate) id: u32,
+    // In bytes
+    capacity: usize,
+    usage: u32,
+}
+
+impl SSBO {
+    pub fn new(update_frequency: BufferUpdateFrequency) -> Self {
+        let mut id: u32 = 0;
+        gl_call!(gl::CreateBuffers(1, &mut id));
+        SSBO { id, capacity: 0, usage: update_frequency.to_gl_enum() }
+    }
+
+    pub fn update(&mut self, data: &[f32]) {
+        let size = data.len() * std::mem::size_of::<f32>();
+        if size > self.capacity {
+            gl_call!(gl::NamedBufferData(self.id, size as isize, data.as_ptr() as *const c_void, self.usage));
+            self.capacity = size;
+        } else {
+            gl_call!(gl::NamedBufferSubData(self.id, 0, size as isize, data.as_ptr() as *const c_void));
+        }
+    }
+
+    pub fn bind(&self, binding_point: u32) -> &Self {
+        gl_call!(gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding_point, self.id));
+        self
+    }
+}
+
+impl Drop for SSBO {
+    fn drop(&mut self) {
+        gl_call!(gl::DeleteBuffers(1, &self.id))
+    }
+}
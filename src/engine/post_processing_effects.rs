@@ -43,7 +43,7 @@ impl Kernel {
         let pp_shader = CONTAINER.get_local::<KernelShader>();
         let quad_vao = CONTAINER.get_local::<PredefinedShapes>().shapes.get("unit_quad").unwrap();
 
-        pp_shader.bind_screen_texture(&input.color_texture);
+        pp_shader.bind_screen_texture(input.color_texture());
         pp_shader.bind_kernel(&self.kernel);
         quad_vao.bind();
         gl_call!(gl::Disable(gl::DEPTH_TEST));
@@ -101,7 +101,7 @@ impl GaussianBlur {
         let quad_vao = CONTAINER.get_local::<PredefinedShapes>().shapes.get("unit_quad").unwrap();
 
         // v pass
-        pp_shader.bind_screen_texture(&input.color_texture);
+        pp_shader.bind_screen_texture(input.color_texture());
         pp_shader.bind_kernel(&self.kernel, true);
         quad_vao.bind();
 
@@ -112,7 +112,7 @@ impl GaussianBlur {
         gl_call!(gl::DrawArrays(gl::TRIANGLES, 0, 6));
 
         // h pass
-        pp_shader.bind_screen_texture(&self.v_pass.color_texture);
+        pp_shader.bind_screen_texture(self.v_pass.color_texture());
         pp_shader.bind_kernel(&self.kernel, false);
         quad_vao.bind();
 
@@ -6,6 +6,7 @@ in VertexAttributes {
     vec2 texture_coords;
     vec3 frag_pos;
     vec3 normal;
+    vec4 tangent;
 } attrs;
 
 struct Material {
@@ -18,25 +19,27 @@ struct Material {
     bool using_specular_texture;
     sampler2D specular_texture;
 
+    bool using_normal_texture;
     sampler2D normal_texture;
     float shininess;
 };
 
-struct Light {
-    vec3 position;
-    vec3 color;
-    float ambient_strength;
-    float intensity;
-};
+#include "lighting.glsl"
 
 uniform Material material;
-uniform Light light;
-in vec3 light_pos;
+uniform vec3 ambient_light;
+
+// Diffuse and specular reflection of a light coming from light_dir
+vec3 shade(vec3 light_dir, vec3 radiance, vec3 normal, vec3 view_dir, vec3 diffuse_frag, vec3 specular_frag) {
+    float diff = max(dot(normal, light_dir), 0.0);
+    vec3 light_reflection = reflect(-light_dir, normal);
+    float spec = pow(max(dot(view_dir, light_reflection), 0.0), material.shininess);
+    return radiance * (diff * diffuse_frag + spec * specular_frag);
+}
 
 void main() {
     vec3 diffuse_frag = material.diffuse_color;
     vec3 specular_frag = material.specular_color;
-    vec3 normal;
 
     if (material.using_diffuse_texture) {
         diffuse_frag = texture(material.diffuse_texture, attrs.texture_coords).rgb;
@@ -46,22 +49,42 @@ void main() {
         specular_frag = texture(material.specular_texture, attrs.texture_coords).rgb;
     }
 
-    // ambient
-    vec3 ambient_color = light.ambient_strength * diffuse_frag;
+    vec3 normal = normalize(attrs.normal);
+    if (material.using_normal_texture) {
+        normal = map_normal(material.normal_texture, normal);
+    }
+    vec3 view_dir = normalize(cam.position.xyz - attrs.frag_pos);
+
+    vec3 result = ambient_light * diffuse_frag;
 
-    // diffuse
-    normal = normalize(attrs.normal);
-    vec3 light_dir = normalize(light_pos - attrs.frag_pos);
-    float diff = max(dot(normal, light_dir), 0.0);
-    vec3 diffuse_color = light.color * diff * diffuse_frag;
+    for (uint i = 0; i < dir_lights.count; i++) {
+        DirLight light = dir_lights.lights[i];
+        vec3 light_dir = -normalize(light.direction);
+        vec3 radiance = light.color * light.intensity * dir_shadow(light, normal, light_dir);
+        result += shade(light_dir, radiance, normal, view_dir, diffuse_frag, specular_frag);
+    }
 
-    // specular
-    vec3 frag_to_camera = normalize(- attrs.frag_pos);
-    vec3 light_reflection = reflect(-light_dir, normal);
+    for (uint i = 0; i < point_lights.count; i++) {
+        PointLight light = point_lights.lights[i];
+        vec3 to_light = light.position - attrs.frag_pos;
+        float distance = length(to_light);
+        vec3 light_dir = to_light / distance;
+        vec3 radiance = light.color * light.intensity * attenuation(distance, light.range)
+            * point_shadow(light, normal, light_dir, distance);
+        result += shade(light_dir, radiance, normal, view_dir, diffuse_frag, specular_frag);
+    }
 
-    float spec = pow(max(dot(frag_to_camera, light_reflection), 0.0), material.shininess);
-    vec3 specular_color = light.color * spec * light.intensity * specular_frag;
+    for (uint i = 0; i < spotlights.count; i++) {
+        SpotLight light = spotlights.lights[i];
+        vec3 to_light = light.position - attrs.frag_pos;
+        float distance = length(to_light);
+        vec3 light_dir = to_light / distance;
+        // 1 inside the inner cone, 0 outside the outer one
+        float cone = smoothstep(light.outer_cos, light.inner_cos, dot(-light_dir, normalize(light.direction)));
+        vec3 radiance = light.color * light.intensity * attenuation(distance, light.range) * cone
+            * spot_shadow(light, normal, light_dir);
+        result += shade(light_dir, radiance, normal, view_dir, diffuse_frag, specular_frag);
+    }
 
-    vec3 result = ambient_color + diffuse_color + specular_color;
     Color = vec4(result, 1.0);
-}
\ No newline at end of file
+}
@@ -3,23 +3,20 @@ use super::*;
 use crate::gl_wrapper::shader_compilation::*;
 use std::ffi::CString;
 use nalgebra_glm::{Vec3, Mat4};
-use std::sync::Arc;
-use crate::containers::CONTAINER;
-use specs::ReadStorage;
-use specs::join::Join;
+use crate::containers::{Handle, CONTAINER};
 use crate::ToVec3;
 
 #[derive(Clone)]
 pub(crate) enum PixelData {
     Color(Vec3),
-    Texture(Arc<Texture2D>),
+    Texture(Handle<Texture2D>),
 }
 
 #[derive(Clone)]
 pub struct DiffuseData {
     pub(crate) diffuse: PixelData,
     pub(crate) specular: PixelData,
-    pub normal: Option<Arc<Texture2D>>,
+    pub normal: Option<Handle<Texture2D>>,
     pub shininess: f32,
 }
 
@@ -42,7 +39,7 @@ impl ShaderData for DiffuseData {
         // Bind diffuse
         match &self.diffuse {
             PixelData::Texture(texture) => {
-                texture.activate(0);
+                texture.current_texture().activate(0);
                 shader.program.set_uniform1i("material.diffuse_texture", 0);
                 shader.program.set_uniform1i("material.using_diffuse_texture", 1);
 
@@ -56,7 +53,7 @@ impl ShaderData for DiffuseData {
         // Bind specular
         match &self.specular {
             PixelData::Texture(texture) => {
-                texture.activate(1);
+                texture.current_texture().activate(1);
                 shader.program.set_uniform1i("material.specular_texture", 1);
                 shader.program.set_uniform1i("material.using_specular_texture", 1);
             },
@@ -69,26 +66,18 @@ impl ShaderData for DiffuseData {
         // Bind normal
         match &self.normal {
             Some(texture) => {
-                texture.activate(2);
+                texture.current_texture().activate(2);
                 shader.program.set_uniform1i("material.normal_texture", 2);
+                shader.program.set_uniform1i("material.using_normal_texture", 1);
             },
             None => {
-//                panic!("Normal texture doesn't exist");
-//                shader.program.set_uniform1i("material.normal_texture", -1);
+                shader.program.set_uniform1i("material.using_normal_texture", 0);
             }
         }
 
         // Bind shininess
         shader.program.set_uniform1f("material.shininess", self.shininess);
     }
-
-    fn bind_lights(&self,
-                   transforms: &ReadStorage<Transform>,
-                   point_lights: &ReadStorage<PointLight>
-    ) {
-        let shader = CONTAINER.get_local::<DiffuseShader>();
-        shader.bind_lights(transforms, point_lights);
-    }
 }
 
 #[derive(Clone)]
@@ -103,7 +92,7 @@ impl DiffuseShader {
         ).unwrap();
 
         let frag_shader = ShaderPart::from_frag_source(
-            &CString::new(include_str!("diffuse.frag")).unwrap()
+            &with_lighting(include_str!("diffuse.frag"))
         ).unwrap();
 
         ShaderProgram::from_shaders(vert_shader, frag_shader).unwrap()
@@ -124,17 +113,9 @@ impl DiffuseShader {
         self.program.set_uniform_matrix4fv("model", model.as_ptr());
     }
 
-    fn bind_lights(&self, transforms: &ReadStorage<Transform>, point_lights: &ReadStorage<PointLight>) {
-        // TODO bind multiple lights
-        for (transform, point_light) in (transforms, point_lights).join() {
-            let transform = transform as &Transform;
-            let point_light = point_light as &PointLight;
-
-            self.program.set_uniform3f("light.position", transform.position.as_slice());
-            self.program.set_uniform3f("light.color", point_light.color.as_slice());
-            self.program.set_uniform1f("light.ambient_strength", 0.5);
-            self.program.set_uniform1f("light.intensity", point_light.intensity);
-            break;
-        }
+    // The lights themselves are read from the buffers bound by LightBuffers
+    pub fn set_ambient_light(&self, color: &Vec3) {
+        self.program.use_program();
+        self.program.set_uniform3f("ambient_light", color.as_slice());
     }
 }
\ No newline at end of file
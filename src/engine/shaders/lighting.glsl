@@ -0,0 +1,157 @@
+// Lights, shadows and camera shared by the lit shaders, pasted in place of their
+// `#include "lighting.glsl"` line, see shaders::with_lighting.
+// The `attrs` block with `texture_coords` and the world space `frag_pos`, `normal` and `tangent`
+// must be declared before.
+
+// The layouts must match the ones packed in shaders/lights.rs
+// shadow_map is -1 for the lights without shadows
+struct DirLight {
+    vec3 direction;
+    float intensity;
+    vec3 color;
+    float shadow_bias;
+    int shadow_map;
+};
+
+struct PointLight {
+    vec3 position;
+    float range;
+    vec3 color;
+    float intensity;
+    float shadow_bias;
+    int shadow_map;
+};
+
+struct SpotLight {
+    vec3 position;
+    float range;
+    vec3 direction;
+    float intensity;
+    vec3 color;
+    float inner_cos;
+    float outer_cos;
+    float shadow_bias;
+    int shadow_map;
+};
+
+layout(std140, binding = 0) uniform CameraMatrices {
+    mat4 view;
+    mat4 projection;
+    vec4 position;
+} cam;
+
+layout(std430, binding = 1) readonly buffer DirLights {
+    uint count;
+    DirLight lights[];
+} dir_lights;
+
+layout(std430, binding = 2) readonly buffer PointLights {
+    uint count;
+    PointLight lights[];
+} point_lights;
+
+layout(std430, binding = 3) readonly buffer SpotLights {
+    uint count;
+    SpotLight lights[];
+} spotlights;
+
+// The layouts must match the ones packed in shaders/shadows.rs
+layout(std430, binding = 4) readonly buffer Shadows {
+    mat4 cascade_matrices[4];
+    // Far distance of each cascade from the camera
+    vec4 cascade_splits;
+    mat4 spot_matrices[4];
+} shadows;
+
+layout(binding = 3) uniform sampler2DArrayShadow cascade_maps;
+layout(binding = 4) uniform sampler2DArrayShadow spot_shadow_maps;
+// Distance to the light divided by its range
+layout(binding = 5) uniform samplerCubeArrayShadow point_shadow_maps;
+
+// Directions sampled around the fragment for the soft point light shadows
+const vec3 POINT_SHADOW_OFFSETS[20] = vec3[](
+    vec3(1, 1, 1), vec3(1, -1, 1), vec3(-1, -1, 1), vec3(-1, 1, 1),
+    vec3(1, 1, -1), vec3(1, -1, -1), vec3(-1, -1, -1), vec3(-1, 1, -1),
+    vec3(1, 1, 0), vec3(1, -1, 0), vec3(-1, -1, 0), vec3(-1, 1, 0),
+    vec3(1, 0, 1), vec3(-1, 0, 1), vec3(1, 0, -1), vec3(-1, 0, -1),
+    vec3(0, 1, 1), vec3(0, -1, 1), vec3(0, -1, -1), vec3(0, 1, -1)
+);
+
+// Inverse square falloff, smoothly brought to 0 at the range of the light
+float attenuation(float distance, float range) {
+    float ratio = distance / range;
+    float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
+
+// Bigger offset on the surfaces at a grazing angle from the light
+float slope_bias(float bias, vec3 normal, vec3 light_dir) {
+    return max(bias * (1.0 - dot(normal, light_dir)), bias * 0.1);
+}
+
+// Fraction of the light reaching the fragment, averaged over 3x3 texels of the map
+float pcf(sampler2DArrayShadow maps, int layer, vec4 light_space_pos, float bias) {
+    vec3 coords = light_space_pos.xyz / light_space_pos.w * 0.5 + 0.5;
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(maps, 0).xy);
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(maps, vec4(coords.xy + vec2(x, y) * texel, layer, coords.z - bias));
+        }
+    }
+    return lit / 9.0;
+}
+
+float dir_shadow(DirLight light, vec3 normal, vec3 light_dir) {
+    if (light.shadow_map < 0) {
+        return 1.0;
+    }
+
+    float depth = -(cam.view * vec4(attrs.frag_pos, 1.0)).z;
+    for (int i = 0; i < 4; i++) {
+        if (depth < shadows.cascade_splits[i]) {
+            vec4 light_space_pos = shadows.cascade_matrices[i] * vec4(attrs.frag_pos, 1.0);
+            return pcf(cascade_maps, i, light_space_pos, slope_bias(light.shadow_bias, normal, light_dir));
+        }
+    }
+    // Past the last cascade
+    return 1.0;
+}
+
+float spot_shadow(SpotLight light, vec3 normal, vec3 light_dir) {
+    if (light.shadow_map < 0) {
+        return 1.0;
+    }
+
+    vec4 light_space_pos = shadows.spot_matrices[light.shadow_map] * vec4(attrs.frag_pos, 1.0);
+    return pcf(spot_shadow_maps, light.shadow_map, light_space_pos, slope_bias(light.shadow_bias, normal, light_dir));
+}
+
+// The penumbra gets wider as the camera moves away
+float point_shadow(PointLight light, vec3 normal, vec3 light_dir, float distance) {
+    if (light.shadow_map < 0) {
+        return 1.0;
+    }
+
+    vec3 from_light = attrs.frag_pos - light.position;
+    float depth = distance / light.range - slope_bias(light.shadow_bias, normal, light_dir);
+    float radius = (1.0 + length(cam.position.xyz - attrs.frag_pos) / light.range) * 0.02;
+    float lit = 0.0;
+    for (int i = 0; i < 20; i++) {
+        vec3 direction = from_light + POINT_SHADOW_OFFSETS[i] * radius;
+        lit += texture(point_shadow_maps, vec4(direction, light.shadow_map), depth);
+    }
+    return lit / 20.0;
+}
+
+// Normal from a tangent space normal map, the bitangent is rebuilt like MikkTSpace does
+vec3 map_normal(sampler2D normal_map, vec3 normal) {
+    vec3 tangent = normalize(attrs.tangent.xyz - normal * dot(normal, attrs.tangent.xyz));
+    vec3 bitangent = cross(normal, tangent) * attrs.tangent.w;
+    vec3 tangent_normal = texture(normal_map, attrs.texture_coords).xyz * 2.0 - 1.0;
+    return normalize(mat3(tangent, bitangent, normal) * tangent_normal);
+}
@@ -0,0 +1,118 @@
+use crate::ecs::components::*;
+use crate::gl_wrapper::{BufferUpdateFrequency, SSBO};
+use nalgebra_glm::{Vec3, Vec4, vec4};
+use crate::shaders::shadows::ShadowMaps;
+use specs::{Entities, ReadStorage};
+use specs::join::Join;
+
+// Binding points of the light buffers, see diffuse.frag
+pub const DIR_LIGHTS_BINDING: u32 = 1;
+pub const POINT_LIGHTS_BINDING: u32 = 2;
+pub const SPOTLIGHTS_BINDING: u32 = 3;
+
+// Every buffer starts with the light count, padded to the alignment of the light structs
+fn light_buffer(count: usize, floats_per_light: usize) -> Vec<f32> {
+    let mut data = Vec::with_capacity(4 + count * floats_per_light);
+    data.extend_from_slice(&[f32::from_bits(count as u32), 0.0, 0.0, 0.0]);
+    data
+}
+
+pub(crate) fn world_position(transform: &Transform) -> Vec3 {
+    (transform.model_matrix * vec4(0.0, 0.0, 0.0, 1.0)).xyz()
+}
+
+pub(crate) fn world_forward(transform: &Transform) -> Vec3 {
+    let forward: Vec4 = transform.model_matrix * vec4(0.0, 0.0, -1.0, 0.0);
+    forward.xyz().normalize()
+}
+
+// std430: direction, intensity, color, shadow bias, shadow map, padding.
+// The shadow map is an index in the shadow maps, -1 without shadows.
+pub fn pack_dir_lights(lights: &[(&DirLight, i32)]) -> Vec<f32> {
+    let mut data = light_buffer(lights.len(), 12);
+    for (light, shadow_map) in lights {
+        let direction = light.direction.normalize();
+        data.extend_from_slice(&[direction.x, direction.y, direction.z, light.intensity]);
+        data.extend_from_slice(&[light.color.x, light.color.y, light.color.z, light.shadow_bias]);
+        data.extend_from_slice(&[f32::from_bits(*shadow_map as u32), 0.0, 0.0, 0.0]);
+    }
+    data
+}
+
+// std430: position, range, color, intensity, shadow bias, shadow map, padding
+pub fn pack_point_lights(lights: &[(Vec3, &PointLight, i32)]) -> Vec<f32> {
+    let mut data = light_buffer(lights.len(), 12);
+    for (position, light, shadow_map) in lights {
+        data.extend_from_slice(&[position.x, position.y, position.z, light.range]);
+        data.extend_from_slice(&[light.color.x, light.color.y, light.color.z, light.intensity]);
+        data.extend_from_slice(&[light.shadow_bias, f32::from_bits(*shadow_map as u32), 0.0, 0.0]);
+    }
+    data
+}
+
+// std430: position, range, direction, intensity, color, cosine of the inner angle,
+// cosine of the outer angle, shadow bias, shadow map, padding
+pub fn pack_spotlights(lights: &[(Vec3, Vec3, &Spotlight, i32)]) -> Vec<f32> {
+    let mut data = light_buffer(lights.len(), 16);
+    for (position, direction, light, shadow_map) in lights {
+        data.extend_from_slice(&[position.x, position.y, position.z, light.range]);
+        data.extend_from_slice(&[direction.x, direction.y, direction.z, light.intensity]);
+        data.extend_from_slice(&[light.color.x, light.color.y, light.color.z, light.inner_angle.cos()]);
+        data.extend_from_slice(&[light.outer_angle.cos(), light.shadow_bias, f32::from_bits(*shadow_map as u32), 0.0]);
+    }
+    data
+}
+
+// Every light of the scene, gathered once per frame and shared by all the draws
+pub struct LightBuffers {
+    dir_lights: SSBO,
+    point_lights: SSBO,
+    spotlights: SSBO,
+}
+
+impl Default for LightBuffers {
+    fn default() -> Self {
+        LightBuffers {
+            dir_lights: SSBO::new(BufferUpdateFrequency::Often),
+            point_lights: SSBO::new(BufferUpdateFrequency::Often),
+            spotlights: SSBO::new(BufferUpdateFrequency::Often),
+        }
+    }
+}
+
+impl LightBuffers {
+    // Positions and directions are in world space, taken from the world matrices.
+    // The shadow maps must have been rendered for this frame.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(&mut self,
+                  entities: &Entities,
+                  shadow_maps: &ShadowMaps,
+                  transforms: &ReadStorage<Transform>,
+                  dir_lights: &ReadStorage<DirLight>,
+                  point_lights: &ReadStorage<PointLight>,
+                  spotlights: &ReadStorage<Spotlight>
+    ) {
+        let dir_lights: Vec<(&DirLight, i32)> = (entities, dir_lights).join()
+            .map(|(entity, light)| (light, shadow_maps.dir_shadow_map(entity)))
+            .collect();
+        self.dir_lights.update(&pack_dir_lights(&dir_lights));
+
+        let point_lights: Vec<(Vec3, &PointLight, i32)> = (entities, transforms, point_lights).join()
+            .map(|(entity, transform, light)| (world_position(transform), light, shadow_maps.point_shadow_map(entity)))
+            .collect();
+        self.point_lights.update(&pack_point_lights(&point_lights));
+
+        let spotlights: Vec<(Vec3, Vec3, &Spotlight, i32)> = (entities, transforms, spotlights).join()
+            .map(|(entity, transform, light)| {
+                (world_position(transform), world_forward(transform), light, shadow_maps.spot_shadow_map(entity))
+            })
+            .collect();
+        self.spotlights.update(&pack_spotlights(&spotlights));
+    }
+
+    pub fn bind(&self) {
+        self.dir_lights.bind(DIR_LIGHTS_BINDING);
+        self.point_lights.bind(POINT_LIGHTS_BINDING);
+        self.spotlights.bind(SPOTLIGHTS_BINDING);
+    }
+}
@@ -1,18 +1,22 @@
-use nalgebra_glm::{Mat4, Vec3};
-use specs::ReadStorage;
-use crate::ecs::components::*;
+use nalgebra_glm::Mat4;
+use std::ffi::CString;
 
 pub mod diffuse;
 pub mod outline;
 pub mod post_processing;
 pub mod cube_map;
 pub mod voxel;
+pub mod lights;
+pub mod shadow;
+pub mod shadows;
+pub mod pbr;
 
+// The lights aren't bound per draw, see lights::LightBuffers
 pub trait ShaderData: Sync + Send {
     fn bind_model(&self, model: &Mat4);
+}
 
-    fn bind_lights(&self,
-                   transforms: &ReadStorage<Transform>,
-                   point_lights: &ReadStorage<PointLight>
-    );
-}
\ No newline at end of file
+// Fragment shader source with the shared lights and shadows code pasted in
+pub(crate) fn with_lighting(source: &str) -> CString {
+    CString::new(source.replacen("#include \"lighting.glsl\"", include_str!("lighting.glsl"), 1)).unwrap()
+}
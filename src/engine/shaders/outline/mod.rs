@@ -15,10 +15,6 @@ impl ShaderData for OutlineData {
         shader.bind_model(model);
         shader.program.set_uniform3f("color", self.color.as_slice());
     }
-
-    fn bind_lights(&self, _transforms: &ReadStorage<Transform>, _point_lights: &ReadStorage<PointLight>) {
-        unimplemented!()
-    }
 }
 
 #[derive(Clone)]
@@ -0,0 +1,123 @@
+use crate::ecs::components::Background;
+use crate::gl_wrapper::shader_compilation::*;
+use crate::gl_wrapper::texture_2d::*;
+use crate::gl_wrapper::texture_cube_map::TextureCubeMap;
+use std::ffi::CString;
+use std::sync::Arc;
+
+pub const IRRADIANCE_RESOLUTION: u32 = 32;
+pub const PREFILTERED_RESOLUTION: u32 = 128;
+pub const PREFILTERED_MIPMAP_LEVELS: u32 = 5;
+pub const BRDF_LUT_RESOLUTION: u32 = 512;
+
+// Texture units of the maps, see pbr.frag
+const IRRADIANCE_UNIT: u32 = 8;
+const PREFILTERED_UNIT: u32 = 9;
+const BRDF_LUT_UNIT: u32 = 10;
+
+// Matches the local size of the compute shaders
+const WORK_GROUP_SIZE: u32 = 8;
+
+fn work_groups(size: u32) -> u32 {
+    (size + WORK_GROUP_SIZE - 1) / WORK_GROUP_SIZE
+}
+
+fn compute_program(source: &str) -> ShaderProgram {
+    let compute_shader = ShaderPart::from_compute_source(&CString::new(source).unwrap()).unwrap();
+    ShaderProgram::from_compute(compute_shader).unwrap()
+}
+
+// Convolutions of a skybox, used as ambient lighting by the PbrShader
+struct IblMaps {
+    irradiance: TextureCubeMap,
+    prefiltered: TextureCubeMap,
+}
+
+// Image based lighting from the active camera's skybox. The maps are only
+// computed again when the skybox changes, the BRDF lookup table only once.
+pub struct EnvironmentLighting {
+    irradiance_program: ShaderProgram,
+    prefilter_program: ShaderProgram,
+    brdf_lut: Texture2D,
+    skybox: Option<Arc<TextureCubeMap>>,
+    maps: Option<IblMaps>,
+}
+
+impl Default for EnvironmentLighting {
+    fn default() -> Self {
+        let mut brdf_lut = Texture2D::new();
+        brdf_lut.allocate(TextureFormat::RG16F, BRDF_LUT_RESOLUTION, BRDF_LUT_RESOLUTION, 1);
+        gl_call!(gl::TextureParameteri(brdf_lut.id, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32));
+        gl_call!(gl::TextureParameteri(brdf_lut.id, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32));
+        gl_call!(gl::TextureParameteri(brdf_lut.id, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32));
+        gl_call!(gl::TextureParameteri(brdf_lut.id, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32));
+
+        let brdf_program = compute_program(include_str!("brdf_lut.comp"));
+        gl_call!(gl::BindImageTexture(0, brdf_lut.id, 0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RG16F));
+        brdf_program.dispatch_compute(work_groups(BRDF_LUT_RESOLUTION), work_groups(BRDF_LUT_RESOLUTION), 1);
+        gl_call!(gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT));
+
+        EnvironmentLighting {
+            irradiance_program: compute_program(include_str!("irradiance.comp")),
+            prefilter_program: compute_program(include_str!("prefilter.comp")),
+            brdf_lut,
+            skybox: None,
+            maps: None,
+        }
+    }
+}
+
+impl EnvironmentLighting {
+    pub fn update(&mut self, background: &Background) {
+        match background {
+            Background::Skybox(skybox) => {
+                let changed = self.skybox.as_ref().map_or(true, |current| !Arc::ptr_eq(current, skybox));
+                if changed {
+                    self.maps = Some(self.convolve(skybox));
+                    self.skybox = Some(skybox.clone());
+                }
+            }
+            Background::Color(..) => {
+                self.skybox = None;
+                self.maps = None;
+            }
+        }
+    }
+
+    fn convolve(&self, skybox: &TextureCubeMap) -> IblMaps {
+        TextureCubeMap::activate(0);
+        skybox.bind();
+
+        let irradiance = TextureCubeMap::new_hdr(IRRADIANCE_RESOLUTION, 1);
+        gl_call!(gl::BindImageTexture(0, irradiance.id, 0, gl::TRUE, 0, gl::WRITE_ONLY, gl::RGBA16F));
+        self.irradiance_program.dispatch_compute(work_groups(IRRADIANCE_RESOLUTION), work_groups(IRRADIANCE_RESOLUTION), 6);
+
+        // Rougher for every mip level
+        let prefiltered = TextureCubeMap::new_hdr(PREFILTERED_RESOLUTION, PREFILTERED_MIPMAP_LEVELS);
+        for level in 0..PREFILTERED_MIPMAP_LEVELS {
+            let size = PREFILTERED_RESOLUTION >> level;
+            let roughness = level as f32 / (PREFILTERED_MIPMAP_LEVELS - 1) as f32;
+            gl_call!(gl::BindImageTexture(0, prefiltered.id, level as i32, gl::TRUE, 0, gl::WRITE_ONLY, gl::RGBA16F));
+            self.prefilter_program.use_program();
+            self.prefilter_program.set_uniform1f("roughness", roughness);
+            self.prefilter_program.dispatch_compute(work_groups(size), work_groups(size), 6);
+        }
+
+        gl_call!(gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT));
+        IblMaps { irradiance, prefiltered }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.maps.is_some()
+    }
+
+    pub fn bind(&self) {
+        if let Some(maps) = &self.maps {
+            TextureCubeMap::activate(IRRADIANCE_UNIT);
+            maps.irradiance.bind();
+            TextureCubeMap::activate(PREFILTERED_UNIT);
+            maps.prefiltered.bind();
+        }
+        self.brdf_lut.activate(BRDF_LUT_UNIT);
+    }
+}
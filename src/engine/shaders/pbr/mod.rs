@@ -0,0 +1,120 @@
+use crate::gl_wrapper::texture_2d::*;
+use super::*;
+use crate::gl_wrapper::shader_compilation::*;
+use std::ffi::CString;
+use nalgebra_glm::{Vec3, Mat4, vec3};
+use crate::containers::{Handle, CONTAINER};
+
+mod ibl;
+pub use ibl::*;
+
+// Texture units of the maps, see pbr.frag. Units 3 to 5 hold the shadow maps.
+const ALBEDO_UNIT: u32 = 0;
+const METALLIC_ROUGHNESS_UNIT: u32 = 1;
+const NORMAL_UNIT: u32 = 2;
+const AO_UNIT: u32 = 6;
+const EMISSIVE_UNIT: u32 = 7;
+
+// Metallic-roughness material. The factors are multiplied with the maps when they are present.
+#[derive(Clone)]
+pub struct PbrData {
+    pub albedo: Vec3,
+    pub albedo_map: Option<Handle<Texture2D>>,
+    pub metallic: f32,
+    pub roughness: f32,
+    // Roughness in the green channel, metallic in the blue one
+    pub metallic_roughness_map: Option<Handle<Texture2D>>,
+    pub normal_map: Option<Handle<Texture2D>>,
+    // Ambient occlusion in the red channel
+    pub ao_map: Option<Handle<Texture2D>>,
+    pub emissive: Vec3,
+    pub emissive_map: Option<Handle<Texture2D>>,
+}
+
+impl Default for PbrData {
+    fn default() -> Self {
+        PbrData {
+            albedo: vec3(1.0, 1.0, 1.0),
+            albedo_map: None,
+            metallic: 0.0,
+            roughness: 0.5,
+            metallic_roughness_map: None,
+            normal_map: None,
+            ao_map: None,
+            emissive: vec3(0.0, 0.0, 0.0),
+            emissive_map: None,
+        }
+    }
+}
+
+impl ShaderData for PbrData {
+    fn bind_model(&self, model: &Mat4) {
+        let shader = CONTAINER.get_local::<PbrShader>();
+        shader.bind_model(model);
+
+        shader.program.set_uniform3f("albedo_factor", self.albedo.as_slice());
+        shader.program.set_uniform1f("metallic_factor", self.metallic);
+        shader.program.set_uniform1f("roughness_factor", self.roughness);
+        shader.program.set_uniform3f("emissive_factor", self.emissive.as_slice());
+
+        shader.bind_map("has_albedo_map", &self.albedo_map, ALBEDO_UNIT);
+        shader.bind_map("has_metallic_roughness_map", &self.metallic_roughness_map, METALLIC_ROUGHNESS_UNIT);
+        shader.bind_map("has_normal_map", &self.normal_map, NORMAL_UNIT);
+        shader.bind_map("has_ao_map", &self.ao_map, AO_UNIT);
+        shader.bind_map("has_emissive_map", &self.emissive_map, EMISSIVE_UNIT);
+    }
+}
+
+#[derive(Clone)]
+pub struct PbrShader {
+    program: ShaderProgram,
+}
+
+impl PbrShader {
+    fn compile_program() -> ShaderProgram {
+        let vert_shader = ShaderPart::from_vert_source(
+            &CString::new(include_str!("pbr.vert")).unwrap()
+        ).unwrap();
+
+        let frag_shader = ShaderPart::from_frag_source(
+            &with_lighting(include_str!("pbr.frag"))
+        ).unwrap();
+
+        ShaderProgram::from_shaders(vert_shader, frag_shader).unwrap()
+    }
+}
+
+impl Default for PbrShader {
+    fn default() -> Self {
+        PbrShader {
+            program: Self::compile_program(),
+        }
+    }
+}
+
+impl PbrShader {
+    fn bind_model(&self, model: &Mat4) {
+        self.program.use_program();
+        self.program.set_uniform_matrix4fv("model", model.as_ptr());
+    }
+
+    fn bind_map(&self, flag: &str, map: &Option<Handle<Texture2D>>, unit: u32) {
+        match map {
+            Some(texture) => {
+                texture.current_texture().activate(unit);
+                self.program.set_uniform1i(flag, 1);
+            },
+            None => {
+                self.program.set_uniform1i(flag, 0);
+            }
+        }
+    }
+
+    // The maps themselves are bound by EnvironmentLighting. Without them, the ambient light is used.
+    pub fn set_environment(&self, environment: &EnvironmentLighting, ambient_light: &Vec3) {
+        self.program.use_program();
+        self.program.set_uniform1i("has_environment", environment.is_available() as i32);
+        self.program.set_uniform1f("prefiltered_max_lod", (PREFILTERED_MIPMAP_LEVELS - 1) as f32);
+        self.program.set_uniform3f("ambient_light", ambient_light.as_slice());
+    }
+}
@@ -0,0 +1,165 @@
+#version 450 core
+
+out vec4 Color;
+
+in VertexAttributes {
+    vec2 texture_coords;
+    vec3 frag_pos;
+    vec3 normal;
+    vec4 tangent;
+} attrs;
+
+#include "lighting.glsl"
+
+// The texture units must match the ones bound in shaders/pbr
+layout(binding = 0) uniform sampler2D albedo_map;
+// Green: roughness, blue: metallic
+layout(binding = 1) uniform sampler2D metallic_roughness_map;
+layout(binding = 2) uniform sampler2D normal_map;
+layout(binding = 6) uniform sampler2D ao_map;
+layout(binding = 7) uniform sampler2D emissive_map;
+
+layout(binding = 8) uniform samplerCubeArray irradiance_map;
+layout(binding = 9) uniform samplerCubeArray prefiltered_map;
+layout(binding = 10) uniform sampler2D brdf_lut;
+
+// Multiplied with the maps when they are present
+uniform vec3 albedo_factor;
+uniform float metallic_factor;
+uniform float roughness_factor;
+uniform vec3 emissive_factor;
+
+uniform bool has_albedo_map;
+uniform bool has_metallic_roughness_map;
+uniform bool has_normal_map;
+uniform bool has_ao_map;
+uniform bool has_emissive_map;
+
+// Without a skybox, the ambient light is used instead of the environment
+uniform bool has_environment;
+uniform float prefiltered_max_lod;
+uniform vec3 ambient_light;
+
+const float PI = 3.14159265359;
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    float ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Cook-Torrance reflection of a light coming from light_dir
+vec3 shade(vec3 light_dir, vec3 radiance, vec3 normal, vec3 view_dir, vec3 albedo, float metallic, float roughness, vec3 f0) {
+    vec3 half_dir = normalize(view_dir + light_dir);
+    float n_dot_l = max(dot(normal, light_dir), 0.0);
+    float n_dot_v = max(dot(normal, view_dir), 0.0001);
+
+    float d = distribution_ggx(max(dot(normal, half_dir), 0.0), roughness);
+    float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+    vec3 f = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
+
+    vec3 specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    vec3 diffuse = (1.0 - f) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+void main() {
+    vec3 albedo = albedo_factor;
+    if (has_albedo_map) {
+        // The textures are sRGB, the lighting is done in linear space
+        albedo *= pow(texture(albedo_map, attrs.texture_coords).rgb, vec3(2.2));
+    }
+
+    float metallic = metallic_factor;
+    float roughness = roughness_factor;
+    if (has_metallic_roughness_map) {
+        vec3 metallic_roughness = texture(metallic_roughness_map, attrs.texture_coords).rgb;
+        roughness *= metallic_roughness.g;
+        metallic *= metallic_roughness.b;
+    }
+    roughness = clamp(roughness, 0.04, 1.0);
+
+    float ao = has_ao_map ? texture(ao_map, attrs.texture_coords).r : 1.0;
+
+    vec3 emissive = emissive_factor;
+    if (has_emissive_map) {
+        emissive *= pow(texture(emissive_map, attrs.texture_coords).rgb, vec3(2.2));
+    }
+
+    vec3 normal = normalize(attrs.normal);
+    if (has_normal_map) {
+        normal = map_normal(normal_map, normal);
+    }
+    vec3 view_dir = normalize(cam.position.xyz - attrs.frag_pos);
+    float n_dot_v = max(dot(normal, view_dir), 0.0);
+
+    // Dielectrics reflect 4% at normal incidence
+    vec3 f0 = mix(vec3(0.04), albedo, metallic);
+
+    vec3 result = vec3(0.0);
+
+    for (uint i = 0; i < dir_lights.count; i++) {
+        DirLight light = dir_lights.lights[i];
+        vec3 light_dir = -normalize(light.direction);
+        vec3 radiance = light.color * light.intensity * dir_shadow(light, normal, light_dir);
+        result += shade(light_dir, radiance, normal, view_dir, albedo, metallic, roughness, f0);
+    }
+
+    for (uint i = 0; i < point_lights.count; i++) {
+        PointLight light = point_lights.lights[i];
+        vec3 to_light = light.position - attrs.frag_pos;
+        float distance = length(to_light);
+        vec3 light_dir = to_light / distance;
+        vec3 radiance = light.color * light.intensity * attenuation(distance, light.range)
+            * point_shadow(light, normal, light_dir, distance);
+        result += shade(light_dir, radiance, normal, view_dir, albedo, metallic, roughness, f0);
+    }
+
+    for (uint i = 0; i < spotlights.count; i++) {
+        SpotLight light = spotlights.lights[i];
+        vec3 to_light = light.position - attrs.frag_pos;
+        float distance = length(to_light);
+        vec3 light_dir = to_light / distance;
+        float cone = smoothstep(light.outer_cos, light.inner_cos, dot(-light_dir, normalize(light.direction)));
+        vec3 radiance = light.color * light.intensity * attenuation(distance, light.range) * cone
+            * spot_shadow(light, normal, light_dir);
+        result += shade(light_dir, radiance, normal, view_dir, albedo, metallic, roughness, f0);
+    }
+
+    vec3 ambient;
+    if (has_environment) {
+        vec3 f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+        vec3 diffuse = (1.0 - f) * (1.0 - metallic) * texture(irradiance_map, vec4(normal, 0.0)).rgb * albedo;
+
+        vec3 reflection = reflect(-view_dir, normal);
+        vec3 prefiltered = textureLod(prefiltered_map, vec4(reflection, 0.0), roughness * prefiltered_max_lod).rgb;
+        vec2 brdf = texture(brdf_lut, vec2(n_dot_v, roughness)).rg;
+        vec3 specular = prefiltered * (f * brdf.x + brdf.y);
+
+        ambient = diffuse + specular;
+    } else {
+        ambient = ambient_light * albedo;
+    }
+    result += ambient * ao + emissive;
+
+    // Reinhard tone mapping, then back to sRGB
+    result = result / (result + 1.0);
+    Color = vec4(pow(result, vec3(1.0 / 2.2)), 1.0);
+}
@@ -0,0 +1,33 @@
+#version 450 core
+
+layout (location = 0) in vec3 pos;
+layout (location = 1) in vec2 texture_coords;
+layout (location = 2) in vec3 normal;
+// The handedness of the bitangent in w
+layout (location = 3) in vec4 tangent;
+
+layout(std140, binding = 0) uniform CameraMatrices {
+    mat4 view;
+    mat4 projection;
+    vec4 position;
+} cam;
+
+// World space
+out VertexAttributes {
+    vec2 texture_coords;
+    vec3 frag_pos;
+    vec3 normal;
+    vec4 tangent;
+} attrs;
+
+uniform mat4 model;
+
+void main() {
+    attrs.frag_pos = vec3(model * vec4(pos, 1.0f));
+    attrs.texture_coords = texture_coords;
+    // TODO very expensive, do this on the CPU
+    attrs.normal = mat3(transpose(inverse(model))) * normal;
+    attrs.tangent = vec4(mat3(model) * tangent.xyz, tangent.w);
+
+    gl_Position = cam.projection * cam.view * vec4(attrs.frag_pos, 1.0f);
+}
@@ -0,0 +1,85 @@
+use crate::gl_wrapper::shader_compilation::*;
+use std::ffi::CString;
+use nalgebra_glm::{Mat4, Vec3};
+
+// Depth only, used by the shadow pass
+#[derive(Clone)]
+pub struct ShadowShader {
+    program: ShaderProgram,
+}
+
+impl ShadowShader {
+    fn compile_program() -> ShaderProgram {
+        let vert_shader = ShaderPart::from_vert_source(
+            &CString::new(include_str!("shadow.vert")).unwrap()
+        ).unwrap();
+
+        let frag_shader = ShaderPart::from_frag_source(
+            &CString::new(include_str!("shadow.frag")).unwrap()
+        ).unwrap();
+
+        ShaderProgram::from_shaders(vert_shader, frag_shader).unwrap()
+    }
+}
+
+impl Default for ShadowShader {
+    fn default() -> Self {
+        ShadowShader {
+            program: Self::compile_program(),
+        }
+    }
+}
+
+impl ShadowShader {
+    pub fn bind_light_space(&self, light_space: &Mat4) {
+        self.program.use_program();
+        self.program.set_uniform_matrix4fv("light_space", light_space.as_ptr());
+    }
+
+    pub fn bind_model(&self, model: &Mat4) {
+        self.program.use_program();
+        self.program.set_uniform_matrix4fv("model", model.as_ptr());
+    }
+}
+
+// Writes the distance to the light instead of the depth, the same in every face of the cube map
+#[derive(Clone)]
+pub struct PointShadowShader {
+    program: ShaderProgram,
+}
+
+impl PointShadowShader {
+    fn compile_program() -> ShaderProgram {
+        let vert_shader = ShaderPart::from_vert_source(
+            &CString::new(include_str!("point_shadow.vert")).unwrap()
+        ).unwrap();
+
+        let frag_shader = ShaderPart::from_frag_source(
+            &CString::new(include_str!("point_shadow.frag")).unwrap()
+        ).unwrap();
+
+        ShaderProgram::from_shaders(vert_shader, frag_shader).unwrap()
+    }
+}
+
+impl Default for PointShadowShader {
+    fn default() -> Self {
+        PointShadowShader {
+            program: Self::compile_program(),
+        }
+    }
+}
+
+impl PointShadowShader {
+    pub fn bind_light(&self, light_space: &Mat4, position: &Vec3, range: f32) {
+        self.program.use_program();
+        self.program.set_uniform_matrix4fv("light_space", light_space.as_ptr());
+        self.program.set_uniform3f("light_position", position.as_slice());
+        self.program.set_uniform1f("range", range);
+    }
+
+    pub fn bind_model(&self, model: &Mat4) {
+        self.program.use_program();
+        self.program.set_uniform_matrix4fv("model", model.as_ptr());
+    }
+}
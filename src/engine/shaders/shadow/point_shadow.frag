@@ -0,0 +1,11 @@
+#version 450 core
+
+in vec3 frag_pos;
+
+uniform vec3 light_position;
+uniform float range;
+
+// Linear distance in [0, 1], compared against the fragments' distance in diffuse.frag
+void main() {
+    gl_FragDepth = clamp(distance(frag_pos, light_position) / range, 0.0, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 450 core
+
+layout (location = 0) in vec3 pos;
+
+uniform mat4 model;
+uniform mat4 light_space;
+
+out vec3 frag_pos;
+
+void main() {
+    frag_pos = vec3(model * vec4(pos, 1.0f));
+    gl_Position = light_space * vec4(frag_pos, 1.0f);
+}
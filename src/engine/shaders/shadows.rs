@@ -0,0 +1,282 @@
+use crate::ecs::components::*;
+use crate::gl_wrapper::{BufferUpdateFrequency, SSBO, FBO, Texture2DArray, TextureCubeMap};
+use crate::containers::CONTAINER;
+use crate::shaders::shadow::{ShadowShader, PointShadowShader};
+use crate::shaders::lights::{world_position, world_forward};
+use nalgebra_glm::{Mat4, Vec3, vec3, vec4};
+use specs::{Entities, Entity, ReadStorage};
+use specs::join::Join;
+
+pub const SHADOW_CASCADES: usize = 4;
+pub const CASCADE_RESOLUTION: u32 = 2048;
+pub const MAX_SPOT_SHADOWS: usize = 4;
+pub const SPOT_SHADOW_RESOLUTION: u32 = 1024;
+pub const MAX_POINT_SHADOWS: usize = 4;
+pub const POINT_SHADOW_RESOLUTION: u32 = 512;
+
+// Binding point of the light space matrices and texture units of the maps, see diffuse.frag
+pub const SHADOWS_BINDING: u32 = 4;
+pub const CASCADES_TEXTURE_UNIT: u32 = 3;
+pub const SPOT_SHADOWS_TEXTURE_UNIT: u32 = 4;
+pub const POINT_SHADOWS_TEXTURE_UNIT: u32 = 5;
+
+// 0 splits the view uniformly, 1 logarithmically
+const CASCADE_SPLIT_LAMBDA: f32 = 0.6;
+// How far behind a cascade the casters are still rendered
+const CASCADE_CASTER_DISTANCE: f32 = 50.0;
+const SPOT_SHADOW_NEAR_PLANE: f32 = 0.05;
+const POINT_SHADOW_NEAR_PLANE: f32 = 0.05;
+
+// Direction and up vector of the cube map faces, in the GL order
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+// Far distance of every cascade from the camera, the first one starts at the near plane
+pub fn cascade_splits(near_plane: f32, far_plane: f32) -> [f32; SHADOW_CASCADES] {
+    let mut splits = [0.0; SHADOW_CASCADES];
+    for (i, split) in splits.iter_mut().enumerate() {
+        let p = (i + 1) as f32 / SHADOW_CASCADES as f32;
+        let uniform = near_plane + (far_plane - near_plane) * p;
+        let logarithmic = near_plane * (far_plane / near_plane).powf(p);
+        *split = uniform + (logarithmic - uniform) * CASCADE_SPLIT_LAMBDA;
+    }
+    splits
+}
+
+// Any up vector works as long as it isn't parallel to the direction
+fn light_up(direction: &Vec3) -> Vec3 {
+    if direction.y.abs() > 0.99 {
+        vec3(1.0, 0.0, 0.0)
+    } else {
+        vec3(0.0, 1.0, 0.0)
+    }
+}
+
+// Orthographic projection around the bounding sphere of a slice of the camera's frustum.
+// The sphere keeps the size of the map constant while the camera rotates.
+fn cascade_matrix(camera: &Camera, view: &Mat4, near_plane: f32, far_plane: f32, direction: &Vec3) -> Mat4 {
+    let inverse = (camera.projection_matrix_range(near_plane, far_plane) * view)
+        .try_inverse()
+        .unwrap_or_else(Mat4::identity);
+
+    let mut corners = Vec::with_capacity(8);
+    for &x in &[-1.0, 1.0] {
+        for &y in &[-1.0, 1.0] {
+            for &z in &[-1.0, 1.0] {
+                let corner = inverse * vec4(x, y, z, 1.0);
+                corners.push(corner.xyz() / corner.w);
+            }
+        }
+    }
+
+    let center = corners.iter().fold(Vec3::zeros(), |sum, corner| sum + corner) / 8.0;
+    let radius = corners.iter()
+        .map(|corner| (corner - center).norm())
+        .fold(0.0f32, f32::max)
+        .ceil();
+
+    let direction = direction.normalize();
+    let eye = center - direction * (radius + CASCADE_CASTER_DISTANCE);
+    let light_view = nalgebra_glm::look_at(&eye, &center, &light_up(&direction));
+    let light_projection = nalgebra_glm::ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + CASCADE_CASTER_DISTANCE);
+    light_projection * light_view
+}
+
+fn spot_matrix(position: &Vec3, direction: &Vec3, light: &Spotlight) -> Mat4 {
+    let view = nalgebra_glm::look_at(position, &(position + direction), &light_up(direction));
+    let projection = nalgebra_glm::perspective(1.0, 2.0 * light.outer_angle, SPOT_SHADOW_NEAR_PLANE, light.range);
+    projection * view
+}
+
+fn point_face_matrices(position: &Vec3, light: &PointLight) -> Vec<Mat4> {
+    let projection = nalgebra_glm::perspective(1.0, 90.0f32.to_radians(), POINT_SHADOW_NEAR_PLANE, light.range);
+    CUBE_FACES.iter()
+        .map(|(direction, up)| {
+            let target = position + Vec3::from_column_slice(direction);
+            projection * nalgebra_glm::look_at(position, &target, &Vec3::from_column_slice(up))
+        })
+        .collect()
+}
+
+// Depth maps of the shadow casting lights, rendered every frame before the scene.
+// The cascades of the directional light and the spotlights' maps are layers of two array textures,
+// the point lights render the six faces of a cube map each.
+pub struct ShadowMaps {
+    fbo: FBO,
+    cascades: Texture2DArray,
+    spot_maps: Texture2DArray,
+    point_maps: TextureCubeMap,
+    matrices: SSBO,
+    dir_caster: Option<Entity>,
+    spot_casters: Vec<Entity>,
+    point_casters: Vec<Entity>,
+}
+
+impl Default for ShadowMaps {
+    fn default() -> Self {
+        let mut cascades = Texture2DArray::new();
+        cascades.allocate_depth(CASCADE_RESOLUTION, CASCADE_RESOLUTION, SHADOW_CASCADES as u32);
+        let mut spot_maps = Texture2DArray::new();
+        spot_maps.allocate_depth(SPOT_SHADOW_RESOLUTION, SPOT_SHADOW_RESOLUTION, MAX_SPOT_SHADOWS as u32);
+
+        ShadowMaps {
+            fbo: FBO::layered_depth(),
+            cascades,
+            spot_maps,
+            point_maps: TextureCubeMap::new_depth(POINT_SHADOW_RESOLUTION, MAX_POINT_SHADOWS as u32),
+            matrices: SSBO::new(BufferUpdateFrequency::Often),
+            dir_caster: None,
+            spot_casters: Vec::with_capacity(MAX_SPOT_SHADOWS),
+            point_casters: Vec::with_capacity(MAX_POINT_SHADOWS),
+        }
+    }
+}
+
+impl ShadowMaps {
+    // Index of the light's map, -1 if it has none
+    pub fn dir_shadow_map(&self, entity: Entity) -> i32 {
+        if self.dir_caster == Some(entity) { 0 } else { -1 }
+    }
+
+    pub fn spot_shadow_map(&self, entity: Entity) -> i32 {
+        self.spot_casters.iter()
+            .position(|&caster| caster == entity)
+            .map_or(-1, |index| index as i32)
+    }
+
+    pub fn point_shadow_map(&self, entity: Entity) -> i32 {
+        self.point_casters.iter()
+            .position(|&caster| caster == entity)
+            .map_or(-1, |index| index as i32)
+    }
+
+    // Leaves the shadow framebuffer bound, the caller sets its own target and viewport back
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(&mut self,
+                  entities: &Entities,
+                  transforms: &ReadStorage<Transform>,
+                  mesh_renderers: &ReadStorage<MeshRenderer>,
+                  camera: &Camera,
+                  camera_transform: &Transform,
+                  dir_lights: &ReadStorage<DirLight>,
+                  spotlights: &ReadStorage<Spotlight>,
+                  point_lights: &ReadStorage<PointLight>
+    ) {
+        // std430: cascade matrices, cascade splits, spotlight matrices
+        let mut data = Vec::with_capacity(16 * SHADOW_CASCADES + 4 + 16 * MAX_SPOT_SHADOWS);
+        let mut cascade_matrices = Vec::with_capacity(SHADOW_CASCADES);
+        let mut splits = [0.0; SHADOW_CASCADES];
+
+        self.dir_caster = None;
+        if let Some((entity, light)) = (entities, dir_lights).join().find(|(_, light)| light.casts_shadows) {
+            self.dir_caster = Some(entity);
+            let view = Camera::view_matrix(camera_transform);
+            let far_plane = camera.far_plane.min(light.range);
+            splits = cascade_splits(camera.near_plane, far_plane);
+
+            let mut near_plane = camera.near_plane;
+            for &split in splits.iter() {
+                cascade_matrices.push(cascade_matrix(camera, &view, near_plane, split, &light.direction));
+                near_plane = split;
+            }
+        }
+        cascade_matrices.resize(SHADOW_CASCADES, Mat4::identity());
+        for matrix in &cascade_matrices {
+            data.extend_from_slice(matrix.as_slice());
+        }
+        data.extend_from_slice(&splits);
+
+        self.spot_casters.clear();
+        let mut spot_matrices = Vec::with_capacity(MAX_SPOT_SHADOWS);
+        for (entity, transform, light) in (entities, transforms, spotlights).join()
+            .filter(|(_, _, light)| light.casts_shadows)
+            .take(MAX_SPOT_SHADOWS) {
+            self.spot_casters.push(entity);
+            spot_matrices.push(spot_matrix(&world_position(transform), &world_forward(transform), light));
+        }
+        let spot_count = spot_matrices.len();
+        spot_matrices.resize(MAX_SPOT_SHADOWS, Mat4::identity());
+        for matrix in &spot_matrices {
+            data.extend_from_slice(matrix.as_slice());
+        }
+        self.matrices.update(&data);
+
+        self.fbo.bind();
+        gl_call!(gl::Enable(gl::DEPTH_TEST));
+        gl_call!(gl::DepthFunc(gl::LESS));
+        gl_call!(gl::DepthMask(gl::TRUE));
+        gl_call!(gl::StencilMask(0x00));
+
+        let shader = CONTAINER.get_local::<ShadowShader>();
+        let cascade_count = if self.dir_caster.is_some() { SHADOW_CASCADES } else { 0 };
+
+        gl_call!(gl::Viewport(0, 0, CASCADE_RESOLUTION as i32, CASCADE_RESOLUTION as i32));
+        for (layer, matrix) in cascade_matrices.iter().take(cascade_count).enumerate() {
+            if let Err(err) = self.fbo.attach_depth_layer(self.cascades.id, layer as u32) {
+                error!("Skipping shadow cascade {}: {}", layer, err);
+                continue;
+            }
+            gl_call!(gl::Clear(gl::DEPTH_BUFFER_BIT));
+            shader.bind_light_space(matrix);
+            Self::draw_casters(transforms, mesh_renderers, |model| shader.bind_model(model));
+        }
+
+        gl_call!(gl::Viewport(0, 0, SPOT_SHADOW_RESOLUTION as i32, SPOT_SHADOW_RESOLUTION as i32));
+        for (layer, matrix) in spot_matrices.iter().take(spot_count).enumerate() {
+            if let Err(err) = self.fbo.attach_depth_layer(self.spot_maps.id, layer as u32) {
+                error!("Skipping spot light shadow {}: {}", layer, err);
+                continue;
+            }
+            gl_call!(gl::Clear(gl::DEPTH_BUFFER_BIT));
+            shader.bind_light_space(matrix);
+            Self::draw_casters(transforms, mesh_renderers, |model| shader.bind_model(model));
+        }
+
+        // Six passes per light, one for each face
+        self.point_casters.clear();
+        let point_shader = CONTAINER.get_local::<PointShadowShader>();
+        gl_call!(gl::Viewport(0, 0, POINT_SHADOW_RESOLUTION as i32, POINT_SHADOW_RESOLUTION as i32));
+        for (entity, transform, light) in (entities, transforms, point_lights).join()
+            .filter(|(_, _, light)| light.casts_shadows)
+            .take(MAX_POINT_SHADOWS) {
+            let cube = self.point_casters.len() as u32;
+            self.point_casters.push(entity);
+
+            let position = world_position(transform);
+            for (face, matrix) in point_face_matrices(&position, light).iter().enumerate() {
+                if let Err(err) = self.point_maps.attach_face(&self.fbo, cube, face as u32) {
+                    error!("Skipping face {} of point light shadow {}: {}", face, cube, err);
+                    continue;
+                }
+                gl_call!(gl::Clear(gl::DEPTH_BUFFER_BIT));
+                point_shader.bind_light(matrix, &position, light.range);
+                Self::draw_casters(transforms, mesh_renderers, |model| point_shader.bind_model(model));
+            }
+        }
+    }
+
+    fn draw_casters<F: Fn(&Mat4)>(transforms: &ReadStorage<Transform>, mesh_renderers: &ReadStorage<MeshRenderer>, bind_model: F) {
+        for (transform, mesh_renderer) in (transforms, mesh_renderers).join() {
+            let mesh = mesh_renderer.current_mesh();
+            mesh.vao.bind();
+            bind_model(&transform.model_matrix);
+            gl_call!(gl::DrawElements(gl::TRIANGLES,
+                                  mesh.indices.len() as i32,
+                                  gl::UNSIGNED_INT, std::ptr::null()));
+        }
+    }
+
+    pub fn bind(&self) {
+        self.matrices.bind(SHADOWS_BINDING);
+        self.cascades.activate(CASCADES_TEXTURE_UNIT);
+        self.spot_maps.activate(SPOT_SHADOWS_TEXTURE_UNIT);
+        TextureCubeMap::activate(POINT_SHADOWS_TEXTURE_UNIT);
+        self.point_maps.bind();
+    }
+}
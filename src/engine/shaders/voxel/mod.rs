@@ -21,6 +21,32 @@ impl VoxelShader {
 
     pub fn bind(&self) {
         self.program.use_program();
+        self.program.set_uniform1i("lightmap", 1);
+    }
+
+    // Light level of the blocks not reached by any light source
+    pub fn set_ambient_light(&self, ambient: f32) {
+        self.program.set_uniform1f("ambient_light", ambient);
+    }
+
+    // In seconds, selects the frame of the animated blocks
+    pub fn set_time(&self, time: f32) {
+        self.program.set_uniform1f("time", time);
+    }
+
+    // z of the layer being drawn
+    pub fn set_depth(&self, depth: f32) {
+        self.program.set_uniform1f("depth", depth);
+    }
+
+    // The pixels with a lower alpha are discarded, 0 to keep them all
+    pub fn set_alpha_cutoff(&self, cutoff: f32) {
+        self.program.set_uniform1f("alpha_cutoff", cutoff);
+    }
+
+    // Darkens the background layer
+    pub fn set_brightness(&self, brightness: f32) {
+        self.program.set_uniform1f("brightness", brightness);
     }
 
     pub fn set_offset(&self, offset: (i32, i32)) {
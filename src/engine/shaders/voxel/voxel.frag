@@ -1,13 +1,41 @@
 #version 450 core
 
 in VertexAttributes {
-    vec2 texture_coords;
+    vec3 texture_coords;
+    flat vec4 atlas_rect;
+    flat vec3 animation;
+    vec2 lightmap_coords;
 } attrs;
 
 out vec4 color;
 
-layout(location = 0) uniform sampler2D texture_atlas;
+layout(location = 0) uniform sampler2DArray texture_atlas;
+uniform sampler2D lightmap;
+uniform float ambient_light;
+uniform float time;
+uniform float brightness;
+uniform float alpha_cutoff;
 
 void main() {
-    color = texture(texture_atlas, attrs.texture_coords);
+    // Merged quads repeat the sprite, the tile coordinates are wrapped inside its atlas rect
+    vec2 rect_size = attrs.atlas_rect.zw - attrs.atlas_rect.xy;
+    vec2 rect_origin = attrs.atlas_rect.xy;
+    // The frames of animated sprites are stacked above the first one
+    if (attrs.animation.x > 1.0) {
+        float frame = mod(floor(time / attrs.animation.y), attrs.animation.x);
+        rect_origin.y += frame * attrs.animation.z;
+    }
+    vec2 uv = rect_origin + fract(attrs.texture_coords.xy) * rect_size;
+    // The gradients come from the unwrapped coordinates, so that the mip level
+    // doesn't jump at the tile borders
+    vec2 dx = dFdx(attrs.texture_coords.xy) * rect_size;
+    vec2 dy = dFdy(attrs.texture_coords.xy) * rect_size;
+
+    vec3 light = max(texture(lightmap, attrs.lightmap_coords).rgb, vec3(ambient_light));
+    color = textureGrad(texture_atlas, vec3(uv, attrs.texture_coords.z), dx, dy);
+    // Cutout blocks, e.g. glass and leaves, don't hide what is behind their holes
+    if (color.a < alpha_cutoff) {
+        discard;
+    }
+    color.rgb *= light * brightness;
 }
@@ -1,9 +1,13 @@
 #version 450 core
 
 layout (location = 0) in vec2 pos;
-layout (location = 1) in vec2 texture_coords;
+layout (location = 1) in vec3 texture_coords;
+layout (location = 2) in vec4 atlas_rect;
+// Frame count, frame time, frame stride
+layout (location = 3) in vec3 animation;
 
 uniform vec2 offset;
+uniform float depth;
 
 layout(std140, binding = 0) uniform CameraMatrices {
     mat4 view;
@@ -11,10 +15,18 @@ layout(std140, binding = 0) uniform CameraMatrices {
 } cam;
 
 out VertexAttributes {
-    vec2 texture_coords;
+    // Tile coordinates, atlas layer
+    vec3 texture_coords;
+    flat vec4 atlas_rect;
+    flat vec3 animation;
+    vec2 lightmap_coords;
 } attrs;
 
 void main() {
     attrs.texture_coords = texture_coords;
-    gl_Position = cam.projection * cam.view * vec4(vec3(pos + offset, 0.0f), 1.0f);
-}
\ No newline at end of file
+    attrs.atlas_rect = atlas_rect;
+    attrs.animation = animation;
+    // Tiles go from y - 1 to y, the light of the block at (x, y) is in the texel (x, y)
+    attrs.lightmap_coords = vec2(pos.x, pos.y + 1.0f) / 16.0f;
+    gl_Position = cam.projection * cam.view * vec4(vec3(pos + offset, depth), 1.0f);
+}
@@ -0,0 +1,411 @@
+use image::{GenericImageView, RgbaImage};
+use std::fmt;
+
+// Normalized texture coordinates of a sprite, its gutter excluded.
+// v0 is the bottom of the sprite since the images are flipped for GL.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UvRect {
+    pub u0: f32,
+    pub v0: f32,
+    pub u1: f32,
+    pub v1: f32,
+    // Layer of the texture array
+    pub layer: u32,
+}
+
+impl UvRect {
+    pub fn width(&self) -> f32 {
+        self.u1 - self.u0
+    }
+
+    pub fn height(&self) -> f32 {
+        self.v1 - self.v0
+    }
+}
+
+impl Default for UvRect {
+    fn default() -> Self {
+        UvRect { u0: 0.0, v0: 0.0, u1: 0.0, v1: 0.0, layer: 0 }
+    }
+}
+
+// Frames of an animated sprite, laid out from the bottom to the top of the atlas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpriteAnimation {
+    pub frame_count: u32,
+    // In seconds
+    pub frame_time: f32,
+    // Distance in v between two frames
+    pub frame_stride: f32,
+}
+
+impl Default for SpriteAnimation {
+    // Not animated
+    fn default() -> Self {
+        SpriteAnimation { frame_count: 1, frame_time: 1.0, frame_stride: 0.0 }
+    }
+}
+
+// The rect is the one of the first frame
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BlockSprite {
+    pub rect: UvRect,
+    pub animation: SpriteAnimation,
+}
+
+pub struct AtlasSettings {
+    // Pages start at this size and double until everything fits in one page
+    pub min_page_size: u32,
+    // Once pages can't grow anymore, the sprites spill into new pages
+    pub max_page_size: u32,
+    pub max_pages: u32,
+    // Border pixels repeated around each sprite, so that the mipmaps
+    // don't sample the neighbouring sprites
+    pub padding: u32,
+}
+
+impl Default for AtlasSettings {
+    fn default() -> Self {
+        AtlasSettings {
+            min_page_size: 64,
+            max_page_size: 2048,
+            max_pages: 256,
+            padding: 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+    SpriteTooBig { index: usize, width: u32, height: u32 },
+    TooManyPages(u32),
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtlasError::SpriteTooBig { index, width, height } =>
+                write!(f, "Sprite {} ({}x{}) doesn't fit in an atlas page", index, width, height),
+            AtlasError::TooManyPages(pages) => write!(f, "The atlas needs too many pages ({})", pages),
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+// Position of a sprite inside the atlas, its gutter excluded
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasPlacement {
+    pub page: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug)]
+pub struct AtlasLayout {
+    // Every page has the same size, they are the layers of a texture array
+    pub page_size: u32,
+    pub page_count: u32,
+    pub padding: u32,
+    // In the same order as the sprites
+    pub placements: Vec<AtlasPlacement>,
+}
+
+impl AtlasLayout {
+    pub fn uv_rect(&self, sprite: usize) -> UvRect {
+        let placement = &self.placements[sprite];
+        let size = self.page_size as f32;
+        UvRect {
+            u0: placement.x as f32 / size,
+            v0: placement.y as f32 / size,
+            u1: (placement.x + placement.width) as f32 / size,
+            v1: (placement.y + placement.height) as f32 / size,
+            layer: placement.page,
+        }
+    }
+
+    // Sprites are aligned on the padding, so the gutter protects the mip levels
+    // whose texels are at most as wide as the padding
+    pub fn mipmap_levels(&self) -> u32 {
+        let levels = alignment(self.padding).trailing_zeros() + 1;
+        let max_levels = 32 - self.page_size.leading_zeros();
+        u32::min(levels, max_levels)
+    }
+
+    // Copies the sprites in their pages and fills the gutters with their border pixels
+    pub fn compose_pages(&self, sprites: &[RgbaImage]) -> Vec<RgbaImage> {
+        assert_eq!(sprites.len(), self.placements.len());
+
+        let mut pages: Vec<RgbaImage> = (0..self.page_count)
+            .map(|_| RgbaImage::new(self.page_size, self.page_size))
+            .collect();
+
+        let padding = self.padding as i64;
+        for (sprite, placement) in sprites.iter().zip(&self.placements) {
+            let page = &mut pages[placement.page as usize];
+            let (width, height) = (sprite.width() as i64, sprite.height() as i64);
+
+            for y in -padding..height + padding {
+                for x in -padding..width + padding {
+                    let pixel = sprite.get_pixel(
+                        x.max(0).min(width - 1) as u32,
+                        y.max(0).min(height - 1) as u32
+                    );
+                    page.put_pixel(
+                        (placement.x as i64 + x) as u32,
+                        (placement.y as i64 + y) as u32,
+                        *pixel
+                    );
+                }
+            }
+        }
+
+        pages
+    }
+}
+
+// Images taller than wide whose height is a multiple of their width are vertical
+// strips of square frames, the first frame being at the top
+pub fn detect_frame_count(width: u32, height: u32) -> u32 {
+    if width > 0 && height > width && height % width == 0 {
+        height / width
+    } else {
+        1
+    }
+}
+
+// Splits a strip in frames, from top to bottom. None if the height isn't a multiple of the frame count.
+pub fn split_frames(strip: &RgbaImage, frame_count: u32) -> Option<Vec<RgbaImage>> {
+    let (width, height) = strip.dimensions();
+    if frame_count == 0 || height % frame_count != 0 {
+        return None;
+    }
+    let frame_height = height / frame_count;
+    Some((0..frame_count)
+        .map(|i| strip.view(0, i * frame_height, width, frame_height).to_image())
+        .collect())
+}
+
+// Stacks the frames from the bottom up, separated by gutters like the sprites of the atlas
+// so that the shader can step from a frame to the next one without bleeding.
+// Returns the sprite and the distance in pixels between two frames.
+pub fn frame_strip(frames: &[RgbaImage], padding: u32) -> (RgbaImage, u32) {
+    let (width, height) = frames[0].dimensions();
+    let stride = align(height + 2 * padding, alignment(padding));
+    let strip_height = stride * (frames.len() as u32 - 1) + height;
+
+    let mut strip = RgbaImage::new(width, strip_height);
+    for y in 0..strip_height {
+        // Gutter rows repeat the border of the closest frame
+        let (frame, frame_y) = if y % stride < height {
+            (y / stride, y % stride)
+        } else if y % stride < height + (stride - height) / 2 {
+            (y / stride, height - 1)
+        } else {
+            (y / stride + 1, 0)
+        };
+
+        for x in 0..width {
+            strip.put_pixel(x, y, *frames[frame as usize].get_pixel(x, frame_y));
+        }
+    }
+
+    (strip, stride)
+}
+
+fn alignment(padding: u32) -> u32 {
+    padding.max(1).next_power_of_two()
+}
+
+fn align(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+// Shelf packing: the sprites are sorted from the tallest to the shortest and laid
+// out left to right in rows, a new page is started when a row doesn't fit
+fn pack_pages(sizes: &[(u32, u32)], padding: u32, page_size: u32) -> (Vec<AtlasPlacement>, u32) {
+    let alignment = alignment(padding);
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| (std::cmp::Reverse(sizes[i].1), std::cmp::Reverse(sizes[i].0), i));
+
+    let mut placements = vec![AtlasPlacement { page: 0, x: 0, y: 0, width: 0, height: 0 }; sizes.len()];
+    let (mut page, mut x, mut y, mut shelf_height) = (0, 0, 0, 0);
+    for i in order {
+        let (width, height) = sizes[i];
+        let padded_width = align(width + 2 * padding, alignment);
+        let padded_height = align(height + 2 * padding, alignment);
+
+        if x + padded_width > page_size {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        if y + padded_height > page_size {
+            page += 1;
+            x = 0;
+            y = 0;
+            shelf_height = 0;
+        }
+
+        placements[i] = AtlasPlacement { page, x: x + padding, y: y + padding, width, height };
+        x += padded_width;
+        shelf_height = u32::max(shelf_height, padded_height);
+    }
+
+    let page_count = if sizes.is_empty() { 0 } else { page + 1 };
+    (placements, page_count)
+}
+
+// Finds the smallest page size that holds all the sprites, or spills them into
+// several pages of the maximum size
+pub fn pack_sprites(sizes: &[(u32, u32)], settings: &AtlasSettings) -> Result<AtlasLayout, AtlasError> {
+    let padding = settings.padding;
+    let alignment = alignment(padding);
+
+    let mut page_size = settings.min_page_size.max(1).next_power_of_two();
+    for (index, &(width, height)) in sizes.iter().enumerate() {
+        let side = align(u32::max(width, height) + 2 * padding, alignment);
+        if side > settings.max_page_size {
+            return Err(AtlasError::SpriteTooBig { index, width, height });
+        }
+        while page_size < side {
+            page_size *= 2;
+        }
+    }
+
+    loop {
+        let (placements, page_count) = pack_pages(sizes, padding, page_size);
+        if page_count <= 1 || page_size * 2 > settings.max_page_size {
+            if page_count > settings.max_pages {
+                return Err(AtlasError::TooManyPages(page_count));
+            }
+            return Ok(AtlasLayout { page_size, page_count, padding, placements });
+        }
+        page_size *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn settings(min_page_size: u32, max_page_size: u32, max_pages: u32, padding: u32) -> AtlasSettings {
+        AtlasSettings { min_page_size, max_page_size, max_pages, padding }
+    }
+
+    // Each pixel tells its sprite and its position apart
+    fn sprite(index: u8, width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([index, x as u8, y as u8, 255]))
+    }
+
+    fn assert_valid(layout: &AtlasLayout) {
+        let padding = layout.padding;
+        // The sprites with their gutters
+        let padded: Vec<(u32, u32, u32, u32, u32)> = layout.placements.iter()
+            .map(|p| (p.page, p.x - padding, p.y - padding, p.x + p.width + padding, p.y + p.height + padding))
+            .collect();
+
+        for (i, &(page, x0, y0, x1, y1)) in padded.iter().enumerate() {
+            assert!(page < layout.page_count);
+            assert!(layout.placements[i].x >= padding && layout.placements[i].y >= padding);
+            assert!(x1 <= layout.page_size && y1 <= layout.page_size, "sprite {} is outside its page", i);
+            for (j, &(other_page, ox0, oy0, ox1, oy1)) in padded.iter().enumerate().skip(i + 1) {
+                let overlap = page == other_page && x0 < ox1 && ox0 < x1 && y0 < oy1 && oy0 < y1;
+                assert!(!overlap, "sprites {} and {} overlap", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn sprites_and_gutters_dont_overlap() {
+        let sizes: Vec<(u32, u32)> = (0..40).map(|i| (4 + (i * 7) % 29, 4 + (i * 11) % 23)).collect();
+        for &padding in &[0, 1, 2, 4] {
+            let layout = pack_sprites(&sizes, &settings(16, 256, 16, padding)).unwrap();
+            assert_eq!(layout.placements.len(), sizes.len());
+            for (placement, &(width, height)) in layout.placements.iter().zip(&sizes) {
+                assert_eq!((placement.width, placement.height), (width, height));
+            }
+            assert_valid(&layout);
+        }
+    }
+
+    #[test]
+    fn pages_grow_then_spill() {
+        // 24 pixels with the gutters, 4 sprites fit in a 64 pixels page
+        let sizes = vec![(16, 16); 20];
+
+        let layout = pack_sprites(&sizes, &settings(64, 2048, 16, 4)).unwrap();
+        assert_eq!((layout.page_size, layout.page_count), (128, 1));
+
+        let layout = pack_sprites(&sizes, &settings(64, 64, 16, 4)).unwrap();
+        assert_eq!((layout.page_size, layout.page_count), (64, 5));
+        assert_valid(&layout);
+        for page in 0..5 {
+            assert_eq!(layout.placements.iter().filter(|p| p.page == page).count(), 4);
+        }
+
+        match pack_sprites(&sizes, &settings(64, 64, 4, 4)) {
+            Err(AtlasError::TooManyPages(5)) => (),
+            other => panic!("expected TooManyPages, got {:?}", other),
+        }
+        match pack_sprites(&[(16, 16), (60, 8)], &settings(64, 64, 4, 4)) {
+            Err(AtlasError::SpriteTooBig { index: 1, width: 60, height: 8 }) => (),
+            other => panic!("expected SpriteTooBig, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn gutters_repeat_the_edge_texels() {
+        let sprites = vec![sprite(1, 3, 2), sprite(2, 2, 4)];
+        let layout = pack_sprites(&[(3, 2), (2, 4)], &settings(16, 64, 1, 2)).unwrap();
+        let pages = layout.compose_pages(&sprites);
+        assert_eq!(pages.len(), 1);
+
+        for (sprite, placement) in sprites.iter().zip(&layout.placements) {
+            let (width, height) = (sprite.width() as i32, sprite.height() as i32);
+            for y in -2..height + 2 {
+                for x in -2..width + 2 {
+                    let expected = sprite.get_pixel(x.max(0).min(width - 1) as u32, y.max(0).min(height - 1) as u32);
+                    let pixel = pages[0].get_pixel((placement.x as i32 + x) as u32, (placement.y as i32 + y) as u32);
+                    assert_eq!(pixel, expected, "at ({}, {}) of {:?}", x, y, placement);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frames_are_stacked_with_gutters() {
+        let frames = vec![sprite(1, 4, 4), sprite(2, 4, 4), sprite(3, 4, 4)];
+        let (strip, stride) = frame_strip(&frames, 2);
+        assert_eq!(stride, 8);
+        assert_eq!(strip.dimensions(), (4, 2 * 8 + 4));
+
+        let rows: Vec<(u8, u8)> = (0..strip.height()).map(|y| {
+            let pixel = strip.get_pixel(1, y);
+            (pixel[0], pixel[2])
+        }).collect();
+        assert_eq!(rows, vec![
+            (1, 0), (1, 1), (1, 2), (1, 3), (1, 3), (1, 3), (2, 0), (2, 0),
+            (2, 0), (2, 1), (2, 2), (2, 3), (2, 3), (2, 3), (3, 0), (3, 0),
+            (3, 0), (3, 1), (3, 2), (3, 3),
+        ]);
+    }
+
+    #[test]
+    fn frame_strips_must_split_evenly() {
+        assert_eq!(detect_frame_count(16, 48), 3);
+        assert_eq!(detect_frame_count(16, 40), 1);
+        assert_eq!(detect_frame_count(16, 16), 1);
+
+        let strip = sprite(1, 4, 12);
+        let frames = split_frames(&strip, 3).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].get_pixel(0, 0)[2], 4);
+        assert!(split_frames(&strip, 5).is_none());
+        assert!(split_frames(&strip, 0).is_none());
+    }
+}
@@ -0,0 +1,140 @@
+use super::{greedy_rects, BlockRect, BlockRegistry, ChunkData, Layer, CHUNK_SIZE};
+use crate::ecs::resources::PhysicsWorld;
+use nalgebra::{Isometry3, Vector3};
+use ncollide3d::shape::{Compound, Cuboid, ShapeHandle};
+use nphysics3d::object::{BodyPartHandle, ColliderDesc};
+
+// Thickness of the colliders along z, the blocks are centered on z = 0
+pub const BLOCK_COLLIDER_DEPTH: f32 = 1.0;
+
+// Merges the solid blocks of the main layer into rectangles like the greedy mesher.
+// Unlike the meshes, different blocks are merged.
+pub fn chunk_collider_rects(data: &ChunkData, registry: &BlockRegistry) -> Vec<BlockRect> {
+    greedy_rects(|x, y| if registry.get(data.get_layer(Layer::Main, x, y).id).solid { Some(()) } else { None })
+        .into_iter()
+        .map(|(rect, _)| rect)
+        .collect()
+}
+
+// One cuboid per rectangle, relative to the origin of the chunk. None if the chunk has no solid block.
+pub fn chunk_collider_shape(rects: &[BlockRect]) -> Option<ShapeHandle<f32>> {
+    if rects.is_empty() {
+        return None;
+    }
+
+    let shapes = rects.iter()
+        .map(|rect| {
+            let (width, height) = (rect.width as f32, rect.height as f32);
+            // The tile of the block at (x, y) goes from y - 1 to y (see meshing.rs)
+            let center = Vector3::new(
+                rect.x as f32 + width / 2.0,
+                rect.y as f32 - 1.0 + height / 2.0,
+                0.0
+            );
+            let cuboid = Cuboid::new(Vector3::new(width / 2.0, height / 2.0, BLOCK_COLLIDER_DEPTH / 2.0));
+            (Isometry3::translation(center.x, center.y, center.z), ShapeHandle::new(cuboid))
+        })
+        .collect();
+
+    Some(ShapeHandle::new(Compound::new(shapes)))
+}
+
+// Replaces the static collider of the chunk, removes it if data is None or has no solid block
+pub fn update_chunk_collider(
+    physics: &mut PhysicsWorld,
+    registry: &BlockRegistry,
+    coords: (i32, i32),
+    data: Option<&ChunkData>
+) {
+    if let Some(handle) = physics.chunk_collider_handles.remove(&coords) {
+        if physics.world.collider(handle).is_some() {
+            physics.world.remove_colliders(&[handle]);
+        }
+    }
+
+    let shape = match data.and_then(|data| chunk_collider_shape(&chunk_collider_rects(data, registry))) {
+        Some(shape) => shape,
+        None => return
+    };
+
+    let size = CHUNK_SIZE as f32;
+    let collider_handle = ColliderDesc::new(shape)
+        .translation(Vector3::new(coords.0 as f32 * size, coords.1 as f32 * size, 0.0))
+        .build_with_parent(BodyPartHandle::ground(), &mut physics.world)
+        .unwrap()
+        .handle();
+
+    physics.chunk_collider_handles.insert(coords, collider_handle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Block, BlockDefinition};
+
+    fn registry() -> BlockRegistry {
+        let textures = vec!["leaves".to_string(), "stone".to_string(), "wood".to_string()];
+        let leaves = BlockDefinition { solid: false, ..BlockDefinition::new("leaves") };
+        BlockRegistry::from_definitions(vec![leaves], &textures).unwrap()
+    }
+
+    // Number of rectangles covering each block
+    fn coverage(rects: &[BlockRect]) -> Vec<u32> {
+        let mut coverage = vec![0; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+        for rect in rects {
+            assert!(rect.x + rect.width <= CHUNK_SIZE && rect.y + rect.height <= CHUNK_SIZE);
+            for y in rect.y..rect.y + rect.height {
+                for x in rect.x..rect.x + rect.width {
+                    coverage[(y * CHUNK_SIZE + x) as usize] += 1;
+                }
+            }
+        }
+        coverage
+    }
+
+    #[test]
+    fn rects_cover_the_solid_blocks_exactly_once() {
+        let registry = registry();
+        let block = |name| Block { id: registry.id(name).unwrap() };
+
+        let mut data = ChunkData::new();
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                // Different solid blocks, leaves and holes
+                let name = match (x * 7 + y * 3) % 5 {
+                    0 => continue,
+                    1 => "leaves",
+                    2 | 3 => "stone",
+                    _ => "wood",
+                };
+                data.set_layer(Layer::Main, x, y, block(name));
+            }
+        }
+        // The other layers don't collide
+        data.set_layer(Layer::Background, 0, 0, block("stone"));
+
+        let rects = chunk_collider_rects(&data, &registry);
+        let coverage = coverage(&rects);
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let solid = registry.get(data.get_layer(Layer::Main, x, y).id).solid;
+                assert_eq!(coverage[(y * CHUNK_SIZE + x) as usize], solid as u32, "block ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn different_solid_blocks_are_merged() {
+        let registry = registry();
+        let block = |name| Block { id: registry.id(name).unwrap() };
+
+        let mut data = ChunkData::new();
+        for x in 0..CHUNK_SIZE {
+            data.set_layer(Layer::Main, x, 0, block(if x % 2 == 0 { "stone" } else { "wood" }));
+            data.set_layer(Layer::Main, x, 1, block("leaves"));
+        }
+
+        assert_eq!(chunk_collider_rects(&data, &registry), vec![BlockRect { x: 0, y: 0, width: CHUNK_SIZE, height: 1 }]);
+        assert!(chunk_collider_rects(&ChunkData::new(), &registry).is_empty());
+    }
+}
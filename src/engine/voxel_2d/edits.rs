@@ -0,0 +1,35 @@
+use super::{Block, Layer, VoxelWorld};
+use std::collections::VecDeque;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VoxelEdit {
+    Set { layer: Layer, position: (i32, i32), block: Block },
+    // The corners are inclusive
+    FillRect { layer: Layer, from: (i32, i32), to: (i32, i32), block: Block },
+    Replace { layer: Layer, from: (i32, i32), to: (i32, i32), target: Block, replacement: Block },
+}
+
+// Specs resource where any system can queue block edits, they are applied
+// to the VoxelWorld by the VoxelEditSystem on the main thread
+#[derive(Default)]
+pub struct VoxelEditEvents {
+    pub queue: VecDeque<VoxelEdit>
+}
+
+impl VoxelEditEvents {
+    pub fn push(&mut self, edit: VoxelEdit) {
+        self.queue.push_back(edit);
+    }
+}
+
+impl VoxelWorld {
+    pub fn apply_edit(&mut self, edit: VoxelEdit) {
+        match edit {
+            VoxelEdit::Set { layer, position, block } => self.set_block(layer, position.0, position.1, block),
+            VoxelEdit::FillRect { layer, from, to, block } => self.fill_rect(layer, from, to, block),
+            VoxelEdit::Replace { layer, from, to, target, replacement } => {
+                self.replace(layer, from, to, target, replacement);
+            }
+        }
+    }
+}
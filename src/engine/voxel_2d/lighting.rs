@@ -0,0 +1,381 @@
+use super::{Block, BlockRegistry, CHUNK_SIZE};
+use std::collections::VecDeque;
+
+// Light level per channel, from 0 to MAX_LIGHT
+pub type LightColor = [u8; 3];
+
+pub const MAX_LIGHT: u8 = 15;
+pub const NO_LIGHT: LightColor = [0, 0, 0];
+
+const NEIGHBOURS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+// Light lost when going through a solid and opaque block
+pub const SOLID_ATTENUATION: u8 = 4;
+
+fn emission(registry: &BlockRegistry, block: Block) -> LightColor {
+    registry.get(block.id).light_emission
+}
+
+fn attenuation(registry: &BlockRegistry, block: Block) -> u8 {
+    let block_type = registry.get(block.id);
+    if !block_type.solid || block_type.transparent {
+        1
+    } else {
+        SOLID_ATTENUATION
+    }
+}
+
+// Block coordinates are in world space, not relative to a chunk
+pub trait LightWorld {
+    // None if the chunk containing the block isn't loaded
+    fn block(&self, x: i32, y: i32) -> Option<Block>;
+    fn light(&self, x: i32, y: i32) -> LightColor;
+    fn set_light(&mut self, x: i32, y: i32, light: LightColor);
+}
+
+fn set_channel(world: &mut dyn LightWorld, x: i32, y: i32, channel: usize, level: u8) {
+    let mut light = world.light(x, y);
+    light[channel] = level;
+    world.set_light(x, y, light);
+}
+
+fn propagate(world: &mut dyn LightWorld, registry: &BlockRegistry, channel: usize, mut queue: VecDeque<(i32, i32)>) {
+    while let Some((x, y)) = queue.pop_front() {
+        let level = world.light(x, y)[channel];
+        if level <= 1 {
+            continue;
+        }
+
+        for (dx, dy) in NEIGHBOURS.iter() {
+            let (nx, ny) = (x + dx, y + dy);
+            let block = match world.block(nx, ny) {
+                Some(block) => block,
+                None => continue
+            };
+
+            let new_level = level.saturating_sub(attenuation(registry, block));
+            if new_level > world.light(nx, ny)[channel] {
+                set_channel(world, nx, ny, channel, new_level);
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+}
+
+pub fn add_light_source(world: &mut dyn LightWorld, registry: &BlockRegistry, x: i32, y: i32, emission: LightColor) {
+    let current = world.light(x, y);
+    for channel in 0..3 {
+        if emission[channel] > current[channel] {
+            set_channel(world, x, y, channel, emission[channel]);
+            propagate(world, registry, channel, vec![(x, y)].into());
+        }
+    }
+}
+
+// Unlight-then-refill: clears the light that came from the cells of the queue, which had
+// the given levels, and propagates back the light coming from other sources
+fn unlight(world: &mut dyn LightWorld, registry: &BlockRegistry, channel: usize, mut removal: VecDeque<(i32, i32, u8)>) {
+    let mut refill = VecDeque::new();
+
+    while let Some((x, y, level)) = removal.pop_front() {
+        for (dx, dy) in NEIGHBOURS.iter() {
+            let (nx, ny) = (x + dx, y + dy);
+            let block = match world.block(nx, ny) {
+                Some(block) => block,
+                None => continue
+            };
+
+            let neighbour_level = world.light(nx, ny)[channel];
+            if neighbour_level != 0 && neighbour_level < level {
+                set_channel(world, nx, ny, channel, 0);
+                removal.push_back((nx, ny, neighbour_level));
+
+                // Other light sources in the cleared area are lit again
+                let emission = emission(registry, block)[channel];
+                if emission > 0 {
+                    set_channel(world, nx, ny, channel, emission);
+                    refill.push_back((nx, ny));
+                }
+            } else if neighbour_level >= level {
+                refill.push_back((nx, ny));
+            }
+        }
+    }
+
+    propagate(world, registry, channel, refill);
+}
+
+// Clears the light that went through the block
+pub fn remove_light(world: &mut dyn LightWorld, registry: &BlockRegistry, x: i32, y: i32) {
+    for channel in 0..3 {
+        let level = world.light(x, y)[channel];
+        if level == 0 {
+            continue;
+        }
+
+        set_channel(world, x, y, channel, 0);
+        unlight(world, registry, channel, vec![(x, y, level)].into());
+    }
+}
+
+// Must be called after the block at (x, y) has been changed
+pub fn update_light(world: &mut dyn LightWorld, registry: &BlockRegistry, x: i32, y: i32) {
+    remove_light(world, registry, x, y);
+
+    // The light around gets back into the block, with its new attenuation
+    for channel in 0..3 {
+        let queue = NEIGHBOURS.iter()
+            .map(|(dx, dy)| (x + dx, y + dy))
+            .filter(|(nx, ny)| world.light(*nx, *ny)[channel] > 0)
+            .collect();
+        propagate(world, registry, channel, queue);
+    }
+
+    if let Some(block) = world.block(x, y) {
+        let emission = emission(registry, block);
+        if emission != NO_LIGHT {
+            add_light_source(world, registry, x, y, emission);
+        }
+    }
+}
+
+// Lights a chunk that just got loaded: its own light sources spread and
+// the light of the loaded neighbours gets in
+pub fn light_chunk(world: &mut dyn LightWorld, registry: &BlockRegistry, chunk_coords: (i32, i32)) {
+    let size = CHUNK_SIZE as i32;
+    let (origin_x, origin_y) = (chunk_coords.0 * size, chunk_coords.1 * size);
+
+    for y in origin_y..origin_y + size {
+        for x in origin_x..origin_x + size {
+            if let Some(block) = world.block(x, y) {
+                let emission = emission(registry, block);
+                if emission != NO_LIGHT {
+                    add_light_source(world, registry, x, y, emission);
+                }
+            }
+        }
+    }
+
+    let mut border = Vec::new();
+    for i in 0..size {
+        border.push((origin_x - 1, origin_y + i));
+        border.push((origin_x + size, origin_y + i));
+        border.push((origin_x + i, origin_y - 1));
+        border.push((origin_x + i, origin_y + size));
+    }
+    for channel in 0..3 {
+        let queue = border.iter()
+            .cloned()
+            .filter(|(x, y)| world.light(*x, *y)[channel] > 0)
+            .collect();
+        propagate(world, registry, channel, queue);
+    }
+}
+
+// Light of the blocks on the edges of a chunk, see unlight_evicted_chunk
+pub fn chunk_edge_light(world: &dyn LightWorld, chunk_coords: (i32, i32)) -> Vec<(i32, i32, LightColor)> {
+    let size = CHUNK_SIZE as i32;
+    let (origin_x, origin_y) = (chunk_coords.0 * size, chunk_coords.1 * size);
+
+    let mut edges = Vec::new();
+    for i in 0..size {
+        edges.push((origin_x, origin_y + i));
+        edges.push((origin_x + size - 1, origin_y + i));
+        edges.push((origin_x + i, origin_y));
+        edges.push((origin_x + i, origin_y + size - 1));
+    }
+    edges.sort();
+    edges.dedup();
+
+    edges.into_iter()
+        .map(|(x, y)| (x, y, world.light(x, y)))
+        .filter(|(_, _, light)| *light != NO_LIGHT)
+        .collect()
+}
+
+// Clears the light that an evicted chunk spread into the loaded chunks around it.
+// `edge_light` is given by chunk_edge_light before the chunk is removed.
+pub fn unlight_evicted_chunk(world: &mut dyn LightWorld, registry: &BlockRegistry, edge_light: &[(i32, i32, LightColor)]) {
+    for channel in 0..3 {
+        let removal: VecDeque<(i32, i32, u8)> = edge_light.iter()
+            .filter(|(_, _, light)| light[channel] > 0)
+            .map(|(x, y, light)| (*x, *y, light[channel]))
+            .collect();
+        if !removal.is_empty() {
+            unlight(world, registry, channel, removal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_2d::{world_to_local, BlockDefinition};
+    use std::collections::{HashMap, HashSet};
+
+    // Chunks of air with a few blocks
+    struct TestWorld {
+        chunks: HashSet<(i32, i32)>,
+        blocks: HashMap<(i32, i32), Block>,
+        light: HashMap<(i32, i32), LightColor>,
+    }
+
+    impl LightWorld for TestWorld {
+        fn block(&self, x: i32, y: i32) -> Option<Block> {
+            if !self.chunks.contains(&world_to_local(x, y).0) {
+                return None;
+            }
+            Some(self.blocks.get(&(x, y)).cloned().unwrap_or_else(Block::air))
+        }
+
+        fn light(&self, x: i32, y: i32) -> LightColor {
+            self.light.get(&(x, y)).cloned().unwrap_or(NO_LIGHT)
+        }
+
+        fn set_light(&mut self, x: i32, y: i32, light: LightColor) {
+            if self.chunks.contains(&world_to_local(x, y).0) {
+                self.light.insert((x, y), light);
+            }
+        }
+    }
+
+    impl TestWorld {
+        fn new(chunks: &[(i32, i32)]) -> Self {
+            TestWorld { chunks: chunks.iter().cloned().collect(), blocks: HashMap::new(), light: HashMap::new() }
+        }
+
+        fn evict(&mut self, registry: &BlockRegistry, chunk_coords: (i32, i32)) {
+            let edge_light = chunk_edge_light(self, chunk_coords);
+            self.chunks.remove(&chunk_coords);
+            self.light.retain(|(x, y), _| world_to_local(*x, *y).0 != chunk_coords);
+            unlight_evicted_chunk(self, registry, &edge_light);
+        }
+    }
+
+    fn registry() -> BlockRegistry {
+        let torch = BlockDefinition {
+            solid: false,
+            light_emission: Some([MAX_LIGHT, 8, 0]),
+            ..BlockDefinition::new("torch")
+        };
+        BlockRegistry::from_definitions(vec![torch], &["stone".to_string(), "torch".to_string()]).unwrap()
+    }
+
+    fn torch(registry: &BlockRegistry) -> Block {
+        Block { id: registry.id("torch").unwrap() }
+    }
+
+    fn stone(registry: &BlockRegistry) -> Block {
+        Block { id: registry.id("stone").unwrap() }
+    }
+
+    // Chunks (0, 0) and (1, 0) lit from scratch with the blocks
+    fn lit_from_scratch(registry: &BlockRegistry, blocks: &[((i32, i32), Block)]) -> TestWorld {
+        let mut world = TestWorld::new(&[(0, 0), (1, 0)]);
+        world.blocks.extend(blocks.iter().cloned());
+        light_chunk(&mut world, registry, (0, 0));
+        light_chunk(&mut world, registry, (1, 0));
+        world
+    }
+
+    fn assert_same_light(world: &TestWorld, expected: &TestWorld) {
+        for y in 0..16 {
+            for x in 0..32 {
+                assert_eq!(world.light(x, y), expected.light(x, y), "at ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn emitters_light_across_the_seam_and_leave_no_light_behind() {
+        let registry = registry();
+        let mut world = lit_from_scratch(&registry, &[]);
+
+        world.blocks.insert((14, 8), torch(&registry));
+        update_light(&mut world, &registry, 14, 8);
+        assert_eq!(world.light(14, 8), [MAX_LIGHT, 8, 0]);
+        assert_eq!(world.light(16, 8), [MAX_LIGHT - 2, 6, 0]);
+        assert_eq!(world.light(20, 10), [MAX_LIGHT - 8, 0, 0]);
+        assert_same_light(&world, &lit_from_scratch(&registry, &[((14, 8), torch(&registry))]));
+
+        world.blocks.remove(&(14, 8));
+        update_light(&mut world, &registry, 14, 8);
+        assert!(world.light.values().all(|light| *light == NO_LIGHT));
+    }
+
+    #[test]
+    fn removing_an_emitter_refills_the_light_of_the_others() {
+        let registry = registry();
+        let (left, right) = (((13, 8), torch(&registry)), ((18, 9), torch(&registry)));
+        let mut world = lit_from_scratch(&registry, &[left, right]);
+
+        world.blocks.remove(&left.0);
+        update_light(&mut world, &registry, (left.0).0, (left.0).1);
+        assert_same_light(&world, &lit_from_scratch(&registry, &[right]));
+
+        world.blocks.insert(left.0, left.1);
+        update_light(&mut world, &registry, (left.0).0, (left.0).1);
+        assert_same_light(&world, &lit_from_scratch(&registry, &[left, right]));
+    }
+
+    #[test]
+    fn opaque_blocks_on_the_seam_dim_the_light() {
+        let registry = registry();
+        let emitter = ((13, 8), torch(&registry));
+        let mut world = lit_from_scratch(&registry, &[emitter]);
+        let before = world.light(16, 8);
+
+        // On the last column of chunk (0, 0), then the first of chunk (1, 0)
+        for &wall in &[(15, 8), (16, 8)] {
+            world.blocks.insert(wall, stone(&registry));
+            update_light(&mut world, &registry, wall.0, wall.1);
+            assert_same_light(&world, &lit_from_scratch(&registry, &[emitter, (wall, stone(&registry))]));
+            assert!(world.light(17, 8)[0] < before[0] - 1);
+
+            world.blocks.remove(&wall);
+            update_light(&mut world, &registry, wall.0, wall.1);
+            assert_same_light(&world, &lit_from_scratch(&registry, &[emitter]));
+            assert_eq!(world.light(16, 8), before);
+        }
+    }
+
+    #[test]
+    fn evicted_light_is_removed_from_neighbours() {
+        let registry = registry();
+        let mut world = TestWorld::new(&[(0, 0), (1, 0)]);
+        world.blocks.insert((14, 8), torch(&registry));
+        light_chunk(&mut world, &registry, (0, 0));
+        light_chunk(&mut world, &registry, (1, 0));
+        assert_eq!(world.light(16, 8), [MAX_LIGHT - 2, 6, 0]);
+
+        world.evict(&registry, (0, 0));
+        assert!(world.light.values().all(|light| *light == NO_LIGHT));
+    }
+
+    #[test]
+    fn eviction_keeps_the_light_of_the_neighbours() {
+        let registry = registry();
+        let mut world = TestWorld::new(&[(0, 0), (1, 0), (2, 0)]);
+        world.blocks.insert((14, 8), torch(&registry));
+        world.blocks.insert((22, 8), torch(&registry));
+        for x in 0..3 {
+            light_chunk(&mut world, &registry, (x, 0));
+        }
+
+        // Lit by the torch of the evicted chunk
+        assert_eq!(world.light(17, 8), [MAX_LIGHT - 3, 5, 0]);
+        world.evict(&registry, (0, 0));
+
+        // What the second torch gives on its own
+        let mut expected = TestWorld::new(&[(1, 0), (2, 0)]);
+        expected.blocks.insert((22, 8), torch(&registry));
+        light_chunk(&mut expected, &registry, (1, 0));
+        light_chunk(&mut expected, &registry, (2, 0));
+        for y in 0..16 {
+            for x in 16..48 {
+                assert_eq!(world.light(x, y), expected.light(x, y), "at ({}, {})", x, y);
+            }
+        }
+        assert_eq!(world.light(17, 8), [MAX_LIGHT - 5, 3, 0]);
+    }
+}
@@ -0,0 +1,407 @@
+use super::{Block, BlockRegistry, BlockSprite, ChunkData, Layer, RenderLayer, CHUNK_SIZE};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+
+// Vertex and index buffers of a chunk, built without touching GL
+#[derive(Clone, Debug, Default)]
+pub struct ChunkMesh {
+    // x, y
+    pub positions: Vec<f32>,
+    // Coordinates in tiles (they go past 1 on merged quads) and atlas layer
+    pub tex_coords: Vec<f32>,
+    // u0, v0, u1, v1 of the sprite, the shader wraps the tile coordinates inside it
+    pub atlas_rects: Vec<f32>,
+    // Frame count, frame time and frame stride, the shader picks the current frame
+    pub animations: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+impl ChunkMesh {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len() / 2
+    }
+
+    pub fn quad_count(&self) -> usize {
+        self.indices.len() / 6
+    }
+}
+
+// Meshes of one layer of a chunk
+#[derive(Clone, Debug, Default)]
+pub struct LayerMeshes {
+    // Opaque and cutout blocks
+    pub opaque: ChunkMesh,
+    // Drawn after the opaque meshes of every layer, see VoxelWorld::render
+    pub translucent: ChunkMesh,
+}
+
+// Quad covering `width` x `height` blocks, the block (x, y) being its top left one.
+// The tile of the block at (x, y) goes from y - 1 to y.
+fn gen_tile(mesh: &mut ChunkMesh, x: f32, y: f32, width: f32, height: f32, sprite: &BlockSprite) {
+    let i = mesh.vertex_count() as u32;
+    let top = y + height - 1.0;
+
+    // Counter clockwise when seen from the camera, so that back faces can be culled
+    mesh.positions.extend_from_slice(&[
+        x, top,
+        x, top - height,
+        x + width, top - height,
+        x + width, top,
+    ]);
+
+    let uv = &sprite.rect;
+    let animation = &sprite.animation;
+    let layer = uv.layer as f32;
+    mesh.tex_coords.extend_from_slice(&[
+        0.0, height, layer,
+        0.0, 0.0, layer,
+        width, 0.0, layer,
+        width, height, layer,
+    ]);
+
+    for _ in 0..4 {
+        mesh.atlas_rects.extend_from_slice(&[uv.u0, uv.v0, uv.u1, uv.v1]);
+        mesh.animations.extend_from_slice(&[
+            animation.frame_count as f32,
+            animation.frame_time,
+            animation.frame_stride
+        ]);
+    }
+
+    mesh.indices.extend_from_slice(&[i, i + 1, i + 2, i + 2, i + 3, i]);
+}
+
+// One quad per block
+pub fn mesh_chunk_naive(data: &ChunkData, layer: Layer, block_sprites: &[BlockSprite]) -> ChunkMesh {
+    let mut mesh = ChunkMesh::default();
+    for y in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            let block = data.get_layer(layer, x, y);
+            if !block.is_air() {
+                gen_tile(&mut mesh, x as f32, y as f32, 1.0, 1.0, &block_sprites[block.id.0 as usize]);
+            }
+        }
+    }
+    mesh
+}
+
+// Rectangle of blocks inside a chunk, (x, y) being its bottom left block
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlockRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Merges the neighbouring blocks with the same key into rectangles, grown along x first
+// and then along y. The blocks without a key are left out.
+pub fn greedy_rects<K: PartialEq>(key: impl Fn(u32, u32) -> Option<K>) -> Vec<(BlockRect, K)> {
+    let mut rects = Vec::new();
+    let mut merged = [false; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+    let index = |x: u32, y: u32| (y * CHUNK_SIZE + x) as usize;
+
+    for y in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            if merged[index(x, y)] {
+                continue;
+            }
+            let current = match key(x, y) {
+                Some(current) => current,
+                None => continue
+            };
+            let same = |x: u32, y: u32| !merged[index(x, y)] && key(x, y).as_ref() == Some(&current);
+
+            let mut width = 1;
+            while x + width < CHUNK_SIZE && same(x + width, y) {
+                width += 1;
+            }
+
+            let mut height = 1;
+            while y + height < CHUNK_SIZE && (0..width).all(|dx| same(x + dx, y + height)) {
+                height += 1;
+            }
+
+            for dy in 0..height {
+                for dx in 0..width {
+                    merged[index(x + dx, y + dy)] = true;
+                }
+            }
+
+            rects.push((BlockRect { x, y, width, height }, current));
+        }
+    }
+    rects
+}
+
+// Greedy meshing: the identical neighbouring blocks are merged into rectangles
+pub fn mesh_chunk(data: &ChunkData, layer: Layer, block_sprites: &[BlockSprite]) -> ChunkMesh {
+    mesh_blocks(data, layer, block_sprites, |_| true)
+}
+
+// Greedy meshing of the blocks accepted by `include`
+fn mesh_blocks(data: &ChunkData, layer: Layer, block_sprites: &[BlockSprite], include: impl Fn(Block) -> bool) -> ChunkMesh {
+    let mut mesh = ChunkMesh::default();
+    let rects = greedy_rects(|x, y| {
+        let block = data.get_layer(layer, x, y);
+        if block.is_air() || !include(block) { None } else { Some(block) }
+    });
+
+    for (rect, block) in rects {
+        gen_tile(
+            &mut mesh,
+            rect.x as f32,
+            rect.y as f32,
+            rect.width as f32,
+            rect.height as f32,
+            &block_sprites[block.id.0 as usize]
+        );
+    }
+    mesh
+}
+
+// Greedy meshes of every layer, indexed by Layer::index
+pub fn mesh_chunk_layers(data: &ChunkData, block_sprites: &[BlockSprite], registry: &BlockRegistry) -> Vec<LayerMeshes> {
+    let translucent = |block: Block| registry.get(block.id).render_layer == RenderLayer::Translucent;
+    Layer::ALL.iter()
+        .map(|&layer| LayerMeshes {
+            opaque: mesh_blocks(data, layer, block_sprites, |block| !translucent(block)),
+            translucent: mesh_blocks(data, layer, block_sprites, translucent),
+        })
+        .collect()
+}
+
+// Coordinates, version and meshes of every layer
+type MeshedChunk = ((i32, i32), u64, Vec<LayerMeshes>);
+
+struct MeshJob {
+    coords: (i32, i32),
+    version: u64,
+    data: ChunkData,
+}
+
+// Meshes chunks on a background thread, the buffers are uploaded on the main thread.
+// Every request gets a new version, the results of older requests for the same chunk are dropped.
+pub struct ChunkMesher {
+    // Behind mutexes so that the voxel world can be shared as a specs resource
+    jobs: Mutex<Sender<MeshJob>>,
+    meshed: Mutex<Receiver<MeshedChunk>>,
+    // Latest requested version of each chunk, shared with the worker so that stale jobs are skipped
+    pending: Arc<Mutex<HashMap<(i32, i32), u64>>>,
+    next_version: u64,
+}
+
+impl ChunkMesher {
+    // The registry gives the render layer of the blocks
+    pub fn new(block_sprites: Arc<Vec<BlockSprite>>, registry: Arc<BlockRegistry>) -> Self {
+        let (jobs, jobs_rx) = channel::<MeshJob>();
+        let (meshed_tx, meshed) = channel();
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+
+        let worker_pending = pending.clone();
+        thread::Builder::new()
+            .name("chunk_mesher".into())
+            .spawn(move || {
+                // Stops when the mesher (and its sender) is dropped
+                for job in jobs_rx.iter() {
+                    let MeshJob { coords, version, data } = job;
+                    if worker_pending.lock().unwrap().get(&coords) != Some(&version) {
+                        continue;
+                    }
+
+                    let meshes = mesh_chunk_layers(&data, &block_sprites, &registry);
+                    if meshed_tx.send((coords, version, meshes)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Unable to spawn the chunk meshing thread");
+
+        ChunkMesher { jobs: Mutex::new(jobs), meshed: Mutex::new(meshed), pending, next_version: 0 }
+    }
+
+    // Replaces the previous request for the same chunk
+    pub fn request(&mut self, coords: (i32, i32), data: ChunkData) {
+        self.next_version += 1;
+        let version = self.next_version;
+        self.pending.lock().unwrap().insert(coords, version);
+        self.jobs.lock().unwrap().send(MeshJob { coords, version, data }).expect("Chunk meshing thread died");
+    }
+
+    pub fn cancel(&self, coords: (i32, i32)) {
+        self.pending.lock().unwrap().remove(&coords);
+    }
+
+    pub fn cancel_all(&self) {
+        self.pending.lock().unwrap().clear();
+    }
+
+    pub fn is_pending(&self, coords: (i32, i32)) -> bool {
+        self.pending.lock().unwrap().contains_key(&coords)
+    }
+
+    // The meshes of every layer of a chunk
+    pub fn poll(&self) -> Option<((i32, i32), Vec<LayerMeshes>)> {
+        loop {
+            let (coords, version, meshes) = self.meshed.lock().unwrap().try_recv().ok()?;
+            // Discard the meshes of cancelled or outdated requests
+            let mut pending = self.pending.lock().unwrap();
+            if pending.get(&coords) == Some(&version) {
+                pending.remove(&coords);
+                return Some((coords, meshes));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_2d::{BlockDefinition, BlockId};
+    use std::time::{Duration, Instant};
+
+    // The sprite of each block is told apart by its u0
+    fn sprites(count: usize) -> Vec<BlockSprite> {
+        (0..count)
+            .map(|id| {
+                let mut sprite = BlockSprite::default();
+                sprite.rect.u0 = id as f32;
+                sprite
+            })
+            .collect()
+    }
+
+    fn patterned_chunk() -> ChunkData {
+        let mut data = ChunkData::new();
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let id = match (x, y) {
+                    (_, 0..=3) => 1,
+                    (0..=7, 4..=9) => 2,
+                    _ => ((x * 7 + y * 13) % 4) as u16,
+                };
+                data.set(x, y, Block { id: BlockId(id) });
+                data.set_layer(Layer::Foreground, x, y, Block { id: BlockId(if x == y { 3 } else { 0 }) });
+            }
+        }
+        data
+    }
+
+    // Sprite id drawn on each block, panics if a block is covered twice
+    fn rasterize(mesh: &ChunkMesh) -> Vec<Option<u32>> {
+        let mut cells = vec![None; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+        for quad in 0..mesh.quad_count() {
+            let vertices = &mesh.positions[quad * 8..quad * 8 + 8];
+            let xs = [vertices[0], vertices[2], vertices[4], vertices[6]];
+            let ys = [vertices[1], vertices[3], vertices[5], vertices[7]];
+            let min = |v: &[f32; 4]| v.iter().cloned().fold(std::f32::INFINITY, f32::min) as i32;
+            let max = |v: &[f32; 4]| v.iter().cloned().fold(std::f32::NEG_INFINITY, f32::max) as i32;
+            let sprite = mesh.atlas_rects[quad * 16] as u32;
+
+            // The tile of the block at (x, y) goes from y - 1 to y
+            for y in min(&ys) + 1..=max(&ys) {
+                for x in min(&xs)..max(&xs) {
+                    let cell = &mut cells[(y as u32 * CHUNK_SIZE + x as u32) as usize];
+                    assert!(cell.is_none(), "block ({}, {}) is covered twice", x, y);
+                    *cell = Some(sprite);
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn greedy_mesh_covers_the_naive_faces() {
+        let sprites = sprites(4);
+        let mut uniform = ChunkData::new();
+        for b in uniform.layers[Layer::Main.index()].iter_mut() {
+            *b = Block { id: BlockId(2) };
+        }
+
+        for data in &[patterned_chunk(), uniform.clone(), ChunkData::new()] {
+            for &layer in Layer::ALL.iter() {
+                let naive = mesh_chunk_naive(data, layer, &sprites);
+                let greedy = mesh_chunk(data, layer, &sprites);
+                assert_eq!(rasterize(&greedy), rasterize(&naive), "layer {:?}", layer);
+                assert!(greedy.quad_count() <= naive.quad_count());
+                assert_eq!(greedy.vertex_count(), 4 * greedy.quad_count());
+            }
+        }
+
+        assert_eq!(mesh_chunk(&uniform, Layer::Main, &sprites).quad_count(), 1);
+        assert_eq!(mesh_chunk_naive(&uniform, Layer::Main, &sprites).quad_count(), 256);
+    }
+
+    // Blocks 1 to 3, the third one is translucent
+    fn registry() -> BlockRegistry {
+        let translucent = BlockDefinition { render_layer: RenderLayer::Translucent, ..BlockDefinition::new("c") };
+        let textures: Vec<String> = ["a", "b", "c"].iter().map(|name| name.to_string()).collect();
+        BlockRegistry::from_definitions(vec![BlockDefinition::new("a"), BlockDefinition::new("b"), translucent], &textures).unwrap()
+    }
+
+    #[test]
+    fn translucent_blocks_get_their_own_mesh() {
+        let (sprites, registry) = (sprites(4), registry());
+        let data = patterned_chunk();
+        let meshes = mesh_chunk_layers(&data, &sprites, &registry);
+
+        for &layer in Layer::ALL.iter() {
+            let (opaque, translucent) = (rasterize(&meshes[layer.index()].opaque), rasterize(&meshes[layer.index()].translucent));
+            let all = rasterize(&mesh_chunk(&data, layer, &sprites));
+            for (i, cell) in all.iter().enumerate() {
+                match cell {
+                    Some(3) => assert!(opaque[i].is_none() && translucent[i] == Some(3)),
+                    _ => assert!(opaque[i] == *cell && translucent[i].is_none()),
+                }
+            }
+        }
+        // The diagonal of the foreground
+        assert_eq!(meshes[Layer::Foreground.index()].opaque.quad_count(), 0);
+        assert_eq!(meshes[Layer::Foreground.index()].translucent.quad_count(), CHUNK_SIZE as usize);
+    }
+
+    fn wait_for_mesh(mesher: &ChunkMesher) -> ((i32, i32), Vec<LayerMeshes>) {
+        let start = Instant::now();
+        loop {
+            if let Some(meshed) = mesher.poll() {
+                return meshed;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "the mesher never answered");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn stale_mesh_versions_are_dropped() {
+        let (sprites, registry) = (sprites(4), Arc::new(registry()));
+        let mut mesher = ChunkMesher::new(Arc::new(sprites.clone()), registry.clone());
+
+        mesher.request((1, 0), patterned_chunk());
+        mesher.cancel((1, 0));
+        assert!(!mesher.is_pending((1, 0)));
+
+        // Only the last request of the chunk is meshed
+        mesher.request((0, 0), patterned_chunk());
+        mesher.request((0, 0), ChunkData::new());
+        let mut latest = ChunkData::new();
+        latest.set(4, 5, Block { id: BlockId(1) });
+        mesher.request((0, 0), latest.clone());
+        assert!(mesher.is_pending((0, 0)));
+
+        let (coords, meshes) = wait_for_mesh(&mesher);
+        assert_eq!(coords, (0, 0));
+        let expected = mesh_chunk_layers(&latest, &sprites, &registry);
+        assert_eq!(meshes.len(), expected.len());
+        for (meshes, expected) in meshes.iter().zip(&expected) {
+            assert_eq!(meshes.opaque.positions, expected.opaque.positions);
+            assert_eq!(meshes.opaque.indices, expected.opaque.indices);
+            assert_eq!(meshes.translucent.positions, expected.translucent.positions);
+        }
+
+        // The jobs are handled in order, the older ones are done by now
+        assert!(!mesher.is_pending((0, 0)));
+        assert!(mesher.poll().is_none());
+    }
+}
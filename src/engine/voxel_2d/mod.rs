@@ -1,39 +1,157 @@
 use crate::gl_wrapper::*;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use image::RgbaImage;
 use crate::containers::CONTAINER;
-use std::ffi::c_void;
 use crate::shaders::voxel::VoxelShader;
+use std::sync::{Arc, Mutex};
+use nalgebra_glm::{Vec2, Vec3, vec3};
+use crate::shaders::outline::OutlineData;
+use crate::shaders::ShaderData;
+use crate::ecs::resources::{PhysicsWorld, Time};
+use nalgebra::Matrix4;
+
+mod terrain;
+mod streaming;
+mod region;
+mod lighting;
+mod registry;
+mod atlas;
+mod picking;
+mod meshing;
+mod collision;
+mod edits;
+
+pub use terrain::*;
+pub use streaming::*;
+pub use region::*;
+pub use lighting::*;
+pub use registry::*;
+pub use atlas::*;
+pub use picking::*;
+pub use meshing::*;
+pub use collision::*;
+pub use edits::*;
+
+pub const CHUNK_SIZE: u32 = 16;
 
 #[derive(Copy)]
 #[derive(Clone)]
+#[derive(Debug, PartialEq)]
 pub struct Block {
-    pub id: &'static str
+    pub id: BlockId
 }
 
 impl Block {
-    fn air() -> Self {
-        Block { id: "air" }
+    pub fn air() -> Self {
+        Block { id: BlockId::AIR }
+    }
+
+    pub fn is_air(&self) -> bool {
+        self.id == BlockId::AIR
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Layer {
+    // Walls behind the world, darkened and never colliding
+    Background,
+    // The solid blocks, the only layer used by the lighting and the collisions
+    Main,
+    // Decorations drawn over the entities
+    Foreground,
+}
+
+pub const LAYER_COUNT: usize = 3;
+
+impl Layer {
+    // In draw order
+    pub const ALL: [Layer; LAYER_COUNT] = [Layer::Background, Layer::Main, Layer::Foreground];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    // z of the tiles, the entities are around 0
+    pub fn depth(self) -> f32 {
+        match self {
+            Layer::Background => -0.5,
+            Layer::Main => 0.0,
+            Layer::Foreground => 0.5,
+        }
+    }
+
+    // Multiplies the color of the tiles
+    pub fn brightness(self) -> f32 {
+        match self {
+            Layer::Background => 0.5,
+            Layer::Main | Layer::Foreground => 1.0,
+        }
     }
 }
 
 pub struct BlockCatalog {
-    pub blocks_texture_atlas: Texture2D,
-    pub block_types: HashMap<String, (u32, u32)>
+    pub blocks_texture_atlas: Texture2DArray,
+    pub registry: Arc<BlockRegistry>,
+    // Texture name -> atlas sprite
+    pub block_types: HashMap<String, BlockSprite>,
+    // Atlas sprites indexed by block id
+    pub block_sprites: Vec<BlockSprite>
 }
 
-pub struct Chunk {
-    blocks: [Block; 256],
-    mesh: VAO,
+// Splits world block coordinates into chunk coordinates and coordinates inside the chunk
+pub fn world_to_local(x: i32, y: i32) -> ((i32, i32), (u32, u32)) {
+    let size = CHUNK_SIZE as i32;
+    (
+        (x.div_euclid(size), y.div_euclid(size)),
+        (x.rem_euclid(size) as u32, y.rem_euclid(size) as u32),
+    )
+}
+
+// Block storage of a chunk, kept apart from the GL objects so it can be
+// generated and inspected without a context
+#[derive(Clone)]
+pub struct ChunkData {
+    // Indexed by Layer::index
+    pub layers: [[Block; 256]; LAYER_COUNT]
+}
+
+impl ChunkData {
+    pub fn new() -> Self {
+        ChunkData { layers: [[Block::air(); 256]; LAYER_COUNT] }
+    }
+
+    // Main layer
+    pub fn get(&self, x: u32, y: u32) -> Block {
+        self.get_layer(Layer::Main, x, y)
+    }
+
+    // Main layer
+    pub fn set(&mut self, x: u32, y: u32, block: Block) {
+        self.set_layer(Layer::Main, x, y, block);
+    }
+
+    pub fn get_layer(&self, layer: Layer, x: u32, y: u32) -> Block {
+        self.layers[layer.index()][(y * CHUNK_SIZE + x) as usize]
+    }
+
+    pub fn set_layer(&mut self, layer: Layer, x: u32, y: u32, block: Block) {
+        self.layers[layer.index()][(y * CHUNK_SIZE + x) as usize] = block;
+    }
+}
+
+// GL buffers of one layer of a chunk
+struct LayerMesh {
+    vao: VAO,
     positions: VBO,
     tex_coords: VBO,
+    atlas_rects: VBO,
+    animations: VBO,
     indices: EBO,
-
-    lightmap: Texture2D
 }
 
-impl Chunk {
-    pub fn new() -> Self {
+impl LayerMesh {
+    fn new() -> Self {
         let positions = VBO::new(vec![
             VertexAttribute {
                 index: 0,
@@ -42,226 +160,693 @@ impl Chunk {
         ]);
 
         let tex_coords = VBO::new(vec![
+            // Tile coordinates, atlas layer
             VertexAttribute {
                 index: 1,
-                components: 2
+                components: 3
+            },
+        ]);
+
+        let atlas_rects = VBO::new(vec![
+            VertexAttribute {
+                index: 2,
+                components: 4
+            },
+        ]);
+
+        // Frame count, frame time, frame stride
+        let animations = VBO::new(vec![
+            VertexAttribute {
+                index: 3,
+                components: 3
             },
         ]);
 
         let indices = EBO::new();
 
-        Chunk {
-            blocks: [Block::air(); 256],
-            mesh: VAO::new(&[positions.clone(), tex_coords.clone()], Some(&indices)),
+        LayerMesh {
+            vao: VAO::new(
+                &[positions.clone(), tex_coords.clone(), atlas_rects.clone(), animations.clone()],
+                Some(&indices)
+            ),
             positions,
             tex_coords,
+            atlas_rects,
+            animations,
             indices,
+        }
+    }
+
+    // GL side of the meshing, the mesh is built by the ChunkMesher
+    fn upload(&mut self, mesh: &ChunkMesh) {
+        self.positions.with(&mesh.positions, BufferUpdateFrequency::Never);
+        self.tex_coords.with(&mesh.tex_coords, BufferUpdateFrequency::Never);
+        self.atlas_rects.with(&mesh.atlas_rects, BufferUpdateFrequency::Never);
+        self.animations.with(&mesh.animations, BufferUpdateFrequency::Never);
+        self.indices.with(&mesh.indices, BufferUpdateFrequency::Never);
+    }
+}
+
+impl Drop for LayerMesh {
+    fn drop(&mut self) {
+        // VBO and EBO don't delete themselves (see their TODOs), the VAO does
+        gl_call!(gl::DeleteBuffers(1, &self.positions.id));
+        gl_call!(gl::DeleteBuffers(1, &self.tex_coords.id));
+        gl_call!(gl::DeleteBuffers(1, &self.atlas_rects.id));
+        gl_call!(gl::DeleteBuffers(1, &self.animations.id));
+        gl_call!(gl::DeleteBuffers(1, &self.indices.id));
+    }
+}
+
+// A loaded chunk. Its GL objects are in the ChunkRenderData of the same coordinates,
+// so that the world can be edited without a context.
+pub struct Chunk {
+    data: ChunkData,
+    // Edited since it was loaded, it must be saved before being evicted
+    modified: bool,
+
+    light: [LightColor; 256],
+    // The lightmap must be uploaded again
+    light_dirty: bool,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::from_data(ChunkData::new())
+    }
+
+    pub fn from_data(data: ChunkData) -> Self {
+        Chunk {
+            data,
+            modified: false,
+            light: [NO_LIGHT; 256],
+            light_dirty: true,
+        }
+    }
+
+    pub fn add_block(&mut self, layer: Layer, x: u32, y: u32, block: Block) {
+        self.data.set_layer(layer, x, y, block);
+        self.modified = true;
+    }
+
+    pub fn remove_block(&mut self, layer: Layer, x: u32, y: u32) {
+        self.data.set_layer(layer, x, y, Block::air());
+        self.modified = true;
+    }
+}
+
+// GL objects of a chunk, created when it is first rendered
+struct ChunkRenderData {
+    // Indexed by Layer::index
+    meshes: Vec<LayerMesh>,
+    translucent_meshes: Vec<LayerMesh>,
+    lightmap: Texture2D
+}
+
+impl ChunkRenderData {
+    fn new() -> Self {
+        ChunkRenderData {
+            meshes: Layer::ALL.iter().map(|_| LayerMesh::new()).collect(),
+            translucent_meshes: Layer::ALL.iter().map(|_| LayerMesh::new()).collect(),
             // TODO Put length inside buffer
             lightmap: {
                 let mut lightmap = Texture2D::new();
-                lightmap.allocate(TextureFormat::RGB, 16, 16, 1);
+                lightmap.allocate(TextureFormat::RGB, CHUNK_SIZE, CHUNK_SIZE, 1);
                 lightmap
             }
         }
     }
 
-    pub fn add_block(&mut self, x: u32, y: u32, block: Block) {
-        self.blocks[(y * 16 + x) as usize] = block;
-        // TODO Modify lightmap if it's a light source
+    // One pair of meshes per layer
+    fn upload_meshes(&mut self, meshes: &[LayerMeshes]) {
+        for (i, meshes) in meshes.iter().enumerate() {
+            self.meshes[i].upload(&meshes.opaque);
+            self.translucent_meshes[i].upload(&meshes.translucent);
+        }
     }
 
-    pub fn remove_block(&mut self, x: u32, y: u32) {
-        self.blocks[(y * 16 + x) as usize] = Block::air();
-        // TODO Regen lightmap if it's a light source
+    fn upload_lightmap(&mut self, light: &[LightColor; 256]) {
+        let mut pixels = Vec::with_capacity(256 * 3);
+        for light in light.iter() {
+            // Levels from 0 to MAX_LIGHT are mapped to 0-255
+            pixels.extend(light.iter().map(|level| level * (255 / MAX_LIGHT)));
+        }
+
+        let img = image::RgbImage::from_raw(CHUNK_SIZE, CHUNK_SIZE, pixels).unwrap();
+        self.lightmap.update(0, 0, &image::DynamicImage::ImageRgb8(img));
     }
+}
 
-    fn regen_mesh(&mut self) {
-        let block_catalog = CONTAINER.get_local::<BlockCatalog>();
-        let mut vec_positions = Vec::<f32>::new();
-        let mut vec_tex_coords = Vec::<f32>::new();
-        let mut vec_indices = Vec::<u32>::new();
-
-        let mut i = 0;
-        for y in 0..16 {
-            for x in 0..16 {
-                let block_id = self.blocks[16 * y + x].id;
-                if block_id != "air" {
-                    let (tex_x, tex_y) = block_catalog.block_types.get(block_id).cloned().unwrap();
-                    let (tex_x, tex_y) = (tex_x as f32 / 128.0, tex_y as f32 / 128.0);
-                    let (tex_w, tex_h) = (16.0 / 128.0, 16.0 / 128.0);
-
-                    println!("Regen i x y {} {} {}", i, x, y);
-                    let (tile, tex_coords, indices) = gen_tile(
-                        i as u32,
-                        x as f32,
-                        y as f32,
-                        1.0,
-                        1.0,
-                        tex_x,
-                        tex_y,
-                        tex_w,
-                        tex_h
-                    );
-                    i += 1;
-
-                    vec_positions.extend_from_slice(&tile);
-                    vec_tex_coords.extend_from_slice(&tex_coords);
-                    vec_indices.extend_from_slice(&indices);
-                }
-            }
+// GL side of the VoxelWorld, created by the first render
+struct VoxelRenderer {
+    mesher: ChunkMesher,
+    chunks: HashMap<(i32, i32), ChunkRenderData>,
+    highlight_mesh: VAO,
+    highlight_positions: VBO
+}
+
+impl VoxelRenderer {
+    fn new() -> Self {
+        let mut highlight_positions = VBO::new(vec![
+            VertexAttribute {
+                index: 0,
+                components: 3,
+            },
+        ]);
+        highlight_positions.with(&gen_outline(0.06), BufferUpdateFrequency::Never);
+
+        VoxelRenderer {
+            mesher: {
+                let block_catalog = CONTAINER.get_local::<BlockCatalog>();
+                ChunkMesher::new(Arc::new(block_catalog.block_sprites.clone()), block_catalog.registry.clone())
+            },
+            chunks: HashMap::new(),
+            highlight_mesh: VAO::new(&[highlight_positions.clone()], None),
+            highlight_positions
         }
+    }
 
-        self.positions.with(&vec_positions, BufferUpdateFrequency::Never);
-        self.tex_coords.with(&vec_tex_coords, BufferUpdateFrequency::Never);
-        self.indices.with(&vec_indices, BufferUpdateFrequency::Never);
-//        gl_call!(gl::NamedBufferData(self.positions.id, 4 * vec_positions.len() as isize, vec_positions.as_ptr() as *mut c_void, gl::STATIC_DRAW));
-//        gl_call!(gl::NamedBufferData(self.tex_coords.id, 4 * vec_tex_coords.len() as isize, vec_tex_coords.as_ptr() as *mut c_void, gl::STATIC_DRAW));
-//        gl_call!(gl::NamedBufferData(self.indices.id, 4 * vec_indices.len() as isize, vec_indices.as_ptr() as *mut c_void, gl::STATIC_DRAW));
+    fn remove_chunk(&mut self, coords: (i32, i32)) {
+        self.mesher.cancel(coords);
+        self.chunks.remove(&coords);
     }
 
-    fn add_light_source(&mut self) {
-        unimplemented!();
+    fn clear(&mut self) {
+        self.mesher.cancel_all();
+        self.chunks.clear();
     }
+}
 
-    fn remove_light_source(&mut self) {
-        unimplemented!();
+impl Drop for VoxelRenderer {
+    fn drop(&mut self) {
+        gl_call!(gl::DeleteBuffers(1, &self.highlight_positions.id));
     }
 }
 
 pub struct ResourceManager;
 
 impl ResourceManager {
-    pub fn gen_blocks_texture_atlas(dir: &Path) -> BlockCatalog {
-        let mut atlas = Texture2D::new();
-        let (atlas_width, atlas_height) = (128, 128);
-        atlas.allocate(TextureFormat::RGBA, atlas_width, atlas_height, 1);
-
-        let mut blocks = HashMap::new();
-
-        assert!(dir.is_dir());
-        println!("Entries:");
-        let mut x = 0;
-        let mut y = 0;
-        for entry in dir.read_dir().expect("Unable to read textures directory") {
-            if let Ok(entry) = entry {
-                if !entry.path().is_file() {
-                    continue;
-                }
-                assert!(y < atlas_height, "Texture atlas is full!");
+    // The block definitions are read from the .ron file next to the textures directory,
+    // e.g. textures/blocks.ron for textures/blocks
+    pub fn gen_blocks_texture_atlas(dir: &Path) -> Result<BlockCatalog, RegistryError> {
+        if !dir.is_dir() {
+            return Err(RegistryError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is not a directory", dir.display())
+            )));
+        }
+        let mut texture_paths = Vec::new();
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+            if path.is_file() {
+                texture_paths.push(path);
+            }
+        }
+        texture_paths.sort();
 
-                // Update atlas
-                let img = image::open(entry.path());
-                let img = match img {
-                    Ok(img) => img.flipv(),
-                    Err(err) => panic!("Could not open block texture image"),
-                };
+        let texture_names: Vec<String> = texture_paths.iter()
+            .map(|path| path.file_stem().unwrap().to_str().unwrap().to_owned())
+            .collect();
 
-                atlas.update(x, y, &img);
+        let definitions_path = dir.with_extension("ron");
+        let registry = if definitions_path.is_file() {
+            BlockRegistry::load(&definitions_path, &texture_names)?
+        } else {
+            warn!("No block definitions found at {}, using the defaults", definitions_path.display());
+            BlockRegistry::from_definitions(Vec::new(), &texture_names)?
+        };
 
-                // Save name and texture coordinates
-                let block_name = entry.path().file_stem().unwrap().to_str().unwrap().to_owned();
-                blocks.insert(block_name, (x, y));
+        let settings = {
+            let mut max_texture_size = 0;
+            let mut max_layers = 0;
+            gl_call!(gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size));
+            gl_call!(gl::GetIntegerv(gl::MAX_ARRAY_TEXTURE_LAYERS, &mut max_layers));
+            AtlasSettings {
+                max_page_size: u32::min(AtlasSettings::default().max_page_size, max_texture_size as u32),
+                max_pages: max_layers as u32,
+                ..AtlasSettings::default()
+            }
+        };
 
-                x += 16;
-                if x / atlas_width > 0 {
-                    x %= atlas_width;
-                    y += 16
-                }
+        // Animated textures are vertical strips of frames, each frame gets its own gutter
+        let mut sprites = Vec::with_capacity(texture_paths.len());
+        let mut animations = Vec::with_capacity(texture_paths.len());
+        for (path, name) in texture_paths.iter().zip(&texture_names) {
+            let img = image::open(path)
+                .map_err(|error| RegistryError::TextureImage { path: path.clone(), error })?
+                .to_rgba();
+            let (width, height) = img.dimensions();
+
+            let metadata = registry.iter()
+                .filter(|(_, block)| block.texture.as_ref() == Some(name))
+                .find_map(|(_, block)| block.animation);
+            let (frame_count, frame_time) = match metadata {
+                Some(animation) => (animation.frame_count.max(1), animation.frame_time),
+                None => (detect_frame_count(width, height), DEFAULT_FRAME_TIME)
+            };
+
+            if frame_count == 1 {
+                sprites.push(image::imageops::flip_vertical(&img));
+                animations.push(None);
+                continue;
             }
+
+            let frames: Vec<RgbaImage> = split_frames(&img, frame_count)
+                .ok_or_else(|| RegistryError::BadAnimation { texture: name.clone(), frame_count, height })?
+                .iter()
+                .map(|frame| image::imageops::flip_vertical(frame))
+                .collect();
+            let (strip, stride) = frame_strip(&frames, settings.padding);
+            sprites.push(strip);
+            animations.push(Some((frame_count, frame_time, stride, height / frame_count)));
         }
 
-        for (key, value) in blocks.iter() {
-            println!("Name: {}, tex: {},{}", key, value.0, value.1);
+        let sizes: Vec<(u32, u32)> = sprites.iter().map(|img| img.dimensions()).collect();
+        let layout = pack_sprites(&sizes, &settings)?;
+        info!("Block atlas: {} page(s) of {}x{}", layout.page_count, layout.page_size, layout.page_size);
+
+        let mut atlas = Texture2DArray::new();
+        atlas.allocate(layout.page_size, layout.page_size, layout.page_count.max(1), layout.mipmap_levels());
+        for (layer, page) in layout.compose_pages(&sprites).iter().enumerate() {
+            atlas.update_layer(layer as u32, 0, 0, page);
         }
+        atlas.generate_mipmaps();
 
-        BlockCatalog { blocks_texture_atlas: atlas, block_types: blocks }
+        let page_size = layout.page_size as f32;
+        let blocks: HashMap<String, BlockSprite> = texture_names.into_iter()
+            .zip(animations)
+            .enumerate()
+            .map(|(i, (name, animation))| {
+                let mut sprite = BlockSprite { rect: layout.uv_rect(i), animation: SpriteAnimation::default() };
+                if let Some((frame_count, frame_time, stride, frame_height)) = animation {
+                    // The rect of the first frame, the shader moves it up to the current one
+                    sprite.rect.v1 = sprite.rect.v0 + frame_height as f32 / page_size;
+                    sprite.animation = SpriteAnimation {
+                        frame_count,
+                        frame_time,
+                        frame_stride: stride as f32 / page_size,
+                    };
+                }
+                (name, sprite)
+            })
+            .collect();
+
+        let block_sprites = registry.iter()
+            .map(|(_, block)| match &block.texture {
+                Some(texture) => blocks[texture],
+                None => BlockSprite::default()
+            })
+            .collect();
+
+        Ok(BlockCatalog {
+            blocks_texture_atlas: atlas,
+            registry: Arc::new(registry),
+            block_types: blocks,
+            block_sprites
+        })
     }
 }
 
-pub struct VoxelWorld<'c> {
-    chunk_size: (u32, u32),
+pub struct VoxelWorld {
+    seed: u64,
+    generator: Arc<dyn TerrainGenerator>,
+    registry: Arc<BlockRegistry>,
+    streamer: ChunkStreamer,
+    region_store: Arc<Mutex<Option<RegionStore>>>,
+    pub streaming: StreamingSettings,
+    pub ambient_light: f32,
     pub chunks: HashMap<(i32, i32), Chunk>,
-    pub dirty_chunks: VecDeque<&'c mut Chunk>
+    // Chunks whose mesh must be rebuilt, they are sent to the mesher on the next render
+    pub dirty_chunks: HashSet<(i32, i32)>,
+    // Chunks whose collider must be rebuilt (or removed if they were evicted)
+    dirty_colliders: HashSet<(i32, i32)>,
+    // Block drawn with an outline, e.g. the one under the cursor
+    pub highlighted_block: Option<(i32, i32)>,
+    renderer: Option<VoxelRenderer>
 }
 
-impl<'c> VoxelWorld<'c> {
-    pub fn new(chunk_size: (u32, u32)) -> Self {
+impl VoxelWorld {
+    // The registry must be the one of the BlockCatalog used to render the world
+    pub fn new(registry: Arc<BlockRegistry>, seed: u64, generator: Box<dyn TerrainGenerator>) -> Self {
+        let generator: Arc<dyn TerrainGenerator> = Arc::from(generator);
+        let region_store = Arc::new(Mutex::new(None));
+
         VoxelWorld {
-            chunk_size,
+            seed,
+            streamer: ChunkStreamer::new(seed, generator.clone(), region_store.clone()),
+            region_store,
+            generator,
+            registry,
+            streaming: StreamingSettings::default(),
+            ambient_light: 0.4,
             chunks: HashMap::new(),
-            dirty_chunks: VecDeque::new()
+            dirty_chunks: HashSet::new(),
+            dirty_colliders: HashSet::new(),
+            highlighted_block: None,
+            renderer: None
         }
     }
 
-    pub fn place_some_blocks(&mut self) {
-        let blocks = CONTAINER.get_local::<BlockCatalog>();
-        let ids: Vec<&String> = blocks.block_types.keys().collect();
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
 
-        use rand::seq::SliceRandom;
-        let mut rng = rand::thread_rng();
+    pub fn generate_chunk_data(&self, coords: (i32, i32)) -> ChunkData {
+        let mut data = ChunkData::new();
+        self.generator.generate(self.seed, coords, &mut data);
+        data
+    }
 
-        for xx in 0..7 {
-            for yy in 0..7 {
-                let mut chunk = Chunk::new();
+    // Generates the chunk if it doesn't exist yet
+    pub fn generate_chunk(&mut self, coords: (i32, i32)) {
+        if self.chunks.contains_key(&coords) {
+            return;
+        }
 
-                for y in 0..self.chunk_size.1 {
-                    for x in 0..self.chunk_size.0 {
-                        chunk.add_block(x, y, Block { id: ids.choose(&mut rng).unwrap() });
-                    }
-                }
+        let data = self.generate_chunk_data(coords);
+        self.insert_chunk(coords, data);
+    }
+
+    // The chunk is drawn once its mesh has been built in the background
+    fn insert_chunk(&mut self, coords: (i32, i32), data: ChunkData) {
+        self.chunks.insert(coords, Chunk::from_data(data));
+        self.dirty_chunks.insert(coords);
+        self.dirty_colliders.insert(coords);
+        self.light_chunk(coords);
+    }
 
-                chunk.remove_block(7, 7);
-                chunk.remove_block(7, 8);
-                chunk.remove_block(8, 8);
-                chunk.regen_mesh();
-                self.chunks.insert((xx, yy), chunk);
+    pub fn generate_area(&mut self, from: (i32, i32), to: (i32, i32)) {
+        for y in from.1..=to.1 {
+            for x in from.0..=to.0 {
+                self.generate_chunk((x, y));
             }
         }
     }
 
-    fn get_chunk_and_block_coords(&self, x: i32, y: i32) -> (i32, i32, u32, u32) {
+    pub fn world_to_chunk_coords(&self, x: f32, y: f32) -> (i32, i32) {
         (
-            x / self.chunk_size.0 as i32,
-            y / self.chunk_size.1 as i32,
-            (x % self.chunk_size.0 as i32) as u32,
-            (y % self.chunk_size.1 as i32) as u32,
+            (x / CHUNK_SIZE as f32).floor() as i32,
+            (y / CHUNK_SIZE as f32).floor() as i32,
         )
     }
 
-    pub fn add_block(&'c mut self, x: i32, y: i32, block: Block) {
-        let (x_chunk, y_chunk, x_block, y_block) = self.get_chunk_and_block_coords(x, y);
+    // Loads the chunks around the given position in the background,
+    // uploads the ones that are ready and evicts the ones too far away
+    pub fn stream_around(&mut self, position: &Vec3) {
+        let center = self.world_to_chunk_coords(position.x, position.y);
+        let load_radius = self.streaming.load_radius;
+        let unload_radius = i32::max(self.streaming.unload_radius, load_radius);
 
-        let chunk = self.chunks.get_mut(&(x_chunk, y_chunk));
-        match chunk {
-            Some(chunk) => {
-                chunk.add_block(x_block, y_block, block);
-                // Invalidate chunk
-                // TODO check if the chunk is already in the list
-                // maybe a hashset or hashmap?
-                self.dirty_chunks.push_back(chunk);
-            },
-            None => panic!("Inexistent chunk ({}, {})", x_chunk, y_chunk),
+        // Evict, saving the edited chunks. They stay in memory until written, for good
+        // if the world isn't saved anywhere yet.
+        let far_chunks: Vec<(i32, i32)> = self.chunks.keys()
+            .filter(|coords| chunk_distance(**coords, center) > unload_radius)
+            .cloned()
+            .collect();
+        for coords in far_chunks {
+            let edge_light = chunk_edge_light(self, coords);
+            let chunk = self.chunks.remove(&coords).unwrap();
+            self.unlight_evicted_chunk(&edge_light);
+            self.dirty_chunks.remove(&coords);
+            self.dirty_colliders.insert(coords);
+            if let Some(renderer) = &mut self.renderer {
+                renderer.remove_chunk(coords);
+            }
+            if chunk.modified {
+                self.streamer.save(coords, chunk.data);
+            }
+        }
+        for coords in self.streamer.pending() {
+            if chunk_distance(coords, center) > unload_radius {
+                self.streamer.cancel(coords);
+            }
+        }
+
+        // Request missing chunks, the closest ones first
+        let mut missing = Vec::new();
+        for y in center.1 - load_radius..=center.1 + load_radius {
+            for x in center.0 - load_radius..=center.0 + load_radius {
+                if !self.chunks.contains_key(&(x, y)) && !self.streamer.is_pending((x, y)) {
+                    missing.push((x, y));
+                }
+            }
+        }
+        missing.sort_by_key(|coords| chunk_distance(*coords, center));
+        for coords in missing {
+            self.streamer.request(coords);
+        }
+
+        // Upload the chunks ready to be rendered
+        for _ in 0..self.streaming.max_uploads_per_frame {
+            let (coords, data) = match self.streamer.poll() {
+                Some(loaded) => loaded,
+                None => break
+            };
+
+            if self.chunks.contains_key(&coords) {
+                continue;
+            }
+
+            self.insert_chunk(coords, data);
         }
     }
 
-    pub fn remove_block(&'c mut self, x: i32, y: i32) {
-        let (x_chunk, y_chunk, x_block, y_block) = self.get_chunk_and_block_coords(x, y);
+    fn light_chunk(&mut self, coords: (i32, i32)) {
+        let registry = self.registry.clone();
+        light_chunk(self, &registry, coords);
+    }
 
-        let chunk = self.chunks.get_mut(&(x_chunk, y_chunk));
-        match chunk {
-            Some(chunk) => {
-                chunk.remove_block(x_block, y_block);
-                // Invalidate chunk
-                self.dirty_chunks.push_back(chunk);
-            },
-            None => panic!("Inexistent chunk ({}, {})", x_chunk, y_chunk),
+    fn update_light(&mut self, x: i32, y: i32) {
+        let registry = self.registry.clone();
+        update_light(self, &registry, x, y);
+    }
+
+    fn unlight_evicted_chunk(&mut self, edge_light: &[(i32, i32, LightColor)]) {
+        let registry = self.registry.clone();
+        unlight_evicted_chunk(self, &registry, edge_light);
+    }
+
+    fn new_region_store(&self, path: &Path) -> RegionStore {
+        RegionStore::new(path, self.registry.clone())
+    }
+
+    // Writes all the loaded chunks, and the evicted ones not written yet, in the region
+    // files inside the directory. Evicted chunks will be saved there from now on.
+    pub fn save(&mut self, path: &Path) -> Result<(), RegionError> {
+        let store = self.new_region_store(path);
+
+        // Hold the lock so that the streaming thread doesn't write at the same time
+        let mut region_store = self.region_store.lock().unwrap();
+        let unsaved = self.streamer.take_unsaved();
+        let mut chunks: Vec<((i32, i32), &ChunkData)> = self.chunks.iter()
+            .map(|(coords, chunk)| (*coords, &chunk.data))
+            .collect();
+        // The loaded copy is the latest
+        chunks.extend(unsaved.iter()
+            .filter(|(coords, _)| !self.chunks.contains_key(coords))
+            .map(|(coords, data)| (*coords, data)));
+        if let Err(err) = store.save_chunks(&chunks) {
+            for (coords, data) in unsaved {
+                self.streamer.save(coords, data);
+            }
+            return Err(err);
         }
+        *region_store = Some(store);
+        drop(region_store);
+
+        for chunk in self.chunks.values_mut() {
+            chunk.modified = false;
+        }
+        Ok(())
+    }
+
+    // Switches to the world saved in the directory. The chunks are streamed back from it,
+    // the ones that were never saved are generated.
+    pub fn load(&mut self, path: &Path) -> Result<(), RegionError> {
+        if !path.is_dir() {
+            return Err(RegionError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is not a directory", path.display())
+            )));
+        }
+
+        let store = self.new_region_store(path);
+        let chunk_count = store.validate()?;
+        info!("Loading {} saved chunks from {}", chunk_count, path.display());
+
+        // The evicted chunks not written yet go to the world they come from
+        let mut region_store = self.region_store.lock().unwrap();
+        let unsaved = self.streamer.take_unsaved();
+        match region_store.as_ref() {
+            Some(old_store) => {
+                let chunks: Vec<((i32, i32), &ChunkData)> = unsaved.iter().map(|(coords, data)| (*coords, data)).collect();
+                if let Err(err) = old_store.save_chunks(&chunks) {
+                    error!("Could not save {} evicted chunks before loading {}: {}", chunks.len(), path.display(), err);
+                }
+            }
+            None if !unsaved.is_empty() => {
+                warn!("Discarding {} edited chunks, the world was never saved", unsaved.len());
+            }
+            None => ()
+        }
+        *region_store = Some(store);
+        drop(region_store);
+        // Requests in flight read the previous store
+        self.streamer.cancel_all();
+        if let Some(renderer) = &mut self.renderer {
+            renderer.clear();
+        }
+        self.dirty_colliders.extend(self.chunks.keys().cloned());
+        self.chunks.clear();
+        self.dirty_chunks.clear();
+        Ok(())
+    }
+
+    // None if the chunk isn't loaded
+    pub fn block_at(&self, layer: Layer, x: i32, y: i32) -> Option<Block> {
+        let (chunk_coords, (x, y)) = world_to_local(x, y);
+        self.chunks.get(&chunk_coords).map(|chunk| chunk.data.get_layer(layer, x, y))
+    }
+
+    // First solid block of the main layer along the ray, the chunks that aren't loaded are considered empty
+    pub fn raycast(&self, origin: &Vec2, direction: &Vec2, max_distance: f32) -> Option<RaycastHit> {
+        let registry = &self.registry;
+        raycast_blocks(origin, direction, max_distance, |x, y| {
+            self.block_at(Layer::Main, x, y).map_or(false, |block| registry.get(block.id).solid)
+        })
+    }
+
+    // Makes sure the chunk is loaded, reading it from the chunks waiting to be saved,
+    // the region files, or generating it.
+    // It is loaded right away, a pending streaming request for it is cancelled.
+    pub fn load_chunk(&mut self, coords: (i32, i32)) {
+        if self.chunks.contains_key(&coords) {
+            return;
+        }
+        self.streamer.cancel(coords);
+
+        let data = self.streamer.load_saved(coords).unwrap_or_else(|| self.generate_chunk_data(coords));
+        self.insert_chunk(coords, data);
     }
 
-    pub fn render(&mut self) {
-        // Process invalidated chunks
-        while let Some(chunk) = self.dirty_chunks.pop_front() {
-            chunk.regen_mesh();
+    // Loads the chunk containing the block if needed
+    pub fn set_block(&mut self, layer: Layer, x: i32, y: i32, block: Block) {
+        let (coords, local) = world_to_local(x, y);
+        self.load_chunk(coords);
+        self.set_loaded_block(layer, coords, local, block);
+    }
+
+    // The chunk must be loaded
+    fn set_loaded_block(&mut self, layer: Layer, coords: (i32, i32), (block_x, block_y): (u32, u32), block: Block) {
+        let chunk = self.chunks.get_mut(&coords).unwrap();
+        if chunk.data.get_layer(layer, block_x, block_y) == block {
+            return;
+        }
+        chunk.add_block(layer, block_x, block_y, block);
+        self.dirty_chunks.insert(coords);
+        // Only the main layer blocks the light and collides
+        if layer == Layer::Main {
+            self.dirty_colliders.insert(coords);
+            let size = CHUNK_SIZE as i32;
+            self.update_light(coords.0 * size + block_x as i32, coords.1 * size + block_y as i32);
+        }
+    }
+
+    pub fn add_block(&mut self, layer: Layer, x: i32, y: i32, block: Block) {
+        self.set_block(layer, x, y, block);
+    }
+
+    pub fn remove_block(&mut self, layer: Layer, x: i32, y: i32) {
+        self.set_block(layer, x, y, Block::air());
+    }
+
+    // Sets every block between the two corners, inclusive
+    pub fn fill_rect(&mut self, layer: Layer, from: (i32, i32), to: (i32, i32), block: Block) {
+        for y in i32::min(from.1, to.1)..=i32::max(from.1, to.1) {
+            for x in i32::min(from.0, to.0)..=i32::max(from.0, to.0) {
+                self.set_block(layer, x, y, block);
+            }
+        }
+    }
+
+    // Replaces the target blocks between the two corners, inclusive.
+    // Returns the number of blocks replaced.
+    pub fn replace(
+        &mut self,
+        layer: Layer,
+        from: (i32, i32),
+        to: (i32, i32),
+        target: Block,
+        replacement: Block
+    ) -> usize {
+        if target == replacement {
+            return 0;
+        }
+
+        let (min_x, max_x) = (i32::min(from.0, to.0), i32::max(from.0, to.0));
+        let (min_y, max_y) = (i32::min(from.1, to.1), i32::max(from.1, to.1));
+        let ((first_x, first_y), _) = world_to_local(min_x, min_y);
+        let ((last_x, last_y), _) = world_to_local(max_x, max_y);
+        let size = CHUNK_SIZE as i32;
+
+        // Chunk by chunk, each one is loaded once
+        let mut count = 0;
+        for chunk_y in first_y..=last_y {
+            for chunk_x in first_x..=last_x {
+                let coords = (chunk_x, chunk_y);
+                self.load_chunk(coords);
+                let (origin_x, origin_y) = (chunk_x * size, chunk_y * size);
+
+                for y in i32::max(min_y, origin_y)..=i32::min(max_y, origin_y + size - 1) {
+                    for x in i32::max(min_x, origin_x)..=i32::min(max_x, origin_x + size - 1) {
+                        let local = ((x - origin_x) as u32, (y - origin_y) as u32);
+                        if self.chunks[&coords].data.get_layer(layer, local.0, local.1) == target {
+                            self.set_loaded_block(layer, coords, local, replacement);
+                            count += 1;
+                        }
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    // Rebuilds the static colliders of the chunks loaded or edited since the last sync,
+    // and removes the ones of the evicted chunks
+    pub fn sync_colliders(&mut self, physics: &mut PhysicsWorld) {
+        if self.dirty_colliders.is_empty() {
+            return;
+        }
+
+        for coords in self.dirty_colliders.drain() {
+            let data = self.chunks.get(&coords).map(|chunk| &chunk.data);
+            update_chunk_collider(physics, &self.registry, coords, data);
+        }
+    }
+
+    // The time drives the animated textures. The camera matrices and the render target
+    // must already be bound, see VoxelRenderSystem.
+    pub fn render(&mut self, time: &Time) {
+        let renderer = self.renderer.get_or_insert_with(VoxelRenderer::new);
+
+        // Remesh the invalidated chunks, a chunk edited again before its mesh is ready
+        // replaces the previous job
+        for coords in self.dirty_chunks.drain() {
+            if let Some(chunk) = self.chunks.get(&coords) {
+                renderer.mesher.request(coords, chunk.data.clone());
+            }
+        }
+
+        for _ in 0..self.streaming.max_mesh_uploads_per_frame {
+            let (coords, meshes) = match renderer.mesher.poll() {
+                Some(meshed) => meshed,
+                None => break
+            };
+            if self.chunks.contains_key(&coords) {
+                renderer.chunks.entry(coords)
+                    .or_insert_with(ChunkRenderData::new)
+                    .upload_meshes(&meshes);
+            }
         }
 
         let block_catalog = CONTAINER.get_local::<BlockCatalog>();
@@ -269,45 +854,262 @@ impl<'c> VoxelWorld<'c> {
 
         let shader = CONTAINER.get_local::<VoxelShader>();
         shader.bind();
-        
-        for (coords, chunk) in &self.chunks {
-            chunk.mesh.bind();
-            shader.set_offset((coords.0 * 16, coords.1 * 16));
-            gl_call!(gl::DrawElements(gl::TRIANGLES,
-                                  chunk.indices.len() as i32,
-                                  gl::UNSIGNED_INT, std::ptr::null()));
+        shader.set_ambient_light(self.ambient_light);
+        shader.set_time(time.elapsed() as f32);
+
+        for (coords, chunk) in self.chunks.iter_mut() {
+            if chunk.light_dirty {
+                renderer.chunks.entry(*coords)
+                    .or_insert_with(ChunkRenderData::new)
+                    .upload_lightmap(&chunk.light);
+                chunk.light_dirty = false;
+            }
         }
+
+        let draw_layer = |layer: Layer, meshes: fn(&ChunkRenderData) -> &[LayerMesh]| {
+            shader.set_depth(layer.depth());
+            shader.set_brightness(layer.brightness());
+
+            for (coords, render_data) in &renderer.chunks {
+                let layer_mesh = &meshes(render_data)[layer.index()];
+                // Empty or not meshed yet
+                if layer_mesh.indices.len() == 0 {
+                    continue;
+                }
+                render_data.lightmap.activate(1);
+                layer_mesh.vao.bind();
+                shader.set_offset((coords.0 * CHUNK_SIZE as i32, coords.1 * CHUNK_SIZE as i32));
+                gl_call!(gl::DrawElements(gl::TRIANGLES,
+                                      layer_mesh.indices.len() as i32,
+                                      gl::UNSIGNED_INT, std::ptr::null()));
+            }
+        };
+
+        // The cutout blocks are drawn with the opaque ones, their transparent pixels discarded
+        shader.set_alpha_cutoff(0.5);
+        for &layer in Layer::ALL.iter() {
+            draw_layer(layer, |render_data| &render_data.meshes);
+        }
+
+        // Then the translucent blocks, back to front. The tiles of a layer don't overlap,
+        // so the chunks don't need to be sorted.
+        shader.set_alpha_cutoff(0.0);
+        gl_call!(gl::DepthMask(gl::FALSE));
+        for &layer in Layer::ALL.iter() {
+            draw_layer(layer, |render_data| &render_data.translucent_meshes);
+        }
+        gl_call!(gl::DepthMask(gl::TRUE));
 //        println!("LEN {}", self.chunk.indices_len);
+
+        if let Some((x, y)) = self.highlighted_block {
+            // Slightly in front of the foreground tiles
+            let depth = Layer::Foreground.depth() + 0.01;
+            let model = Matrix4::new_translation(&vec3(x as f32, y as f32, depth));
+            OutlineData { color: vec3(1.0, 1.0, 0.0) }.bind_model(&model);
+            renderer.highlight_mesh.bind();
+            gl_call!(gl::DrawArrays(gl::TRIANGLES, 0, (renderer.highlight_positions.len() / 3) as i32));
+        }
     }
 }
 
-fn gen_tile(
-    mut i: u32,
-    x: f32,
-    y: f32,
-    width: f32,
-    height: f32,
-    tex_x: f32,
-    tex_y: f32,
-    tex_w: f32,
-    tex_h: f32
-) -> ([f32; 8], [f32; 8], [u32; 6]) {
-    let tile = [
-        x + width * 0.0f32, y + height * 0.0,
-        x + width * 0.0, y + height * -1.0,
-        x + width * 1.0, y + height * -1.0,
-        x + width * 1.0, y + height * 0.0,
-    ];
+impl LightWorld for VoxelWorld {
+    // The background and foreground layers don't block the light
+    fn block(&self, x: i32, y: i32) -> Option<Block> {
+        self.block_at(Layer::Main, x, y)
+    }
 
-    let tex_coords = [
-        tex_x, tex_y + tex_h,
-        tex_x, tex_y,
-        tex_x + tex_w, tex_y,
-        tex_x + tex_w, tex_y + tex_h
+    fn light(&self, x: i32, y: i32) -> LightColor {
+        let (chunk_coords, (x, y)) = world_to_local(x, y);
+        self.chunks.get(&chunk_coords)
+            .map(|chunk| chunk.light[(y * CHUNK_SIZE + x) as usize])
+            .unwrap_or(NO_LIGHT)
+    }
+
+    fn set_light(&mut self, x: i32, y: i32, light: LightColor) {
+        let (chunk_coords, (x, y)) = world_to_local(x, y);
+        if let Some(chunk) = self.chunks.get_mut(&chunk_coords) {
+            chunk.light[(y * CHUNK_SIZE + x) as usize] = light;
+            chunk.light_dirty = true;
+        }
+    }
+}
+
+// Frame around a tile, made of 4 quads of the given thickness
+fn gen_outline(thickness: f32) -> Vec<f32> {
+    let quads = [
+        (0.0, -1.0, 1.0, -1.0 + thickness),
+        (0.0, -thickness, 1.0, 0.0),
+        (0.0, -1.0, thickness, 0.0),
+        (1.0 - thickness, -1.0, 1.0, 0.0),
     ];
 
-    i *= 4;
-    let indices = [i + 0, i + 1, i + 2, i + 2, i + 3, i + 0];
+    let mut vertices = Vec::with_capacity(quads.len() * 18);
+    for (x0, y0, x1, y1) in quads.iter() {
+        vertices.extend_from_slice(&[
+            *x0, *y0, 0.0, *x1, *y0, 0.0, *x1, *y1, 0.0,
+            *x1, *y1, 0.0, *x0, *y1, 0.0, *x0, *y0, 0.0,
+        ]);
+    }
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::vec2;
+
+    // Nothing but air
+    struct EmptyGenerator;
+
+    impl TerrainGenerator for EmptyGenerator {
+        fn generate(&self, _seed: u64, _chunk_coords: (i32, i32), _chunk: &mut ChunkData) {}
+    }
+
+    fn world() -> (VoxelWorld, Block) {
+        let registry = BlockRegistry::from_definitions(Vec::new(), &["stone".to_string()]).unwrap();
+        let stone = Block { id: registry.id("stone").unwrap() };
+        (VoxelWorld::new(Arc::new(registry), 0, Box::new(EmptyGenerator)), stone)
+    }
 
-    (tile, tex_coords, indices)
+    fn clear_dirty(world: &mut VoxelWorld) {
+        world.dirty_chunks.clear();
+        world.dirty_colliders.clear();
+    }
+
+    fn sorted(coords: &HashSet<(i32, i32)>) -> Vec<(i32, i32)> {
+        let mut coords: Vec<(i32, i32)> = coords.iter().cloned().collect();
+        coords.sort();
+        coords
+    }
+
+    #[test]
+    fn world_to_local_rounds_towards_negative_infinity() {
+        assert_eq!(world_to_local(0, 0), ((0, 0), (0, 0)));
+        assert_eq!(world_to_local(-1, -1), ((-1, -1), (15, 15)));
+        assert_eq!(world_to_local(-16, 15), ((-1, 0), (0, 15)));
+        assert_eq!(world_to_local(-17, 16), ((-2, 1), (15, 0)));
+        assert_eq!(world_to_local(31, -32), ((1, -2), (15, 0)));
+    }
+
+    #[test]
+    fn raycasts_hit_the_solid_blocks_of_the_main_layer() {
+        let (mut world, stone) = world();
+        world.set_block(Layer::Background, 3, 0, stone);
+        world.set_block(Layer::Main, 20, 0, stone);
+
+        let hit = world.raycast(&vec2(0.5, -0.5), &vec2(1.0, 0.0), 50.0).unwrap();
+        assert_eq!(hit.position, (20, 0));
+        assert_eq!(hit.normal, (-1, 0));
+
+        // The chunks on the left aren't loaded, then only (-2, 0) is
+        assert_eq!(world.raycast(&vec2(0.5, -0.5), &vec2(-1.0, 0.0), 50.0), None);
+        world.set_block(Layer::Main, -20, 0, stone);
+        assert_eq!(world.raycast(&vec2(0.5, -0.5), &vec2(-1.0, 0.0), 50.0).unwrap().position, (-20, 0));
+    }
+
+    #[test]
+    fn edits_only_invalidate_their_own_chunk() {
+        let (mut world, stone) = world();
+        for coords in &[(-1, 0), (0, 0), (0, -1)] {
+            world.load_chunk(*coords);
+        }
+        clear_dirty(&mut world);
+
+        // Last column of chunk (-1, 0), the mesh of the neighbour doesn't depend on it
+        world.set_block(Layer::Main, -1, 5, stone);
+        assert_eq!(world.chunks[&(-1, 0)].data.get(15, 5), stone);
+        assert_eq!(world.block_at(Layer::Main, 0, 5), Some(Block::air()));
+        assert_eq!(sorted(&world.dirty_chunks), vec![(-1, 0)]);
+        assert_eq!(sorted(&world.dirty_colliders), vec![(-1, 0)]);
+        assert!(world.chunks[&(-1, 0)].modified);
+        assert!(!world.chunks[&(0, 0)].modified);
+
+        // The neighbour isn't loaded by the edit, the background doesn't collide
+        clear_dirty(&mut world);
+        world.set_block(Layer::Background, 15, 8, stone);
+        assert_eq!(sorted(&world.dirty_chunks), vec![(0, 0)]);
+        assert!(world.dirty_colliders.is_empty());
+        assert!(!world.chunks.contains_key(&(1, 0)));
+
+        // Setting the same block again changes nothing
+        clear_dirty(&mut world);
+        world.set_block(Layer::Background, 15, 8, stone);
+        assert!(world.dirty_chunks.is_empty());
+    }
+
+    // Evicts every loaded chunk, the ones around the position are streamed in the background
+    fn evict_all(world: &mut VoxelWorld) {
+        world.streaming.load_radius = 0;
+        world.streaming.unload_radius = 0;
+        world.stream_around(&vec3(10_000.0, 10_000.0, 0.0));
+        assert!(world.chunks.keys().all(|coords| chunk_distance(*coords, (0, 0)) > 1));
+    }
+
+    #[test]
+    fn evicted_edits_are_kept_without_a_store() {
+        let (mut world, stone) = world();
+        world.set_block(Layer::Main, 3, 3, stone);
+        evict_all(&mut world);
+
+        world.load_chunk((0, 0));
+        assert_eq!(world.block_at(Layer::Main, 3, 3), Some(stone));
+    }
+
+    #[test]
+    fn evicted_edits_are_read_back_before_being_written() {
+        let (mut world, stone) = world();
+        let dir = std::env::temp_dir().join(format!("voxel_world_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        world.load_chunk((0, 0));
+        world.save(&dir).unwrap();
+
+        // Whether the worker wrote it yet or not
+        world.set_block(Layer::Main, 3, 3, stone);
+        evict_all(&mut world);
+        world.load_chunk((0, 0));
+        assert_eq!(world.block_at(Layer::Main, 3, 3), Some(stone));
+
+        // Edited again, then the world is reloaded before the worker is done
+        world.set_block(Layer::Main, 4, 3, stone);
+        evict_all(&mut world);
+        world.load(&dir).unwrap();
+        world.load_chunk((0, 0));
+        assert_eq!(world.block_at(Layer::Main, 3, 3), Some(stone));
+        assert_eq!(world.block_at(Layer::Main, 4, 3), Some(stone));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fill_rect_spans_four_chunks() {
+        let (mut world, stone) = world();
+        world.fill_rect(Layer::Main, (1, 1), (-2, -2), stone);
+
+        let corners = vec![(-1, -1), (-1, 0), (0, -1), (0, 0)];
+        let mut loaded: Vec<(i32, i32)> = world.chunks.keys().cloned().collect();
+        loaded.sort();
+        assert_eq!(loaded, corners);
+        assert_eq!(sorted(&world.dirty_chunks), corners);
+        assert_eq!(sorted(&world.dirty_colliders), corners);
+
+        // All in the same four chunks
+        for y in -3..=2 {
+            for x in -3..=2 {
+                let inside = (-2..=1).contains(&x) && (-2..=1).contains(&y);
+                let expected = if inside { stone } else { Block::air() };
+                assert_eq!(world.block_at(Layer::Main, x, y), Some(expected), "at ({}, {})", x, y);
+            }
+        }
+        for chunk in world.chunks.values() {
+            let count = chunk.data.layers[Layer::Main.index()].iter().filter(|block| **block == stone).count();
+            assert_eq!(count, 4);
+            assert!(chunk.modified);
+        }
+
+        clear_dirty(&mut world);
+        assert_eq!(world.replace(Layer::Main, (-2, -2), (1, 1), stone, Block::air()), 16);
+        assert_eq!(world.replace(Layer::Main, (-2, -2), (1, 1), stone, Block::air()), 0);
+        assert_eq!(sorted(&world.dirty_chunks), corners);
+        assert!(world.chunks.values().all(|chunk| chunk.data.layers.iter().flatten().all(Block::is_air)));
+    }
 }
@@ -0,0 +1,255 @@
+use super::{Block, BlockCatalog, BlockRegistry, Layer, VoxelEdit, VoxelEditEvents, VoxelWorld};
+use crate::containers::CONTAINER;
+use crate::ecs::components::{Camera, Transform};
+use crate::ecs::resources::{ActiveCamera, InputCache};
+use glfw::{Key, MouseButton};
+use nalgebra_glm::Vec2;
+use specs::prelude::*;
+
+// The tile of the block at (x, y) goes from y - 1 to y (see meshing.rs)
+pub fn world_to_block(x: f32, y: f32) -> (i32, i32) {
+    (x.floor() as i32, (y + 1.0).floor() as i32)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RaycastHit {
+    pub position: (i32, i32),
+    // Side of the block that was hit, (0, 0) if the ray starts inside the block.
+    // position + normal is where a block would be placed against it.
+    pub normal: (i32, i32),
+    pub distance: f32,
+}
+
+// Walks the blocks crossed by the ray (DDA) until `hit` returns true.
+// The origin is in world space, the direction doesn't need to be normalized.
+pub fn raycast_blocks(
+    origin: &Vec2,
+    direction: &Vec2,
+    max_distance: f32,
+    mut hit: impl FnMut(i32, i32) -> bool
+) -> Option<RaycastHit> {
+    let length = direction.norm();
+    if length == 0.0 {
+        return None;
+    }
+    let direction = direction / length;
+
+    // Block space, where the block (x, y) goes from (x, y) to (x + 1, y + 1)
+    let (origin_x, origin_y) = (origin.x, origin.y + 1.0);
+    let mut position = (origin_x.floor() as i32, origin_y.floor() as i32);
+
+    let step = (direction.x.signum() as i32, direction.y.signum() as i32);
+    let t_delta = (1.0 / direction.x.abs(), 1.0 / direction.y.abs());
+    let first_crossing = |origin: f32, cell: i32, direction: f32| {
+        if direction > 0.0 {
+            (cell as f32 + 1.0 - origin) / direction
+        } else if direction < 0.0 {
+            (origin - cell as f32) / -direction
+        } else {
+            std::f32::INFINITY
+        }
+    };
+    let mut t_max = (
+        first_crossing(origin_x, position.0, direction.x),
+        first_crossing(origin_y, position.1, direction.y),
+    );
+
+    let mut normal = (0, 0);
+    let mut distance = 0.0;
+    while distance <= max_distance {
+        if hit(position.0, position.1) {
+            return Some(RaycastHit { position, normal, distance });
+        }
+
+        if t_max.0 < t_max.1 {
+            distance = t_max.0;
+            position.0 += step.0;
+            t_max.0 += t_delta.0;
+            normal = (-step.0, 0);
+        } else {
+            distance = t_max.1;
+            position.1 += step.1;
+            t_max.1 += t_delta.1;
+            normal = (0, -step.1);
+        }
+    }
+
+    None
+}
+
+const BLOCK_KEYS: [Key; 9] = [
+    Key::Num1, Key::Num2, Key::Num3, Key::Num4, Key::Num5,
+    Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+];
+
+// The blocks the number keys select, the first ones of the registry that can be drawn
+pub fn hotbar_blocks(registry: &BlockRegistry) -> Vec<Block> {
+    registry.iter()
+        .filter(|(_, block_type)| block_type.texture.is_some())
+        .map(|(id, _)| Block { id })
+        .take(BLOCK_KEYS.len())
+        .collect()
+}
+
+// Breaks the hovered block on left click and places the selected one on right click.
+// The number keys select the blocks of the hotbar. Holding shift edits the
+// background layer and holding control the foreground one.
+// Only while the cursor is visible, see InputCache::cursor_visible.
+// Must be a thread local system, the block catalog lives in the main thread's container.
+pub struct BlockPicker {
+    // The first block of the hotbar until a number key is pressed
+    pub selected: Option<Block>,
+    pub layer: Layer,
+    pub hovered: Option<(i32, i32)>,
+}
+
+impl Default for BlockPicker {
+    fn default() -> Self {
+        BlockPicker {
+            selected: None,
+            layer: Layer::Main,
+            hovered: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for BlockPicker {
+    type SystemData = (Read<'a, InputCache>,
+                       Read<'a, ActiveCamera>,
+                       ReadStorage<'a, Camera>,
+                       ReadStorage<'a, Transform>,
+                       WriteExpect<'a, VoxelWorld>,
+                       Write<'a, VoxelEditEvents>);
+
+    fn run(&mut self, (input_cache, active_camera, cameras, transforms, mut voxel_world, mut edits): Self::SystemData) {
+        if !input_cache.cursor_visible {
+            self.hovered = None;
+            voxel_world.highlighted_block = None;
+            return;
+        }
+
+        self.hovered = active_camera.entity.and_then(|entity| {
+            let camera = cameras.get(entity)?;
+            let transform = transforms.get(entity)?;
+            let (origin, direction) = camera.screen_to_world_ray(
+                transform,
+                &input_cache.last_cursor_pos,
+                &input_cache.window_size
+            );
+
+            // The blocks are in the z = 0 plane
+            if direction.z.abs() < std::f32::EPSILON {
+                return None;
+            }
+            let t = -origin.z / direction.z;
+            if t < 0.0 {
+                return None;
+            }
+            let point = origin + direction * t;
+            Some(world_to_block(point.x, point.y))
+        });
+        voxel_world.highlighted_block = self.hovered;
+
+        let hotbar = hotbar_blocks(&CONTAINER.get_local::<BlockCatalog>().registry);
+        for (key, block) in BLOCK_KEYS.iter().zip(&hotbar) {
+            if input_cache.is_key_pressed(*key) {
+                self.selected = Some(*block);
+            }
+        }
+        let selected = self.selected.or_else(|| hotbar.first().cloned());
+
+        self.layer = if input_cache.is_key_pressed(Key::LeftShift) {
+            Layer::Background
+        } else if input_cache.is_key_pressed(Key::LeftControl) {
+            Layer::Foreground
+        } else {
+            Layer::Main
+        };
+
+        let (x, y) = match self.hovered {
+            Some(hovered) => hovered,
+            None => return
+        };
+        let is_air = voxel_world.block_at(self.layer, x, y).map_or(true, |block| block.is_air());
+
+        if input_cache.was_mouse_button_clicked(MouseButton::Button1) && !is_air {
+            edits.push(VoxelEdit::Set { layer: self.layer, position: (x, y), block: Block::air() });
+        } else if input_cache.was_mouse_button_clicked(MouseButton::Button2) && is_air {
+            if let Some(block) = selected {
+                edits.push(VoxelEdit::Set { layer: self.layer, position: (x, y), block });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::BlockDefinition;
+    use nalgebra_glm::vec2;
+
+    // Solid blocks at the given positions
+    fn raycast(origin: Vec2, direction: Vec2, max_distance: f32, solid: &[(i32, i32)]) -> Option<RaycastHit> {
+        raycast_blocks(&origin, &direction, max_distance, |x, y| solid.contains(&(x, y)))
+    }
+
+    #[test]
+    fn rays_stop_at_the_first_solid_block() {
+        // The block (3, 0) covers x in 3..4 and y in -1..0
+        let hit = raycast(vec2(0.5, -0.5), vec2(1.0, 0.0), 10.0, &[(3, 0), (5, 0)]).unwrap();
+        assert_eq!(hit.position, (3, 0));
+        assert_eq!(hit.normal, (-1, 0));
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+
+        let hit = raycast(vec2(0.5, 3.5), vec2(0.0, -2.0), 10.0, &[(0, 1)]).unwrap();
+        assert_eq!(hit.position, (0, 1));
+        assert_eq!(hit.normal, (0, 1));
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn diagonal_rays_visit_every_crossed_block() {
+        let mut visited = Vec::new();
+        let hit = raycast_blocks(&vec2(0.25, -0.5), &vec2(1.0, 0.5), 100.0, |x, y| {
+            visited.push((x, y));
+            (x, y) == (4, 2)
+        }).unwrap();
+
+        assert_eq!(hit.position, (4, 2));
+        assert_eq!(hit.normal, (-1, 0));
+        // 4-connected walk, one axis at a time
+        for pair in visited.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!((a.0 - b.0).abs() + (a.1 - b.1).abs(), 1);
+        }
+        assert_eq!(visited.first(), Some(&(0, 0)));
+    }
+
+    #[test]
+    fn rays_starting_inside_a_block_hit_it() {
+        let hit = raycast(vec2(2.5, -0.5), vec2(1.0, 1.0), 10.0, &[(2, 0)]).unwrap();
+        assert_eq!(hit, RaycastHit { position: (2, 0), normal: (0, 0), distance: 0.0 });
+    }
+
+    #[test]
+    fn rays_give_up_after_the_max_distance() {
+        assert_eq!(raycast(vec2(0.5, -0.5), vec2(1.0, 0.0), 2.0, &[(3, 0)]), None);
+        assert!(raycast(vec2(0.5, -0.5), vec2(1.0, 0.0), 2.5, &[(3, 0)]).is_some());
+        assert_eq!(raycast(vec2(0.5, -0.5), vec2(0.0, 0.0), 10.0, &[(0, 0)]), None);
+    }
+
+    #[test]
+    fn the_hotbar_follows_the_registry() {
+        let textures: Vec<String> = (0..12).map(|i| format!("block_{:02}", i)).collect();
+        let definitions = vec![BlockDefinition::new("block_07"), BlockDefinition::new("block_03")];
+        let registry = BlockRegistry::from_definitions(definitions, &textures).unwrap();
+
+        let names: Vec<&str> = hotbar_blocks(&registry).iter().map(|block| registry.name(block.id)).collect();
+        assert_eq!(names, vec![
+            "block_07", "block_03", "block_00", "block_01", "block_02",
+            "block_04", "block_05", "block_06", "block_08",
+        ]);
+
+        assert!(hotbar_blocks(&BlockRegistry::new()).is_empty());
+    }
+}
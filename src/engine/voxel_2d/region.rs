@@ -0,0 +1,527 @@
+use super::{Block, BlockId, BlockRegistry, ChunkData, Layer};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Region file layout (little endian):
+//   magic "VXRG", version: u16
+//   palette_len: u16, palette_len * (name_len: u8, name: utf8)
+//   chunk_count: u16, chunk_count * chunk
+// chunk:
+//   x: i32, y: i32, run_count: u16, run_count * (palette_index, run_len - 1: u8)
+// palette_index is a u8 if the palette has at most 256 entries, a u16 otherwise.
+// The runs go over the whole chunk, from one row and one layer to the next, so a chunk
+// of air only takes a few bytes.
+// Version 2 stores the background, main and foreground layers one after the other,
+// version 1 only had the main layer.
+
+pub const REGION_MAGIC: &[u8; 4] = b"VXRG";
+pub const REGION_VERSION: u16 = 2;
+// Width and height of a region in chunks
+pub const REGION_SIZE: i32 = 8;
+// Decoded regions kept by a RegionStore
+const REGION_CACHE_SIZE: usize = 4;
+
+// Chunks of a region and their coordinates
+pub type RegionChunks = Vec<((i32, i32), ChunkData)>;
+type ChunkRefs<'a> = Vec<((i32, i32), &'a ChunkData)>;
+type DecodedRegion = HashMap<(i32, i32), ChunkData>;
+
+#[derive(Debug)]
+pub enum RegionError {
+    Io(io::Error),
+    BadMagic,
+    UnknownVersion(u16),
+    Truncated,
+    UnknownBlock(String),
+    Corrupted(&'static str),
+    // The chunks don't fit in the format, nothing was written
+    TooLarge(&'static str),
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegionError::Io(err) => write!(f, "IO error: {}", err),
+            RegionError::BadMagic => write!(f, "Not a region file"),
+            RegionError::UnknownVersion(version) => write!(f, "Unknown region file version {}", version),
+            RegionError::Truncated => write!(f, "Region file is truncated"),
+            RegionError::UnknownBlock(name) => write!(f, "Unknown block '{}'", name),
+            RegionError::Corrupted(reason) => write!(f, "Region file is corrupted: {}", reason),
+            RegionError::TooLarge(reason) => write!(f, "Region can't be saved: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RegionError {}
+
+impl From<io::Error> for RegionError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => RegionError::Truncated,
+            _ => RegionError::Io(err)
+        }
+    }
+}
+
+pub fn region_coords(chunk_coords: (i32, i32)) -> (i32, i32) {
+    (
+        chunk_coords.0.div_euclid(REGION_SIZE),
+        chunk_coords.1.div_euclid(REGION_SIZE),
+    )
+}
+
+fn read_u8(r: &mut impl Read) -> Result<u8, RegionError> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(r: &mut impl Read) -> Result<u16, RegionError> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_i32(r: &mut impl Read) -> Result<i32, RegionError> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+// Block ids are only valid for a given registry, so the palettes contain the names of the blocks
+pub fn write_region(
+    w: &mut impl Write,
+    chunks: &[((i32, i32), &ChunkData)],
+    registry: &BlockRegistry
+) -> Result<(), RegionError> {
+    // Build the palette, air is always the first entry
+    let mut palette: Vec<BlockId> = vec![BlockId::AIR];
+    let mut palette_indices: HashMap<BlockId, usize> = HashMap::new();
+    palette_indices.insert(BlockId::AIR, 0);
+    for (_, data) in chunks {
+        for block in data.layers.iter().flat_map(|blocks| blocks.iter()) {
+            if !palette_indices.contains_key(&block.id) {
+                palette_indices.insert(block.id, palette.len());
+                palette.push(block.id);
+            }
+        }
+    }
+    if palette.len() > u16::max_value() as usize {
+        return Err(RegionError::TooLarge("too many different blocks"));
+    }
+    if chunks.len() > u16::max_value() as usize {
+        return Err(RegionError::TooLarge("too many chunks"));
+    }
+    let wide_indices = palette.len() > 256;
+
+    w.write_all(REGION_MAGIC)?;
+    w.write_all(&REGION_VERSION.to_le_bytes())?;
+
+    w.write_all(&(palette.len() as u16).to_le_bytes())?;
+    for id in &palette {
+        let name = registry.name(*id);
+        if name.len() > u8::max_value() as usize {
+            return Err(RegionError::TooLarge("block name too long"));
+        }
+        w.write_all(&[name.len() as u8])?;
+        w.write_all(name.as_bytes())?;
+    }
+
+    w.write_all(&(chunks.len() as u16).to_le_bytes())?;
+    for ((x, y), data) in chunks {
+        // Run length encoding, a run can't be longer than 256 blocks
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for block in data.layers.iter().flat_map(|blocks| blocks.iter()) {
+            let index = palette_indices[&block.id];
+            match runs.last_mut() {
+                Some((last_index, len)) if *last_index == index && *len < 256 => *len += 1,
+                _ => runs.push((index, 1)),
+            }
+        }
+
+        w.write_all(&x.to_le_bytes())?;
+        w.write_all(&y.to_le_bytes())?;
+        w.write_all(&(runs.len() as u16).to_le_bytes())?;
+        for (index, len) in runs {
+            if wide_indices {
+                w.write_all(&(index as u16).to_le_bytes())?;
+            } else {
+                w.write_all(&[index as u8])?;
+            }
+            w.write_all(&[(len - 1) as u8])?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_region(
+    r: &mut impl Read,
+    registry: &BlockRegistry
+) -> Result<RegionChunks, RegionError> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != REGION_MAGIC {
+        return Err(RegionError::BadMagic);
+    }
+
+    let version = read_u16(r)?;
+    let layers: &[Layer] = match version {
+        1 => &[Layer::Main],
+        REGION_VERSION => &Layer::ALL,
+        _ => return Err(RegionError::UnknownVersion(version)),
+    };
+
+    let palette_len = read_u16(r)? as usize;
+    let mut palette = Vec::with_capacity(palette_len);
+    for _ in 0..palette_len {
+        let name_len = read_u8(r)? as usize;
+        let mut name = vec![0u8; name_len];
+        r.read_exact(&mut name)?;
+        let name = String::from_utf8(name)
+            .map_err(|_| RegionError::Corrupted("block name is not valid UTF-8"))?;
+
+        let id = registry.id(&name).ok_or(RegionError::UnknownBlock(name))?;
+        palette.push(Block { id });
+    }
+    let wide_indices = palette.len() > 256;
+
+    let chunk_count = read_u16(r)? as usize;
+    let mut chunks = Vec::with_capacity(chunk_count);
+    for _ in 0..chunk_count {
+        let x = read_i32(r)?;
+        let y = read_i32(r)?;
+        let run_count = read_u16(r)?;
+
+        // The runs can go from a layer to the next one
+        let mut blocks = vec![Block::air(); layers.len() * 256];
+        let mut i = 0;
+        for _ in 0..run_count {
+            let index = if wide_indices { read_u16(r)? as usize } else { read_u8(r)? as usize };
+            let len = read_u8(r)? as usize + 1;
+
+            let block = *palette.get(index).ok_or(RegionError::Corrupted("palette index out of bounds"))?;
+            if i + len > blocks.len() {
+                return Err(RegionError::Corrupted("chunk has too many blocks"));
+            }
+            for b in &mut blocks[i..i + len] {
+                *b = block;
+            }
+            i += len;
+        }
+        if i != blocks.len() {
+            return Err(RegionError::Corrupted("chunk has too few blocks"));
+        }
+
+        let mut data = ChunkData::new();
+        for (layer, layer_blocks) in layers.iter().zip(blocks.chunks(256)) {
+            data.layers[layer.index()].copy_from_slice(layer_blocks);
+        }
+
+        chunks.push(((x, y), data));
+    }
+
+    Ok(chunks)
+}
+
+// Directory of region files
+pub struct RegionStore {
+    dir: PathBuf,
+    registry: Arc<BlockRegistry>,
+    // Last decoded regions, the most recent at the back. The chunks of a region are
+    // streamed in one at a time, the file is only read for the first one.
+    cache: Mutex<VecDeque<((i32, i32), DecodedRegion)>>,
+}
+
+impl RegionStore {
+    pub fn new(dir: &Path, registry: Arc<BlockRegistry>) -> Self {
+        RegionStore {
+            dir: dir.to_owned(),
+            registry,
+            cache: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn region_path(&self, region: (i32, i32)) -> PathBuf {
+        self.dir.join(format!("r.{}.{}.region", region.0, region.1))
+    }
+
+    pub fn read_region(&self, region: (i32, i32)) -> Result<RegionChunks, RegionError> {
+        let path = self.region_path(region);
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+
+        let mut reader = BufReader::new(File::open(path)?);
+        read_region(&mut reader, &self.registry)
+    }
+
+    // Runs `f` on the chunks of the region, read from the cache or from the file
+    fn with_region<R>(
+        &self,
+        region: (i32, i32),
+        f: impl FnOnce(&mut DecodedRegion) -> R
+    ) -> Result<R, RegionError> {
+        let mut cache = self.cache.lock().unwrap();
+        let entry = match cache.iter().position(|(r, _)| *r == region) {
+            Some(position) => cache.remove(position).unwrap(),
+            None => (region, self.read_region(region)?.into_iter().collect()),
+        };
+        cache.push_back(entry);
+        if cache.len() > REGION_CACHE_SIZE {
+            cache.pop_front();
+        }
+
+        Ok(f(&mut cache.back_mut().unwrap().1))
+    }
+
+    pub fn load_chunk(&self, coords: (i32, i32)) -> Result<Option<ChunkData>, RegionError> {
+        self.with_region(region_coords(coords), |chunks| chunks.get(&coords).cloned())
+    }
+
+    // Writes the chunks, keeping the ones already saved in the same regions
+    pub fn save_chunks(&self, chunks: &[((i32, i32), &ChunkData)]) -> Result<(), RegionError> {
+        fs::create_dir_all(&self.dir)?;
+
+        let mut regions: HashMap<(i32, i32), ChunkRefs> = HashMap::new();
+        for (coords, data) in chunks {
+            regions.entry(region_coords(*coords)).or_default().push((*coords, *data));
+        }
+
+        for (region, new_chunks) in regions {
+            self.with_region(region, |old_chunks| {
+                let mut merged: ChunkRefs = old_chunks.iter()
+                    .filter(|(c, _)| !new_chunks.iter().any(|(new, _)| new == *c))
+                    .map(|(c, data)| (*c, data))
+                    .collect();
+                merged.extend(new_chunks.iter().cloned());
+                merged.sort_by_key(|(c, _)| (c.1, c.0));
+                let written = self.write_region_file(region, &merged);
+
+                // The cache only gets the chunks once they are on disk
+                if written.is_ok() {
+                    for (coords, data) in &new_chunks {
+                        old_chunks.insert(*coords, (*data).clone());
+                    }
+                }
+                written
+            })??;
+        }
+
+        Ok(())
+    }
+
+    fn write_region_file(&self, region: (i32, i32), chunks: &[((i32, i32), &ChunkData)]) -> Result<(), RegionError> {
+        // Write to a temporary file first so that a crash doesn't leave a truncated region
+        let path = self.region_path(region);
+        let tmp_path = path.with_extension("region.tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            write_region(&mut writer, chunks, &self.registry)?;
+            writer.flush()?;
+        }
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    // Reads every region file in the directory, to make sure they're all valid
+    pub fn validate(&self) -> Result<usize, RegionError> {
+        let mut chunk_count = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |ext| ext != "region") {
+                continue;
+            }
+
+            let mut reader = BufReader::new(File::open(path)?);
+            chunk_count += read_region(&mut reader, &self.registry)?.len();
+        }
+        Ok(chunk_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::voxel_2d::{CHUNK_SIZE, LAYER_COUNT};
+
+    fn registry(names: &[&str]) -> BlockRegistry {
+        let textures: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        BlockRegistry::from_definitions(Vec::new(), &textures).unwrap()
+    }
+
+    fn block(registry: &BlockRegistry, name: &str) -> Block {
+        Block { id: registry.id(name).unwrap() }
+    }
+
+    fn encode(chunks: &[((i32, i32), &ChunkData)], registry: &BlockRegistry) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_region(&mut bytes, chunks, registry).unwrap();
+        bytes
+    }
+
+    fn decode(bytes: &[u8], registry: &BlockRegistry) -> Result<RegionChunks, RegionError> {
+        read_region(&mut Cursor::new(bytes), registry)
+    }
+
+    fn assert_same_chunks(read: &[((i32, i32), ChunkData)], written: &[((i32, i32), &ChunkData)]) {
+        assert_eq!(read.len(), written.len());
+        for ((read_coords, read_data), (coords, data)) in read.iter().zip(written) {
+            assert_eq!(read_coords, coords);
+            assert!(read_data.layers == data.layers, "chunk {:?} changed", coords);
+        }
+    }
+
+    fn single_air_chunk(registry: &BlockRegistry) -> Vec<u8> {
+        encode(&[((0, 0), &ChunkData::new())], registry)
+    }
+
+    // Offsets in the file of single_air_chunk
+    const VERSION_OFFSET: usize = 4;
+    const RUN_COUNT_OFFSET: usize = 4 + 2 + 2 + 1 + 3 + 2 + 4 + 4;
+    const RUNS_OFFSET: usize = RUN_COUNT_OFFSET + 2;
+
+    #[test]
+    fn mixed_chunks_round_trip() {
+        let registry = registry(&["dirt", "grass", "stone"]);
+        let (dirt, grass, stone) = (block(&registry, "dirt"), block(&registry, "grass"), block(&registry, "stone"));
+
+        let mut a = ChunkData::new();
+        let mut b = ChunkData::new();
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                a.set(x, y, match (x + y) % 4 { 0 => dirt, 1 => grass, 2 => stone, _ => Block::air() });
+                b.set_layer(Layer::Background, x, y, if y < 8 { stone } else { Block::air() });
+            }
+        }
+        a.set_layer(Layer::Foreground, 3, 4, grass);
+        b.set(15, 15, dirt);
+
+        let chunks = [((-3, 7), &a), ((2, -1), &b)];
+        let read = decode(&encode(&chunks, &registry), &registry).unwrap();
+        assert_same_chunks(&read, &chunks);
+    }
+
+    #[test]
+    fn uniform_chunks_use_long_runs() {
+        let registry = registry(&["stone"]);
+        let air = ChunkData::new();
+        let mut stone = ChunkData::new();
+        for layer in stone.layers.iter_mut() {
+            for b in layer.iter_mut() {
+                *b = block(&registry, "stone");
+            }
+        }
+
+        let bytes = single_air_chunk(&registry);
+        // 768 blocks in runs of 256
+        assert_eq!(u16::from_le_bytes([bytes[RUN_COUNT_OFFSET], bytes[RUN_COUNT_OFFSET + 1]]), 3);
+        assert_eq!(bytes.len(), RUNS_OFFSET + 3 * 2);
+
+        let chunks = [((0, 0), &air), ((1, 0), &stone)];
+        let read = decode(&encode(&chunks, &registry), &registry).unwrap();
+        assert_same_chunks(&read, &chunks);
+    }
+
+    #[test]
+    fn big_palettes_use_wide_indices() {
+        let names: Vec<String> = (0..300).map(|i| format!("block_{:03}", i)).collect();
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        let registry = registry(&names);
+
+        let mut data = ChunkData::new();
+        for (i, name) in names.iter().enumerate() {
+            data.layers[i / 256][i % 256] = block(&registry, name);
+        }
+
+        let chunks = [((0, 0), &data)];
+        let read = decode(&encode(&chunks, &registry), &registry).unwrap();
+        assert_same_chunks(&read, &chunks);
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let registry = registry(&["stone"]);
+        let bytes = single_air_chunk(&registry);
+        for len in &[0, 3, VERSION_OFFSET + 1, RUN_COUNT_OFFSET, bytes.len() - 1] {
+            match decode(&bytes[..*len], &registry) {
+                Err(RegionError::Truncated) => (),
+                other => panic!("{} bytes: expected Truncated, got {:?}", len, other.map(|chunks| chunks.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_files_are_rejected() {
+        let registry = registry(&["stone"]);
+        let bytes = single_air_chunk(&registry);
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(decode(&bad_magic, &registry), Err(RegionError::BadMagic)));
+
+        let mut bad_index = bytes.clone();
+        bad_index[RUNS_OFFSET] = 7;
+        assert!(matches!(decode(&bad_index, &registry), Err(RegionError::Corrupted(_))));
+
+        let mut missing_run = bytes.clone();
+        missing_run[RUN_COUNT_OFFSET] = 2;
+        missing_run.truncate(missing_run.len() - 2);
+        assert!(matches!(decode(&missing_run, &registry), Err(RegionError::Corrupted(_))));
+
+        let mut long_run = bytes.clone();
+        long_run[RUN_COUNT_OFFSET] = 4;
+        long_run.extend_from_slice(&[0, 0]);
+        assert!(matches!(decode(&long_run, &registry), Err(RegionError::Corrupted(_))));
+
+        let stone = ChunkData { layers: [[block(&registry, "stone"); 256]; LAYER_COUNT] };
+        let other_registry = self::registry(&["dirt"]);
+        match decode(&encode(&[((0, 0), &stone)], &registry), &other_registry) {
+            Err(RegionError::UnknownBlock(name)) => assert_eq!(name, "stone"),
+            _ => panic!("expected UnknownBlock"),
+        }
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let registry = registry(&["stone"]);
+        let mut bytes = single_air_chunk(&registry);
+        bytes[VERSION_OFFSET..VERSION_OFFSET + 2].copy_from_slice(&99u16.to_le_bytes());
+        assert!(matches!(decode(&bytes, &registry), Err(RegionError::UnknownVersion(99))));
+    }
+
+    #[test]
+    fn store_keeps_the_other_chunks_of_a_region() {
+        let registry = Arc::new(registry(&["stone"]));
+        let dir = std::env::temp_dir().join(format!("region_store_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut a = ChunkData::new();
+        a.set(1, 2, block(&registry, "stone"));
+        let mut b = ChunkData::new();
+        b.set(3, 4, block(&registry, "stone"));
+
+        let store = RegionStore::new(&dir, registry.clone());
+        assert!(store.load_chunk((0, 0)).unwrap().is_none());
+        store.save_chunks(&[((0, 0), &a)]).unwrap();
+        store.save_chunks(&[((1, 0), &b)]).unwrap();
+        assert!(store.load_chunk((0, 0)).unwrap().unwrap().layers == a.layers);
+
+        // Without the cache
+        let reopened = RegionStore::new(&dir, registry);
+        assert!(reopened.load_chunk((0, 0)).unwrap().unwrap().layers == a.layers);
+        assert!(reopened.load_chunk((1, 0)).unwrap().unwrap().layers == b.layers);
+        assert_eq!(reopened.validate().unwrap(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
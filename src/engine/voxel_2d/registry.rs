@@ -0,0 +1,381 @@
+use super::{AtlasError, LightColor, MAX_LIGHT};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u16);
+
+impl BlockId {
+    pub const AIR: BlockId = BlockId(0);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum RenderLayer {
+    Opaque,
+    // The pixels with an alpha below 0.5 are discarded, see voxel.frag
+    Cutout,
+    // Alpha blended, drawn after the other blocks without writing the depth
+    Translucent,
+}
+
+impl Default for RenderLayer {
+    fn default() -> Self {
+        RenderLayer::Opaque
+    }
+}
+
+// Animated textures are vertical strips of frames, the first frame at the top.
+// Strips without an animation in the definitions use DEFAULT_FRAME_TIME.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub struct BlockAnimation {
+    pub frame_count: u32,
+    // In seconds
+    pub frame_time: f32,
+}
+
+pub const DEFAULT_FRAME_TIME: f32 = 0.1;
+
+fn default_true() -> bool { true }
+fn default_hardness() -> f32 { 1.0 }
+
+// A block as written in the definition file, the missing fields use the defaults
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockDefinition {
+    pub name: String,
+    // File stem of the texture, the name of the block by default
+    #[serde(default)]
+    pub texture: Option<String>,
+    #[serde(default = "default_true")]
+    pub solid: bool,
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default)]
+    pub light_emission: Option<LightColor>,
+    #[serde(default = "default_hardness")]
+    pub hardness: f32,
+    // Name of the dropped block, the block itself by default, "air" to drop nothing
+    #[serde(default)]
+    pub drop: Option<String>,
+    #[serde(default)]
+    pub animation: Option<BlockAnimation>,
+    #[serde(default)]
+    pub render_layer: RenderLayer,
+}
+
+impl BlockDefinition {
+    pub fn new(name: &str) -> Self {
+        BlockDefinition {
+            name: name.to_owned(),
+            texture: None,
+            solid: true,
+            transparent: false,
+            light_emission: None,
+            hardness: default_hardness(),
+            drop: None,
+            animation: None,
+            render_layer: RenderLayer::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockType {
+    pub name: String,
+    // None only for air
+    pub texture: Option<String>,
+    pub solid: bool,
+    pub transparent: bool,
+    pub light_emission: LightColor,
+    // The hardness and the drop are data for the gameplay code, the engine doesn't use them
+    pub hardness: f32,
+    pub drop: BlockId,
+    pub animation: Option<BlockAnimation>,
+    pub render_layer: RenderLayer,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(io::Error),
+    Parse(String),
+    DuplicateBlock(String),
+    MissingTexture { block: String, texture: String },
+    UnknownDrop { block: String, drop: String },
+    TooManyBlocks,
+    BadAnimation { texture: String, frame_count: u32, height: u32 },
+    // A channel is above MAX_LIGHT
+    BadLightEmission { block: String, emission: LightColor },
+    TextureImage { path: PathBuf, error: image::ImageError },
+    Atlas(AtlasError),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::Io(err) => write!(f, "IO error: {}", err),
+            RegistryError::Parse(err) => write!(f, "Invalid block definitions: {}", err),
+            RegistryError::DuplicateBlock(name) => write!(f, "Block '{}' is defined more than once", name),
+            RegistryError::MissingTexture { block, texture } =>
+                write!(f, "Texture '{}' of block '{}' doesn't exist", texture, block),
+            RegistryError::UnknownDrop { block, drop } =>
+                write!(f, "Block '{}' drops the unknown block '{}'", block, drop),
+            RegistryError::TooManyBlocks => write!(f, "Too many blocks"),
+            RegistryError::BadAnimation { texture, frame_count, height } =>
+                write!(f, "Texture '{}' is {} pixels high, it can't be split in {} frames", texture, height, frame_count),
+            RegistryError::BadLightEmission { block, emission } =>
+                write!(f, "Block '{}' emits {:?}, the light levels go up to {}", block, emission, MAX_LIGHT),
+            RegistryError::TextureImage { path, error } =>
+                write!(f, "Could not open block texture image {}: {}", path.display(), error),
+            RegistryError::Atlas(err) => write!(f, "Could not build the texture atlas: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+impl From<io::Error> for RegistryError {
+    fn from(err: io::Error) -> Self {
+        RegistryError::Io(err)
+    }
+}
+
+pub struct BlockRegistry {
+    blocks: Vec<BlockType>,
+    ids: HashMap<String, BlockId>,
+}
+
+impl From<AtlasError> for RegistryError {
+    fn from(err: AtlasError) -> Self {
+        RegistryError::Atlas(err)
+    }
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockRegistry {
+    // Registry with only air
+    pub fn new() -> Self {
+        let air = BlockType {
+            name: "air".to_owned(),
+            texture: None,
+            solid: false,
+            transparent: true,
+            light_emission: [0, 0, 0],
+            hardness: 0.0,
+            drop: BlockId::AIR,
+            animation: None,
+            render_layer: RenderLayer::Opaque,
+        };
+
+        let mut ids = HashMap::new();
+        ids.insert(air.name.clone(), BlockId::AIR);
+        BlockRegistry { blocks: vec![air], ids }
+    }
+
+    // Every texture gets a block, the textures without a definition use the default properties.
+    // Definitions come first, in the order they are written, then the remaining textures sorted by name.
+    pub fn from_definitions(definitions: Vec<BlockDefinition>, textures: &[String]) -> Result<Self, RegistryError> {
+        let mut registry = BlockRegistry::new();
+
+        let mut definitions = definitions;
+        let mut remaining_textures: Vec<&String> = textures.iter()
+            .filter(|texture| !definitions.iter().any(|d| d.texture.as_ref().unwrap_or(&d.name) == *texture))
+            .collect();
+        remaining_textures.sort();
+        definitions.extend(remaining_textures.iter()
+            .filter(|texture| !definitions.iter().any(|d| &d.name == **texture))
+            .map(|texture| BlockDefinition::new(texture))
+            .collect::<Vec<_>>());
+
+        for definition in &definitions {
+            if registry.ids.contains_key(&definition.name) {
+                return Err(RegistryError::DuplicateBlock(definition.name.clone()));
+            }
+
+            let texture = definition.texture.clone().unwrap_or_else(|| definition.name.clone());
+            if !textures.contains(&texture) {
+                return Err(RegistryError::MissingTexture { block: definition.name.clone(), texture });
+            }
+
+            if registry.blocks.len() > u16::max_value() as usize {
+                return Err(RegistryError::TooManyBlocks);
+            }
+            if let Some(emission) = definition.light_emission {
+                if emission.iter().any(|level| *level > MAX_LIGHT) {
+                    return Err(RegistryError::BadLightEmission { block: definition.name.clone(), emission });
+                }
+            }
+
+            let id = BlockId(registry.blocks.len() as u16);
+            registry.ids.insert(definition.name.clone(), id);
+            registry.blocks.push(BlockType {
+                name: definition.name.clone(),
+                texture: Some(texture),
+                solid: definition.solid,
+                transparent: definition.transparent,
+                light_emission: definition.light_emission.unwrap_or([0, 0, 0]),
+                hardness: definition.hardness,
+                drop: id,
+                animation: definition.animation,
+                render_layer: definition.render_layer,
+            });
+        }
+
+        // Drops can reference blocks defined later
+        for definition in &definitions {
+            if let Some(drop) = &definition.drop {
+                let drop_id = registry.id(drop).ok_or_else(|| RegistryError::UnknownDrop {
+                    block: definition.name.clone(),
+                    drop: drop.clone()
+                })?;
+                let id = registry.ids[&definition.name];
+                registry.blocks[id.0 as usize].drop = drop_id;
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub fn parse(source: &str, textures: &[String]) -> Result<Self, RegistryError> {
+        let definitions: Vec<BlockDefinition> = ron::de::from_str(source)
+            .map_err(|err| RegistryError::Parse(err.to_string()))?;
+        Self::from_definitions(definitions, textures)
+    }
+
+    pub fn load(path: &Path, textures: &[String]) -> Result<Self, RegistryError> {
+        Self::parse(&fs::read_to_string(path)?, textures)
+    }
+
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.ids.get(name).cloned()
+    }
+
+    pub fn get(&self, id: BlockId) -> &BlockType {
+        &self.blocks[id.0 as usize]
+    }
+
+    pub fn name(&self, id: BlockId) -> &str {
+        &self.get(id).name
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    // Never true, air is always registered
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BlockId, &BlockType)> {
+        self.blocks.iter().enumerate().map(|(i, block)| (BlockId(i as u16), block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_emission_above_max_is_rejected() {
+        let textures = vec!["lamp".to_string()];
+        let lamp = |emission| BlockDefinition { light_emission: Some(emission), ..BlockDefinition::new("lamp") };
+
+        let registry = BlockRegistry::from_definitions(vec![lamp([MAX_LIGHT, 0, 4])], &textures).unwrap();
+        assert_eq!(registry.get(registry.id("lamp").unwrap()).light_emission, [MAX_LIGHT, 0, 4]);
+
+        match BlockRegistry::from_definitions(vec![lamp([0, MAX_LIGHT + 1, 0])], &textures) {
+            Err(RegistryError::BadLightEmission { block, emission }) => {
+                assert_eq!(block, "lamp");
+                assert_eq!(emission, [0, MAX_LIGHT + 1, 0]);
+            }
+            _ => panic!("expected BadLightEmission"),
+        }
+    }
+
+    fn textures(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn definitions_come_first_then_the_sorted_textures() {
+        let definitions = vec![
+            BlockDefinition::new("stone"),
+            BlockDefinition { texture: Some("log_oak".to_string()), ..BlockDefinition::new("log") },
+        ];
+        let registry = BlockRegistry::from_definitions(definitions, &textures(&["glass", "stone", "dirt", "log_oak"])).unwrap();
+
+        let names: Vec<&str> = registry.iter().map(|(_, block)| block.name.as_str()).collect();
+        assert_eq!(names, vec!["air", "stone", "log", "dirt", "glass"]);
+        assert_eq!(registry.id("air"), Some(BlockId::AIR));
+        assert_eq!(registry.id("dirt"), Some(BlockId(3)));
+        // The texture of "log" has no block of its own
+        assert_eq!(registry.id("log_oak"), None);
+        assert_eq!(registry.get(BlockId(2)).texture, Some("log_oak".to_string()));
+        assert_eq!(registry.get(BlockId(4)).drop, BlockId(4));
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let definitions = vec![BlockDefinition::new("stone"), BlockDefinition::new("stone")];
+        match BlockRegistry::from_definitions(definitions, &textures(&["stone"])) {
+            Err(RegistryError::DuplicateBlock(name)) => assert_eq!(name, "stone"),
+            _ => panic!("expected DuplicateBlock"),
+        }
+
+        // Air is always registered
+        match BlockRegistry::from_definitions(vec![BlockDefinition::new("air")], &textures(&["air"])) {
+            Err(RegistryError::DuplicateBlock(name)) => assert_eq!(name, "air"),
+            _ => panic!("expected DuplicateBlock"),
+        }
+    }
+
+    #[test]
+    fn missing_textures_are_rejected() {
+        let definitions = vec![BlockDefinition { texture: Some("brick".to_string()), ..BlockDefinition::new("wall") }];
+        match BlockRegistry::from_definitions(definitions, &textures(&["stone"])) {
+            Err(RegistryError::MissingTexture { block, texture }) => {
+                assert_eq!(block, "wall");
+                assert_eq!(texture, "brick");
+            }
+            _ => panic!("expected MissingTexture"),
+        }
+    }
+
+    #[test]
+    fn drops_must_be_known_blocks() {
+        let drop = |block: &str, drop: &str| BlockDefinition { drop: Some(drop.to_string()), ..BlockDefinition::new(block) };
+
+        // Defined later, and air to drop nothing
+        let definitions = vec![drop("grass", "dirt"), drop("glass", "air"), BlockDefinition::new("dirt")];
+        let registry = BlockRegistry::from_definitions(definitions, &textures(&["dirt", "glass", "grass"])).unwrap();
+        assert_eq!(registry.get(registry.id("grass").unwrap()).drop, registry.id("dirt").unwrap());
+        assert_eq!(registry.get(registry.id("glass").unwrap()).drop, BlockId::AIR);
+
+        match BlockRegistry::from_definitions(vec![drop("stone", "cobblestone")], &textures(&["stone"])) {
+            Err(RegistryError::UnknownDrop { block, drop }) => {
+                assert_eq!(block, "stone");
+                assert_eq!(drop, "cobblestone");
+            }
+            _ => panic!("expected UnknownDrop"),
+        }
+    }
+
+    #[test]
+    fn render_layers_are_parsed() {
+        let source = r#"[(name: "glass", render_layer: Cutout), (name: "ice", render_layer: Translucent)]"#;
+        let registry = BlockRegistry::parse(source, &textures(&["glass", "ice", "stone"])).unwrap();
+        let layer = |name| registry.get(registry.id(name).unwrap()).render_layer;
+        assert_eq!(layer("glass"), RenderLayer::Cutout);
+        assert_eq!(layer("ice"), RenderLayer::Translucent);
+        assert_eq!(layer("stone"), RenderLayer::Opaque);
+    }
+}
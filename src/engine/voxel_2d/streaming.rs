@@ -0,0 +1,193 @@
+use super::{ChunkData, TerrainGenerator, RegionStore};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+
+pub struct StreamingSettings {
+    // Chunks closer than this (in chunks) to the camera get loaded
+    pub load_radius: i32,
+    // Chunks further than this get evicted, must be >= load_radius
+    // so that chunks on the border don't get loaded and evicted every frame
+    pub unload_radius: i32,
+    // Maximum number of streamed chunks added to the world in a single frame
+    pub max_uploads_per_frame: usize,
+    // Maximum number of chunk meshes uploaded to the GPU in a single frame
+    pub max_mesh_uploads_per_frame: usize,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        StreamingSettings {
+            load_radius: 3,
+            unload_radius: 5,
+            max_uploads_per_frame: 4,
+            max_mesh_uploads_per_frame: 8,
+        }
+    }
+}
+
+pub fn chunk_distance(a: (i32, i32), b: (i32, i32)) -> i32 {
+    i32::max((a.0 - b.0).abs(), (a.1 - b.1).abs())
+}
+
+enum StreamingJob {
+    Load((i32, i32), u64),
+    // The data is taken from the unsaved chunks when the job runs
+    Save((i32, i32)),
+}
+
+type UnsavedChunks = Mutex<HashMap<(i32, i32), ChunkData>>;
+// Coordinates and generation of the request, see ChunkStreamer::pending
+type LoadedChunk = ((i32, i32), u64, ChunkData);
+
+// The chunk as last evicted, or as saved in the store. Must be called with the store locked,
+// so that the worker doesn't write the chunk in between.
+fn read_saved_chunk(store: Option<&RegionStore>, unsaved: &UnsavedChunks, coords: (i32, i32)) -> Option<ChunkData> {
+    if let Some(data) = unsaved.lock().unwrap().get(&coords) {
+        return Some(data.clone());
+    }
+    store?.load_chunk(coords).unwrap_or_else(|err| {
+        error!("Could not load chunk {:?}: {}", coords, err);
+        None
+    })
+}
+
+// Loads (or generates) and saves chunk data on a background thread.
+// The GL objects are created on the main thread when the data is received.
+pub struct ChunkStreamer {
+    // Behind mutexes so that the voxel world can be shared as a specs resource
+    jobs: Mutex<Sender<StreamingJob>>,
+    loaded: Mutex<Receiver<LoadedChunk>>,
+    // Generation of the pending request of each chunk. Shared with the worker so that
+    // cancelled requests are skipped, and results of older requests are stale: cancelled,
+    // or read from the store the world used before.
+    pending: Arc<Mutex<HashMap<(i32, i32), u64>>>,
+    generation: AtomicU64,
+    // Evicted chunks waiting to be written. They are kept until then, or for good
+    // while the world isn't saved anywhere, and loading them reads them from here.
+    unsaved: Arc<UnsavedChunks>,
+    region_store: Arc<Mutex<Option<RegionStore>>>,
+}
+
+impl ChunkStreamer {
+    pub fn new(
+        seed: u64,
+        generator: Arc<dyn TerrainGenerator>,
+        region_store: Arc<Mutex<Option<RegionStore>>>
+    ) -> Self {
+        let (jobs, jobs_rx) = channel::<StreamingJob>();
+        let (loaded_tx, loaded) = channel();
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let unsaved = Arc::new(Mutex::new(HashMap::new()));
+
+        let (worker_pending, worker_unsaved, worker_store) = (pending.clone(), unsaved.clone(), region_store.clone());
+        thread::Builder::new()
+            .name("chunk_streamer".into())
+            .spawn(move || {
+                // Stops when the streamer (and its sender) is dropped
+                for job in jobs_rx.iter() {
+                    match job {
+                        StreamingJob::Load(coords, generation) => {
+                            if worker_pending.lock().unwrap().get(&coords) != Some(&generation) {
+                                continue;
+                            }
+
+                            let saved = read_saved_chunk(worker_store.lock().unwrap().as_ref(), &worker_unsaved, coords);
+                            let data = saved.unwrap_or_else(|| {
+                                let mut data = ChunkData::new();
+                                generator.generate(seed, coords, &mut data);
+                                data
+                            });
+
+                            if loaded_tx.send((coords, generation, data)).is_err() {
+                                break;
+                            }
+                        }
+                        StreamingJob::Save(coords) => {
+                            // Without a store, the chunk stays in memory
+                            let region_store = worker_store.lock().unwrap();
+                            let store = match region_store.as_ref() {
+                                Some(store) => store,
+                                None => continue,
+                            };
+                            // Saved by an earlier job, or by VoxelWorld::save
+                            let data = match worker_unsaved.lock().unwrap().remove(&coords) {
+                                Some(data) => data,
+                                None => continue,
+                            };
+                            if let Err(err) = store.save_chunks(&[(coords, &data)]) {
+                                error!("Could not save chunk {:?}, keeping it in memory: {}", coords, err);
+                                worker_unsaved.lock().unwrap().entry(coords).or_insert(data);
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("Unable to spawn the chunk streaming thread");
+
+        ChunkStreamer {
+            jobs: Mutex::new(jobs),
+            loaded: Mutex::new(loaded),
+            pending,
+            generation: AtomicU64::new(0),
+            unsaved,
+            region_store,
+        }
+    }
+
+    pub fn request(&self, coords: (i32, i32)) {
+        if let Entry::Vacant(entry) = self.pending.lock().unwrap().entry(coords) {
+            let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+            entry.insert(generation);
+            self.jobs.lock().unwrap().send(StreamingJob::Load(coords, generation)).expect("Chunk streaming thread died");
+        }
+    }
+
+    // Writes the evicted chunk in the background
+    pub fn save(&self, coords: (i32, i32), data: ChunkData) {
+        self.unsaved.lock().unwrap().insert(coords, data);
+        self.jobs.lock().unwrap().send(StreamingJob::Save(coords)).expect("Chunk streaming thread died");
+    }
+
+    // The chunk waiting to be saved, or the saved one. Locks the store.
+    pub fn load_saved(&self, coords: (i32, i32)) -> Option<ChunkData> {
+        read_saved_chunk(self.region_store.lock().unwrap().as_ref(), &self.unsaved, coords)
+    }
+
+    // The chunks not written yet, the caller must hold the store lock
+    pub fn take_unsaved(&self) -> Vec<((i32, i32), ChunkData)> {
+        self.unsaved.lock().unwrap().drain().collect()
+    }
+
+    // Forgets every pending request, the ones already being processed are discarded
+    pub fn cancel_all(&self) {
+        self.pending.lock().unwrap().clear();
+    }
+
+    pub fn cancel(&self, coords: (i32, i32)) {
+        self.pending.lock().unwrap().remove(&coords);
+    }
+
+    pub fn is_pending(&self, coords: (i32, i32)) -> bool {
+        self.pending.lock().unwrap().contains_key(&coords)
+    }
+
+    pub fn pending(&self) -> Vec<(i32, i32)> {
+        self.pending.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn poll(&self) -> Option<((i32, i32), ChunkData)> {
+        loop {
+            let (coords, generation, data) = self.loaded.lock().unwrap().try_recv().ok()?;
+            // Discard chunks cancelled while they were being generated
+            let mut pending = self.pending.lock().unwrap();
+            if pending.get(&coords) == Some(&generation) {
+                pending.remove(&coords);
+                return Some((coords, data));
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use super::{Block, BlockRegistry, ChunkData, Layer, CHUNK_SIZE};
+
+pub trait TerrainGenerator: Send + Sync {
+    // Must be deterministic: the same seed and chunk coordinates always
+    // produce the same blocks
+    fn generate(&self, seed: u64, chunk_coords: (i32, i32), chunk: &mut ChunkData);
+}
+
+// splitmix64 finalizer, used instead of rand so that the output doesn't depend
+// on the rand version or the platform
+fn hash(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn hash_2d(seed: u64, x: i32, y: i32) -> u64 {
+    hash(seed ^ hash((x as u32 as u64) << 32 | (y as u32 as u64)))
+}
+
+// Random value in [0, 1)
+fn random_2d(seed: u64, x: i32, y: i32) -> f32 {
+    (hash_2d(seed, x, y) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+pub fn value_noise_1d(seed: u64, x: f32) -> f32 {
+    let x0 = x.floor();
+    let t = smoothstep(x - x0);
+    let x0 = x0 as i32;
+    lerp(random_2d(seed, x0, 0), random_2d(seed, x0 + 1, 0), t)
+}
+
+pub fn value_noise_2d(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+    let (x0, y0) = (x0 as i32, y0 as i32);
+
+    let bottom = lerp(random_2d(seed, x0, y0), random_2d(seed, x0 + 1, y0), tx);
+    let top = lerp(random_2d(seed, x0, y0 + 1), random_2d(seed, x0 + 1, y0 + 1), tx);
+    lerp(bottom, top, ty)
+}
+
+// Fractal sum of value noise, normalized to [0, 1)
+pub fn fbm_1d(seed: u64, x: f32, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut total_amplitude = 0.0;
+
+    for octave in 0..octaves {
+        sum += amplitude * value_noise_1d(hash(seed.wrapping_add(octave as u64)), x * frequency);
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    sum / total_amplitude
+}
+
+pub struct OreVein {
+    pub block: Block,
+    // Veins only appear this many blocks below the surface
+    pub min_depth: i32,
+    // Bigger is rarer, in [0, 1)
+    pub threshold: f32,
+    pub scale: f32,
+}
+
+pub struct SideScrollingGenerator {
+    pub sea_level: i32,
+    pub amplitude: f32,
+    pub wavelength: f32,
+    pub dirt_depth: i32,
+
+    pub grass: Block,
+    pub dirt: Block,
+    pub stone: Block,
+    pub ores: Vec<OreVein>,
+}
+
+impl SideScrollingGenerator {
+    // Picks the layer and ore blocks in the registry by name
+    pub fn new(registry: &BlockRegistry) -> Self {
+        let find = |name: &str, fallback: Block| {
+            registry.id(name)
+                .map(|id| Block { id })
+                .unwrap_or(fallback)
+        };
+
+        let stone = find("stone", Block::air());
+        let dirt = find("dirt", stone);
+        let grass = find("grass", dirt);
+
+        // Ores are sorted by name so that the generation doesn't depend on
+        // the order of the registry
+        let mut ore_names: Vec<&str> = registry.iter()
+            .map(|(_, block)| block.name.as_str())
+            .filter(|name| name.ends_with("_ore"))
+            .collect();
+        ore_names.sort();
+
+        let ores = ore_names.iter()
+            .map(|name| {
+                let (min_depth, threshold) = match *name {
+                    "diamond_ore" | "emerald_ore" => (40, 0.90),
+                    "gold_ore" | "lapis_ore" => (24, 0.86),
+                    _ => (8, 0.80),
+                };
+                OreVein { block: find(name, Block::air()), min_depth, threshold, scale: 0.25 }
+            })
+            .collect();
+
+        SideScrollingGenerator {
+            sea_level: 0,
+            amplitude: 24.0,
+            wavelength: 64.0,
+            dirt_depth: 4,
+            grass,
+            dirt,
+            stone,
+            ores,
+        }
+    }
+
+    pub fn surface_height(&self, seed: u64, x: i32) -> i32 {
+        let noise = fbm_1d(seed, x as f32 / self.wavelength, 4);
+        self.sea_level + ((noise - 0.5) * 2.0 * self.amplitude).round() as i32
+    }
+
+    pub fn block_at(&self, seed: u64, x: i32, y: i32, surface: i32) -> Block {
+        if y > surface {
+            return Block::air();
+        }
+        if y == surface {
+            return self.grass;
+        }
+
+        let depth = surface - y;
+        if depth <= self.dirt_depth {
+            return self.dirt;
+        }
+
+        for (i, ore) in self.ores.iter().enumerate() {
+            if depth < ore.min_depth {
+                continue;
+            }
+            let ore_seed = hash(seed ^ (i as u64 + 1));
+            let noise = value_noise_2d(ore_seed, x as f32 * ore.scale, y as f32 * ore.scale);
+            if noise > ore.threshold {
+                return ore.block;
+            }
+        }
+
+        self.stone
+    }
+
+    // Walls behind the ground, so that digging leaves a cave instead of a hole to the sky
+    pub fn wall_at(&self, y: i32, surface: i32) -> Block {
+        if y >= surface {
+            Block::air()
+        } else if surface - y <= self.dirt_depth {
+            self.dirt
+        } else {
+            self.stone
+        }
+    }
+}
+
+impl TerrainGenerator for SideScrollingGenerator {
+    fn generate(&self, seed: u64, chunk_coords: (i32, i32), chunk: &mut ChunkData) {
+        let size = CHUNK_SIZE as i32;
+        for x in 0..CHUNK_SIZE {
+            let world_x = chunk_coords.0 * size + x as i32;
+            let surface = self.surface_height(seed, world_x);
+
+            for y in 0..CHUNK_SIZE {
+                let world_y = chunk_coords.1 * size + y as i32;
+                chunk.set(x, y, self.block_at(seed, world_x, world_y, surface));
+                chunk.set_layer(Layer::Background, x, y, self.wall_at(world_y, surface));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 1337;
+
+    fn generator() -> SideScrollingGenerator {
+        let textures: Vec<String> = ["coal_ore", "dirt", "gold_ore", "grass", "stone"].iter()
+            .map(|name| name.to_string())
+            .collect();
+        SideScrollingGenerator::new(&BlockRegistry::from_definitions(Vec::new(), &textures).unwrap())
+    }
+
+    fn generate(generator: &SideScrollingGenerator, chunk_coords: (i32, i32)) -> ChunkData {
+        let mut chunk = ChunkData::new();
+        generator.generate(SEED, chunk_coords, &mut chunk);
+        chunk
+    }
+
+    #[test]
+    fn same_seed_gives_same_chunk() {
+        let generator = generator();
+        for &coords in &[(0, 0), (-3, -2), (7, -5), (-1, 1)] {
+            let a = generate(&generator, coords);
+            let b = generate(&generator, coords);
+            assert!(a.layers == b.layers, "chunk {:?} differs between two runs", coords);
+        }
+
+        let differs = (-2..2).any(|y| {
+            let mut other_seed = ChunkData::new();
+            generator.generate(SEED + 1, (0, y), &mut other_seed);
+            other_seed.layers != generate(&generator, (0, y)).layers
+        });
+        assert!(differs, "another seed gives the same terrain");
+    }
+
+    // Highest solid block of the column in a stack of chunks, in world coordinates
+    fn surface_in(chunks: &[(i32, ChunkData)], x: u32) -> Option<i32> {
+        chunks.iter()
+            .flat_map(|(chunk_y, chunk)| (0..CHUNK_SIZE)
+                .filter(move |&y| !chunk.get(x, y).is_air())
+                .map(move |y| chunk_y * CHUNK_SIZE as i32 + y as i32))
+            .max()
+    }
+
+    #[test]
+    fn seeds_near_the_maximum_dont_overflow() {
+        let generator = generator();
+        let mut chunk = ChunkData::new();
+        generator.generate(u64::MAX, (0, -1), &mut chunk);
+        generator.generate(u64::MAX - 1, (0, -1), &mut chunk);
+    }
+
+    #[test]
+    fn adjacent_chunks_line_up() {
+        let generator = generator();
+
+        // Without a cliff at the seam
+        for chunk_x in -4..4 {
+            let left: Vec<(i32, ChunkData)> = (-3..3).map(|y| (y, generate(&generator, (chunk_x, y)))).collect();
+            let right: Vec<(i32, ChunkData)> = (-3..3).map(|y| (y, generate(&generator, (chunk_x + 1, y)))).collect();
+
+            let left_surface = surface_in(&left, CHUNK_SIZE - 1).unwrap();
+            let right_surface = surface_in(&right, 0).unwrap();
+            assert!((left_surface - right_surface).abs() <= 2,
+                    "surface jumps from {} to {} at x = {}", left_surface, right_surface, (chunk_x + 1) * CHUNK_SIZE as i32);
+        }
+
+        // Vertically, the column goes on in the chunk above
+        for chunk_x in -4..4 {
+            for chunk_y in -3..2 {
+                let below = generate(&generator, (chunk_x, chunk_y));
+                let above = generate(&generator, (chunk_x, chunk_y + 1));
+                for x in 0..CHUNK_SIZE {
+                    if !above.get(x, 0).is_air() {
+                        assert!(!below.get(x, CHUNK_SIZE - 1).is_air(), "air under the ground at column {}", x);
+                    }
+                }
+            }
+        }
+    }
+}
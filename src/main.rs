@@ -12,9 +12,10 @@ use specs::prelude::*;
 use ecs::systems::*;
 use ecs::resources::*;
 use crate::shaders::diffuse::DiffuseShader;
+use crate::shaders::pbr::PbrShader;
 use crate::ecs::components::PointLight;
 use glfw::ffi::{glfwSwapInterval};
-use nalgebra_glm::{vec3, Mat3};
+use nalgebra_glm::{vec2, vec3, Mat3};
 use crate::containers::*;
 use nalgebra::{Vector};
 use ncollide3d::shape::{ShapeHandle, Cuboid};
@@ -22,6 +23,7 @@ use nphysics3d::object::{BodyStatus};
 use nphysics3d::material::BasicMaterial;
 use nphysics3d::algebra::Velocity3;
 use crate::shaders::outline::OutlineShader;
+use crate::shaders::shadow::{ShadowShader, PointShadowShader};
 use crate::shaders::post_processing::{KernelShader, GaussianBlurShader};
 use engine::shaders::cube_map::CubeMapShader;
 use engine::gl_wrapper::texture_cube_map::TextureCubeMap;
@@ -29,7 +31,7 @@ use engine::shapes::PredefinedShapes;
 use std::sync::Arc;
 use debugging::debug_message_callback;
 use std::os::raw::c_void;
-use engine::voxel_2d::{ResourceManager, BlockCatalog, VoxelWorld};
+use engine::voxel_2d::{ResourceManager, BlockCatalog, VoxelWorld, VoxelEditEvents, SideScrollingGenerator, BlockPicker};
 use std::path::Path;
 use engine::shaders::voxel::VoxelShader;
 
@@ -44,6 +46,8 @@ fn setup_window(title: &str, width: u32, height: u32, mode: WindowMode) -> (Wind
     window.set_key_polling(true);
     window.set_cursor_enter_polling(true);
     window.set_cursor_pos_polling(true);
+    window.set_mouse_button_polling(true);
+    window.set_size_polling(true);
     window.set_cursor_mode(CursorMode::Disabled);
     window.set_cursor_pos(300.0, 300.0);
     window.set_raw_mouse_motion(true);
@@ -65,6 +69,8 @@ fn main() {
 
     let mut world = World::new();
     world.register::<Transform>();
+    world.register::<Parent>();
+    world.register::<Children>();
     world.register::<MeshRenderer>();
     world.register::<Camera>();
     world.insert(ActiveCamera::default());
@@ -73,8 +79,15 @@ fn main() {
     world.register::<Spotlight>();
     world.register::<Input>();
     world.insert(InputEventQueue::default());
-    world.insert(InputCache::default());
+    world.insert(InputCache {
+        window_size: {
+            let (width, height) = window.get_size();
+            vec2(width as f32, height as f32)
+        },
+        ..InputCache::default()
+    });
     world.insert(Time::default());
+    world.insert(AmbientLight::default());
     world.register::<Outliner>();
 
     // Physics stuff
@@ -92,9 +105,13 @@ fn main() {
 
     CONTAINER.set_local(ModelLoader::default);
     CONTAINER.set_local(TextureCache::default);
+    CONTAINER.set_local(AssetServer::default);
     CONTAINER.set_local(CubeMapShader::default);
     CONTAINER.set_local(DiffuseShader::default);
+    CONTAINER.set_local(PbrShader::default);
     CONTAINER.set_local(OutlineShader::default);
+    CONTAINER.set_local(ShadowShader::default);
+    CONTAINER.set_local(PointShadowShader::default);
     CONTAINER.set_local(KernelShader::default);
     CONTAINER.set_local(GaussianBlurShader::default);
 
@@ -104,13 +121,7 @@ fn main() {
 //    let mesh_renderer = model_loader.load("models/cube/box_test.obj");
 //    let gun = model_loader.load("models/gun/modified_gun.obj");
     
-    let transform_system = {
-        let mut comps = world.write_storage::<Transform>();
-        TransformSystem {
-            reader_id: comps.register_reader(),
-            dirty: BitSet::new(),
-        }
-    };
+    let transform_system = TransformSystem::new(&mut world);
 
     let sync_bodies_to_physics_system = {
         let mut transforms = world.write_storage::<Transform>();
@@ -149,7 +160,15 @@ fn main() {
         ])
         .with_barrier()
         .with(transform_system, "transform_system", &[])
+        // Thread local systems run in the order they are added
+        .with_thread_local(AssetUploadSystem)
+        .with_thread_local(BlockPicker::default())
+        .with_thread_local(VoxelEditSystem)
+        .with_thread_local(VoxelStreamingSystem)
+        // Clears the camera's render target, the voxels are drawn over it
         .with_thread_local(MeshRendererSystem::default())
+        .with_thread_local(VoxelRenderSystem)
+        .with_thread_local(PostProcessingSystem)
         .build();
 
 
@@ -176,18 +195,19 @@ fn main() {
 //        })
 //        .build();
 
-    CONTAINER.set_local(|| ResourceManager::gen_blocks_texture_atlas(Path::new("models/papercraft/textures/blocks")));
+    CONTAINER.set_local(|| {
+        ResourceManager::gen_blocks_texture_atlas(Path::new("models/papercraft/textures/blocks"))
+            .unwrap_or_else(|err| panic!("Could not load the blocks: {}", err))
+    });
     CONTAINER.set_local(VoxelShader::default);
-    let mut voxel_world = VoxelWorld::new((16, 16));
-    voxel_world.place_some_blocks();
+    let terrain_generator = SideScrollingGenerator::new(&CONTAINER.get_local::<BlockCatalog>().registry);
+    let block_registry = CONTAINER.get_local::<BlockCatalog>().registry.clone();
+    world.insert(VoxelWorld::new(block_registry, 1337, Box::new(terrain_generator)));
+    world.insert(VoxelEditEvents::default());
 
-    use std::f32;
     let camera_entity = world.create_entity()
-        .with(Transform {
-            position: vec3(0.0, 0.0, 10.0),
-            rotation: vec3(0.0, -f32::consts::PI / 2.0, 0.0),
-            ..Transform::default()
-        })
+        // Looks towards -z, at the blocks
+        .with(Transform::from_position(vec3(0.0, 0.0, 10.0)))
         .with(Camera::new(
 //            Projection::Perspective(70.0f32.to_radians()),
             Projection::Orthographic(35.0f32),
@@ -223,6 +243,8 @@ fn main() {
     gl_call!(gl::Enable(gl::STENCIL_TEST));
     gl_call!(gl::Enable(gl::BLEND));
     gl_call!(gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA));
+    // The environment maps are sampled across the faces
+    gl_call!(gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS));
 
     while !window.should_close() {
         for (_, event) in glfw::flush_messages(&events) {
@@ -230,6 +252,11 @@ fn main() {
                 glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
                     window.set_should_close(true);
                 }
+                glfw::WindowEvent::Key(Key::Tab, _, Action::Press, _) => {
+                    let mut input_cache = world.write_resource::<InputCache>();
+                    input_cache.cursor_visible = !input_cache.cursor_visible;
+                    window.set_cursor_mode(if input_cache.cursor_visible { CursorMode::Normal } else { CursorMode::Disabled });
+                }
                 _ => {
                     world.write_resource::<InputEventQueue>().queue.push_back(event);
                 }
@@ -237,8 +264,6 @@ fn main() {
         };
 
         dispatcher.dispatch(&world);
-        gl_call!(gl::Disable(gl::CULL_FACE));
-        voxel_world.render();
         input_system.run_now(&world);
         world.maintain();
 
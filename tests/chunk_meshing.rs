@@ -0,0 +1,67 @@
+// Compares the greedy mesher with the one quad per block mesher on generated terrain.
+// Doesn't need a GL context: cargo test --test chunk_meshing -- --nocapture
+use engine::voxel_2d::*;
+use std::path::Path;
+use std::time::Instant;
+
+struct Terrain {
+    chunks: Vec<ChunkData>,
+    block_sprites: Vec<BlockSprite>,
+}
+
+fn terrain() -> Terrain {
+    let textures_dir = Path::new("models/papercraft/textures/blocks");
+    let mut textures: Vec<String> = textures_dir.read_dir()
+        .expect("Could not read the block textures")
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.path().file_stem().unwrap().to_str().unwrap().to_owned())
+        .collect();
+    textures.sort();
+
+    let registry = BlockRegistry::load(&textures_dir.with_extension("ron"), &textures)
+        .unwrap_or_else(|err| panic!("Could not load the blocks: {}", err));
+    let block_sprites = vec![BlockSprite::default(); registry.len()];
+
+    // Chunks from the surface to deep underground
+    let generator = SideScrollingGenerator::new(&registry);
+    let mut chunks = Vec::new();
+    for y in -8..2 {
+        for x in -16..16 {
+            let mut data = ChunkData::new();
+            generator.generate(1337, (x, y), &mut data);
+            chunks.push(data);
+        }
+    }
+
+    Terrain { chunks, block_sprites }
+}
+
+// Vertices of all the layers of all the chunks
+fn mesh_terrain(terrain: &Terrain, mesher: fn(&ChunkData, Layer, &[BlockSprite]) -> ChunkMesh) -> usize {
+    let mut vertices = 0;
+    for data in &terrain.chunks {
+        for &layer in Layer::ALL.iter() {
+            vertices += mesher(data, layer, &terrain.block_sprites).vertex_count();
+        }
+    }
+    vertices
+}
+
+#[test]
+fn greedy_meshes_have_fewer_vertices() {
+    let terrain = terrain();
+
+    let start = Instant::now();
+    let naive = mesh_terrain(&terrain, mesh_chunk_naive);
+    let naive_time = start.elapsed();
+
+    let start = Instant::now();
+    let greedy = mesh_terrain(&terrain, mesh_chunk);
+    let greedy_time = start.elapsed();
+
+    println!("naive: {} vertices in {:?}, greedy: {} vertices in {:?} for {} chunks, {:.1}% of the naive mesher's",
+             naive, naive_time, greedy, greedy_time, terrain.chunks.len(), 100.0 * greedy as f32 / naive as f32);
+    assert!(naive > 0);
+    assert!(greedy < naive);
+}